@@ -1,32 +1,41 @@
+use std::sync::atomic::{AtomicBool, AtomicU64};
+
+use super::difficulty::Target;
+use super::header_encoding::BLOCK_HEADER_VERSION;
+use super::merkle::{merkle_root, MerkleProof, MerkleTree};
 use crate::{
-    config::{BLOCKCHAIN_INITIAL_DIFFICULTY, BLOCKCHAIN_INITIAL_NONCE},
-    transaction::Transaction,
+    config::BLOCKCHAIN_INITIAL_NONCE,
+    miner::{search_nonce, NonceSearch},
+    transaction::{BlockTransactionError, Transaction},
     utils::HashHelper,
 };
 use chrono::Utc;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Block {
     pub header: BlockHeader,
     pub body: BlockBody,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BlockHeader {
+    pub version: u32,
     pub timestamp: String,
     pub previous_hash: String,
+    pub merkle_root: String,
     pub current_hash: String,
     pub nonce: u64,
-    pub difficulty: u8,
+    pub bits: u32, // Proof-of-work target, in compact form
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BlockBody {
     pub transactions: Vec<Transaction>,
 }
 
-#[derive(Error, Debug)]
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
 pub enum BlockValidationError {
     #[error("Block not found with the specified hash")]
     BlockNotFound,
@@ -38,21 +47,48 @@ pub enum BlockValidationError {
     PreviousBlockNotFound,
     #[error("Previous hash mismatch")]
     PreviousHashMismatch,
-    #[error("Block timestamp must be greater than previous block")]
+    #[error("Invalid block timestamp")]
     InvalidTimestamp,
+    #[error("Block timestamp must be later than the median time of the previous blocks")]
+    TimestampTooOld,
+    #[error("Block timestamp is too far in the future")]
+    TimestampTooFarInFuture,
     #[error("Hash `from_hash` index needs to be lower than `to_hash`")]
     RangeIndexFault,
+    #[error("Block is already known")]
+    AlreadyKnown,
+    #[error("Block bits {found:#010x} do not match expected bits {expected:#010x}")]
+    DifficultyMismatch { expected: u32, found: u32 },
+    #[error("Block bits {0:#010x} do not encode a valid target")]
+    InvalidBits(u32),
+    #[error("Unsupported block header version {0}")]
+    UnsupportedVersion(u32),
+    #[error("Block hash does not meet its target")]
+    InsufficientWork,
+    #[error("No nonce meets the block target, refresh the block timestamp")]
+    NonceSpaceExhausted,
+    #[error("Merkle root does not match block transactions")]
+    MerkleRootMismatch,
+    #[error("First block transaction must be a coinbase")]
+    MissingCoinbase,
+    #[error("Coinbase found at index {0}, only the first transaction can be a coinbase")]
+    MisplacedCoinbase(usize),
+    #[error("Coinbase value {value} exceeds block subsidy plus fees {allowed}")]
+    CoinbaseValueTooHigh { value: u64, allowed: u64 },
+    #[error(transparent)]
+    InvalidTransaction(#[from] BlockTransactionError),
 }
 
 impl Block {
-    /// Generates a new block based on previous block hash, transactions that are meant to go into the block
-    /// and current blockchain difficulty
+    /// Generates a new block based on previous block hash, transactions that are meant to go
+    /// into the block and current blockchain target, in compact form,
+    /// see [`Block::create_data_block`]
     pub fn new(
-        previous_hash: &String,
-        transactions: &Vec<Transaction>,
-        blockchain_difficulty: u8,
-    ) -> Self {
-        Block::create_data_block(previous_hash, transactions, blockchain_difficulty)
+        previous_hash: &str,
+        transactions: &[Transaction],
+        bits: u32,
+    ) -> Result<Self, BlockValidationError> {
+        Block::create_data_block(previous_hash, transactions, bits)
     }
 
     /// Returns BlockHeader
@@ -65,85 +101,85 @@ impl Block {
         &self.body
     }
 
+    /// Merkle tree over the block transactions
+    pub fn merkle_tree(&self) -> MerkleTree {
+        MerkleTree::from_transactions(self.body.transactions())
+    }
+
+    /// Inclusion proof for the transaction with `transaction_hash`, if it is in the block
+    pub fn merkle_proof(&self, transaction_hash: &[u8; 32]) -> Option<MerkleProof> {
+        let index = self
+            .body
+            .transactions()
+            .iter()
+            .position(|t| &t.metadata().transaction_hash == transaction_hash)?;
+        self.merkle_tree().proof(index, *transaction_hash)
+    }
+
     /// Crates genesis block, where previoush hash is `"0".repeat(64)`, based on:
     /// - previous hash
     /// - current timestamp
-    /// - blockchain difficulty
-    /// - transactions (empty)
-    /// - nonce that is iterated until the blockchain difficulty is met
-    pub fn create_genesis_block(coinbase_transaction: Transaction) -> Self {
-        let previous_hash = "0".repeat(64);
-        let timestamp = Utc::now().to_rfc3339();
-        let mut transactions = Vec::new();
-        let mut nonce = BLOCKCHAIN_INITIAL_NONCE;
-        let mut hash_result;
-        let blockchain_difficulty_str = "0".repeat(BLOCKCHAIN_INITIAL_DIFFICULTY as usize);
-
-        transactions.push(coinbase_transaction);
-
-        loop {
-            hash_result = HashHelper::generate_hash(
-                &previous_hash,
-                BLOCKCHAIN_INITIAL_DIFFICULTY,
-                &timestamp,
-                &transactions,
-                nonce,
-            );
-            if hash_result.starts_with(&blockchain_difficulty_str) {
-                break;
-            }
-            nonce += 1
-        }
-
-        let header = BlockHeader {
-            previous_hash: previous_hash.to_string(),
-            difficulty: BLOCKCHAIN_INITIAL_DIFFICULTY,
-            nonce,
-            timestamp,
-            current_hash: hash_result,
-        };
-
-        let body = BlockBody { transactions };
-
-        Self { header, body }
+    /// - blockchain target
+    /// - transactions (coinbase only)
+    /// - nonce that is iterated until the blockchain target is met
+    pub fn create_genesis_block(
+        coinbase_transaction: Transaction,
+        bits: u32,
+    ) -> Result<Self, BlockValidationError> {
+        Block::create_data_block(&"0".repeat(64), &[coinbase_transaction], bits)
     }
 
     /// Crates data block, based on:
     /// - previous block hash
     /// - current timestamp
-    /// - blockchain difficulty
+    /// - blockchain target
     /// - transactions included in the block
-    /// - nonce that is iterated until the blockchain difficulty is met
+    /// - nonce that is iterated until the blockchain target is met
+    ///
+    /// Searches nonces on the calling thread, nodes mine through [`Miner`](crate::miner::Miner).
+    /// Fails if `bits` do not encode a valid target, if `previous_hash` is not a hex encoded
+    /// 32-byte hash, or if no nonce meets the target.
     pub fn create_data_block(
-        previous_hash: &String,
-        transactions: &Vec<Transaction>,
-        blockchain_difficulty: u8,
-    ) -> Self {
-        let timestamp = Utc::now().to_rfc3339();
-        let mut nonce = BLOCKCHAIN_INITIAL_NONCE;
-        let mut hash_result;
-        let blockchain_difficulty_str = "0".repeat(blockchain_difficulty as usize);
-
-        loop {
-            hash_result = HashHelper::generate_hash(
-                previous_hash,
-                blockchain_difficulty,
-                &timestamp,
-                transactions,
-                nonce,
-            );
-            if hash_result.starts_with(&blockchain_difficulty_str) {
-                break;
+        previous_hash: &str,
+        transactions: &[Transaction],
+        bits: u32,
+    ) -> Result<Self, BlockValidationError> {
+        let mut block = Block::candidate(previous_hash, transactions, bits);
+        let target = Target::from_compact(bits).ok_or(BlockValidationError::InvalidBits(bits))?;
+
+        // Encode once, only the trailing nonce changes between attempts
+        let mut encoded_header =
+            block.header.encode().map_err(|_| BlockValidationError::InvalidHash)?;
+        let nonces = BLOCKCHAIN_INITIAL_NONCE..u64::MAX;
+        match search_nonce(
+            &mut encoded_header,
+            &target,
+            nonces,
+            &AtomicBool::new(false),
+            &AtomicU64::new(0),
+        ) {
+            NonceSearch::Found { nonce, hash } => {
+                block.header.nonce = nonce;
+                block.header.current_hash = HashHelper::to_hex(&hash);
+            }
+            NonceSearch::Exhausted | NonceSearch::Stopped => {
+                return Err(BlockValidationError::NonceSpaceExhausted)
             }
-            nonce += 1
         }
+        Ok(block)
+    }
 
+    /// Block without proof of work yet: timestamped now, with the initial nonce
+    /// and an empty `current_hash`
+    pub fn candidate(previous_hash: &str, transactions: &[Transaction], bits: u32) -> Self {
         let header = BlockHeader {
+            version: BLOCK_HEADER_VERSION,
+            timestamp: Utc::now().to_rfc3339(),
             previous_hash: previous_hash.to_string(),
-            difficulty: blockchain_difficulty,
-            nonce,
-            timestamp,
-            current_hash: hash_result,
+            merkle_root: HashHelper::to_hex(&merkle_root(transactions)),
+            current_hash: String::new(),
+            nonce: BLOCKCHAIN_INITIAL_NONCE,
+            bits,
         };
 
         let body = BlockBody {
@@ -156,6 +192,10 @@ impl Block {
 
 /// BlockHeader structure
 impl BlockHeader {
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
     pub fn current_hash(&self) -> &String {
         &self.current_hash
     }
@@ -164,8 +204,12 @@ impl BlockHeader {
         &self.previous_hash
     }
 
-    pub fn difficulty(&self) -> u8 {
-        self.difficulty
+    pub fn merkle_root(&self) -> &String {
+        &self.merkle_root
+    }
+
+    pub fn bits(&self) -> u32 {
+        self.bits
     }
 
     pub fn timestamp(&self) -> &String {
@@ -183,3 +227,25 @@ impl BlockBody {
         &self.transactions
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::BLOCKCHAIN_POW_LIMIT_BITS;
+
+    #[test]
+    fn it_refuses_to_build_a_block_from_invalid_parts() {
+        let previous_hash = "0".repeat(64);
+        assert!(Block::new(&previous_hash, &[], BLOCKCHAIN_POW_LIMIT_BITS).is_ok());
+
+        assert_eq!(
+            Block::new("not a hash", &[], BLOCKCHAIN_POW_LIMIT_BITS).unwrap_err(),
+            BlockValidationError::InvalidHash
+        );
+        // Negative target
+        assert_eq!(
+            Block::new(&previous_hash, &[], 0x0080_0001).unwrap_err(),
+            BlockValidationError::InvalidBits(0x0080_0001)
+        );
+    }
+}
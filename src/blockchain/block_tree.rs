@@ -0,0 +1,183 @@
+//! # Block Tree
+//!
+//! Every known block, on the active chain or on a side branch, keyed by hash.
+//!
+//! Each entry records its height and the cumulative proof-of-work of the branch ending
+//! with it (`chain_work`). The node follows the branch with the most cumulative work,
+//! see [`Blockchain::submit_block`](super::Blockchain::submit_block).
+
+use std::collections::HashMap;
+
+use super::{block_work, Block, BlockValidationError};
+
+/// A block of the tree, with its position and the work of the branch ending with it
+#[derive(Debug, Clone)]
+pub struct BlockTreeEntry {
+    pub block: Block,
+    pub height: u64,
+    pub chain_work: u128,
+}
+
+/// Tree of known blocks, rooted at the genesis block
+#[derive(Debug, Clone, Default)]
+pub struct BlockTree {
+    entries: HashMap<String, BlockTreeEntry>,
+    children: HashMap<String, Vec<String>>,
+}
+
+impl BlockTree {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, hash: &str) -> Option<&BlockTreeEntry> {
+        self.entries.get(hash)
+    }
+
+    pub fn contains(&self, hash: &str) -> bool {
+        self.entries.contains_key(hash)
+    }
+
+    /// Adds `block` below its parent, or as the root if the tree is empty
+    pub fn insert(&mut self, block: Block) -> Result<&BlockTreeEntry, BlockValidationError> {
+        let hash = block.header().current_hash().clone();
+        if self.entries.contains_key(&hash) {
+            return Err(BlockValidationError::AlreadyKnown);
+        }
+
+        let work = block_work(block.header().bits());
+        let (height, chain_work) = if self.entries.is_empty() {
+            (0, work)
+        } else {
+            let parent = self
+                .entries
+                .get(block.header().previous_hash())
+                .ok_or(BlockValidationError::PreviousBlockNotFound)?;
+            (parent.height + 1, parent.chain_work.saturating_add(work))
+        };
+
+        if height > 0 {
+            self.children
+                .entry(block.header().previous_hash().clone())
+                .or_default()
+                .push(hash.clone());
+        }
+
+        Ok(self.entries.entry(hash).or_insert(BlockTreeEntry {
+            block,
+            height,
+            chain_work,
+        }))
+    }
+
+    /// Ancestor of the block with `hash` at `height`, the block itself at its own height
+    pub fn ancestor(&self, hash: &str, height: u64) -> Option<&BlockTreeEntry> {
+        let mut entry = self.entries.get(hash)?;
+        while entry.height > height {
+            entry = self.entries.get(entry.block.header().previous_hash())?;
+        }
+        (entry.height == height).then_some(entry)
+    }
+
+    /// Blocks from the child of the first ancestor of `hash` matching `is_fork_point`,
+    /// up to the block with `hash`, in ascending height.
+    /// Starts from the root if no ancestor matches, `None` if `hash` is unknown.
+    pub fn branch(
+        &self,
+        hash: &str,
+        is_fork_point: impl Fn(&BlockTreeEntry) -> bool,
+    ) -> Option<Vec<&BlockTreeEntry>> {
+        let mut branch = Vec::new();
+        let mut entry = self.entries.get(hash)?;
+        while !is_fork_point(entry) {
+            branch.push(entry);
+            if entry.height == 0 {
+                break;
+            }
+            entry = self.entries.get(entry.block.header().previous_hash())?;
+        }
+        branch.reverse();
+        Some(branch)
+    }
+
+    /// Removes the block with `hash` and all its descendants, e.g. once it proved invalid
+    pub fn remove_subtree(&mut self, hash: &str) -> Vec<Block> {
+        let mut removed = Vec::new();
+        let mut pending = vec![hash.to_string()];
+
+        if let Some(entry) = self.entries.get(hash) {
+            let parent = entry.block.header().previous_hash();
+            if let Some(siblings) = self.children.get_mut(parent) {
+                siblings.retain(|child| child != hash);
+            }
+        }
+
+        while let Some(hash) = pending.pop() {
+            if let Some(entry) = self.entries.remove(&hash) {
+                removed.push(entry.block);
+            }
+            pending.extend(self.children.remove(&hash).unwrap_or_default());
+        }
+        removed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::BLOCKCHAIN_POW_LIMIT_BITS;
+
+    fn child_of(parent: &Block) -> Block {
+        Block::new(parent.header().current_hash(), &[], BLOCKCHAIN_POW_LIMIT_BITS).unwrap()
+    }
+
+    #[test]
+    fn it_tracks_branches_and_work() {
+        let genesis = Block::new(&"0".repeat(64), &[], BLOCKCHAIN_POW_LIMIT_BITS).unwrap();
+        let main_1 = child_of(&genesis);
+        let main_2 = child_of(&main_1);
+        let side_1 = child_of(&genesis);
+
+        let mut tree = BlockTree::new();
+        for block in [&genesis, &main_1, &main_2, &side_1] {
+            tree.insert(block.clone()).unwrap();
+        }
+
+        let work = block_work(BLOCKCHAIN_POW_LIMIT_BITS);
+        let tip = tree.get(main_2.header().current_hash()).unwrap();
+        assert_eq!((tip.height, tip.chain_work), (2, 3 * work));
+        assert_eq!(tree.get(side_1.header().current_hash()).unwrap().height, 1);
+
+        assert_eq!(
+            tree.insert(main_1.clone()).unwrap_err(),
+            BlockValidationError::AlreadyKnown
+        );
+        let orphan = child_of(&child_of(&main_2));
+        assert_eq!(
+            tree.insert(orphan).unwrap_err(),
+            BlockValidationError::PreviousBlockNotFound
+        );
+
+        let ancestor = tree.ancestor(main_2.header().current_hash(), 1).unwrap();
+        assert_eq!(ancestor.block.header().current_hash(), main_1.header().current_hash());
+
+        // Branch of main_2 above the genesis block
+        let branch = tree
+            .branch(main_2.header().current_hash(), |entry| entry.height == 0)
+            .unwrap();
+        assert_eq!(branch.len(), 2);
+        assert_eq!(branch[0].block.header().current_hash(), main_1.header().current_hash());
+
+        let removed = tree.remove_subtree(main_1.header().current_hash());
+        assert_eq!(removed.len(), 2);
+        assert_eq!(tree.len(), 2);
+    }
+}
@@ -5,32 +5,86 @@
 //!
 
 // Imports
-use tracing::info;
+use tracing::{debug, info, warn};
+use chrono::Utc;
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
+use std::fmt;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::pin::Pin;
 use std::sync::Arc;
-use std::{collections::HashMap, vec};
-use tokio::sync::Mutex;
+use std::time::{Duration, Instant};
+use std::vec;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::net::TcpListener;
+use tokio::sync::{broadcast, mpsc, watch, Mutex};
 
 // Modules/Crates
-use super::{Block, BlockValidationError, BlockchainListener};
-use crate::transaction::{Transaction, TransactionInput, TransactionManager, TransactionOutput};
-use crate::wallet::{Account, Wallet};
+use thiserror::Error;
+
+use super::{
+    block_time, consensus, consensus::ConsensusParams, next_bits, next_bits_at, Balance, Block,
+    BlockStorage, BlockTree, BlockTreeEntry, BlockUndo, BlockValidationError, BlockchainListener,
+    ChainValidationFailure, ChainValidator, DifficultyConfig, NodeRequest, StorageError, Target,
+    UtxoError, UtxoSet, BLOCK_HEADER_VERSION,
+};
+use crate::comms::{EventTopic, NodeEvent, RequestType};
+use crate::mempool::{FeeEstimator, FeeRate, Mempool, MempoolConfig, MempoolError, RemovalReason};
+use crate::miner::{
+    BlockTemplate, CoinbaseBuilder, MinedBlock, Miner, MinerConfig, MinerError, MiningControl,
+    MiningState,
+};
+use crate::transaction::{
+    validate_block_transactions, validate_transaction, BlockTransactionError, Transaction,
+    TransactionManager, TransactionStatus, OutPoint, SpendContext, UtxoEntry, UtxoView,
+};
+use crate::wallet::{Chain, KdfParams, Wallet};
 use crate::{
     config::{
-        BLOCKCHAIN_COINBASE_BLOCK_FEE, BLOCKCHAIN_COINBASE_GENESIS_BLOCK_FEE,
-        BLOCKCHAIN_INITIAL_DIFFICULTY, WEBSOCKET_URI,
+        BLOCKCHAIN_DATA_DIR, BLOCKCHAIN_MAX_BLOCK_SIZE, FEE_ESTIMATOR_BLOCKS,
+        NODE_WALLET_PASSWORD_ENV, WEBSOCKET_URI,
     },
-    utils::HashHelper,
+    utils::{HashHelper, TransactionHelper},
 };
 
+/// Capacity of the node event channel, before slow subscribers start lagging.
+const EVENT_CHANNEL_CAPACITY: usize = 1_024;
+
+/// How often the node drops expired mempool transactions.
+const MEMPOOL_EXPIRY_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How often the mining service checks its policy while idle.
+const MINING_POLICY_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Name of the node wallet file in the data directory.
+const NODE_WALLET_FILE: &str = "wallet.json";
+
+/// Block being mined by the mining service
+type MiningFuture = Pin<Box<dyn Future<Output = Result<MinedBlock, MinerError>> + Send>>;
+
 #[derive(Debug)]
 pub struct Blockchain {
-    blocks: Vec<Block>,                            // Mined blocks
-    mempool: Vec<TransactionInput>,                // Pending transactions
-    utxo: HashMap<String, Vec<TransactionOutput>>, // Unspent transaction outputs used for inputs into other transactions
+    blocks: Vec<Block>,   // Active chain, the branch of `tree` with the most work
+    tree: BlockTree,      // Every known block, including side branches
+    mempool: Mempool,     // Pending transactions
+    fees: FeeEstimator,   // Fee rates accepted by recent blocks
+    utxo: UtxoSet,        // Unspent transaction outputs used for inputs into other transactions
+    undo: Vec<BlockUndo>, // Outputs spent by each connected block, by height
     ledger: Vec<Transaction>, // The blockchain ledger keeps track of every transaction and the issuance of new coins through coinbase transactions.
+    mined: HashMap<[u8; 32], u64>, // Transaction hash -> height of the active block including it
+    awaiting_parent: Vec<Transaction>, // Disconnected transactions spending a pending one
     config: Arc<BlockchainConfig>,
     wallet: Wallet,
+    storage: Arc<Mutex<BlockStorage>>, // On-disk block storage, shared between clones
+    miner: Miner,
+    mining: MiningControl,             // Starts and stops the mining service of `run`
+    tip: watch::Sender<String>,        // Hash of the active chain tip, miners stop when it moves
+    local_addr: SocketAddr,            // Address the listener is actually bound to
+    events: broadcast::Sender<NodeEvent>, // Node events, forwarded to subscribed clients
+    requests: Option<mpsc::UnboundedReceiver<NodeRequest>>, // Client requests from the listener
     pub listener: Arc<Mutex<BlockchainListener>>,
 }
 
@@ -38,24 +92,120 @@ impl Clone for Blockchain {
     fn clone(&self) -> Self {
         Self {
             blocks: self.blocks.clone(),   // Mined blocks
+            tree: self.tree.clone(),
             mempool: self.mempool.clone(), // Pending transactions
+            fees: self.fees.clone(),
             utxo: self.utxo.clone(), // Unspent transaction outputs used for inputs into other transactions
+            undo: self.undo.clone(),
             ledger: self.ledger.clone(), // The blockchain ledger keeps track of every transaction and the issuance of new coins through coinbase transactions.
+            mined: self.mined.clone(),
+            awaiting_parent: self.awaiting_parent.clone(),
             config: self.config.clone(),
             wallet: self.wallet.clone(),
+            storage: self.storage.clone(),
+            miner: self.miner.clone(),
+            mining: self.mining.clone(),
+            tip: self.tip.clone(),
+            local_addr: self.local_addr,
+            events: self.events.clone(),
+            requests: None, // Only the original node processes client requests
             listener: self.listener.clone(),
         }
     }
 }
-#[derive(Debug, Clone)]
+
+/// How a submitted block was accepted
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BlockAcceptance {
+    /// The block extends the active chain
+    Extended,
+    /// The block completes a side branch with more work, which replaced the active chain
+    Reorganized { disconnected: usize, connected: usize },
+    /// The block is stored on a side branch with no more work than the active chain
+    SideBranch,
+}
+
+#[derive(Error, Debug)]
+pub enum SubmitBlockError {
+    #[error(transparent)]
+    Invalid(#[from] BlockValidationError),
+    #[error(transparent)]
+    Storage(#[from] StorageError),
+}
+
+/// Branch of the block tree validated against the UTXO set at its fork point,
+/// ready to become the active chain
+struct Branch {
+    tip: String,
+    fork_height: usize, // Number of active blocks kept, the height of the first branch block
+    blocks: Vec<Block>,
+}
+
+/// Work for an external miner, answering `RequestType::GetBlockTemplate`.
+///
+/// The miner builds its own coinbase, claiming at most `coinbase_value`, puts it before
+/// `transactions`, solves the block with `Block::new(previous_hash, transactions, bits)`
+/// and sends it back with `RequestType::SubmitBlock`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlockTemplateResponse {
+    pub version: u32,
+    pub height: u64,
+    pub previous_hash: String,
+    pub bits: u32,      // Target the block must declare and meet, in compact form
+    pub target: String, // Expanded target, hex encoded
+    pub coinbase_value: u64, // Block subsidy plus the fees of `transactions`
+    pub transactions: Vec<Transaction>, // Transactions following the coinbase
+}
+
+/// Unspent outputs of addresses, answering `RequestType::GetUtxos`. Outputs spent by
+/// pending transactions are left out.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UtxosResponse {
+    pub height: u64,            // Height of the next block, which may spend the outputs
+    pub coinbase_maturity: u64, // Blocks between a coinbase and the first block that may spend it
+    pub utxos: Vec<(OutPoint, UtxoEntry)>,
+}
+
+/// Transaction paying or spending from an address, answering `RequestType::GetHistory`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub transaction: Transaction,
+    pub status: TransactionStatus,
+}
+
+#[derive(Clone)]
 pub struct BlockchainConfig {
-    pub difficulty: u8,
+    pub difficulty: DifficultyConfig, // Initial target, target block time and retarget window
     pub addr: String,
+    pub data_dir: PathBuf, // Directory holding the block file, its index and the node wallet
+    pub wallet_password: String, // Password of the node wallet file, from the environment
+    pub wallet_kdf: KdfParams,   // Argon2id parameters deriving the wallet file key
+    pub mempool: MempoolConfig,
+    pub max_block_size: usize, // Maximum size of block transactions, in bytes
+    pub miner: MinerConfig,    // Worker threads and nonce space of the node miner
+    pub consensus: ConsensusParams, // Tunable consensus rules, e.g. the coinbase maturity
+}
+
+// The wallet password is never printed
+impl fmt::Debug for BlockchainConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BlockchainConfig")
+            .field("difficulty", &self.difficulty)
+            .field("addr", &self.addr)
+            .field("data_dir", &self.data_dir)
+            .field("wallet_kdf", &self.wallet_kdf)
+            .field("mempool", &self.mempool)
+            .field("max_block_size", &self.max_block_size)
+            .field("miner", &self.miner)
+            .field("consensus", &self.consensus)
+            .finish_non_exhaustive()
+    }
 }
 
 impl BlockchainConfig {
     pub fn new(dynamic: bool) -> Self {
-        let difficulty = BLOCKCHAIN_INITIAL_DIFFICULTY;
+        let difficulty = DifficultyConfig::default();
 
         let addr = if dynamic {
             "127.0.0.1:0".to_string()
@@ -63,70 +213,125 @@ impl BlockchainConfig {
             WEBSOCKET_URI.to_string()
         };
 
-        BlockchainConfig { addr, difficulty }
+        let data_dir = PathBuf::from(BLOCKCHAIN_DATA_DIR);
+
+        BlockchainConfig {
+            addr,
+            difficulty,
+            data_dir,
+            wallet_password: std::env::var(NODE_WALLET_PASSWORD_ENV).unwrap_or_default(),
+            wallet_kdf: KdfParams::default(),
+            mempool: MempoolConfig::default(),
+            max_block_size: BLOCKCHAIN_MAX_BLOCK_SIZE,
+            miner: MinerConfig::default(),
+            consensus: ConsensusParams::default(),
+        }
+    }
+
+    /// Sets the directory where the chain is persisted
+    pub fn with_data_dir(mut self, data_dir: impl Into<PathBuf>) -> Self {
+        self.data_dir = data_dir.into();
+        self
     }
 }
 
 /// Blockchain structure, consisting of vector of blocks and its configuration
 impl Blockchain {
-    /// Builds a blockchain from scratch
-    /// Creates genesis block based on the BLOCKCHAIN_INITIAL_DIFFICULTY
+    /// Builds a blockchain, reloading the existing chain from `config.data_dir`.
+    /// If no chain is stored yet, creates genesis block based on the initial target of
+    /// `config.difficulty`
     pub async fn build(config: BlockchainConfig) -> Result<Self, Box<dyn Error>> {
         let config = Arc::new(config);
-        let config_clone = Arc::clone(&config);
 
-        // Websocket server for wallets to connect
-        let listener = Arc::new(Mutex::new(BlockchainListener::new()));
-        let listener_clone = Arc::clone(&listener);
-
-        tokio::spawn(async move {
-            listener_clone.lock().await.run(&config_clone.addr).await;
-        });
+        let mut storage = BlockStorage::open(&config.data_dir)?;
+        let stored_blocks = storage.load_blocks()?;
 
-        let mut wallet =
-            Wallet::new("MiningFeeWallet#1".to_string(), config.addr.to_string()).await;
+        // Websocket server for wallets to connect
+        // Bind before spawning, so the actual address is known when `addr` uses port 0
+        let tcp_listener = TcpListener::bind(&config.addr).await?;
+        let local_addr = tcp_listener.local_addr()?;
 
-        wallet.create_new_account("BlockchainNodeWalletAccount");
+        let (events, _) = broadcast::channel(EVENT_CHANNEL_CAPACITY);
+        let (request_sender, requests) = mpsc::unbounded_channel();
 
-        let coinbase_account: &Account;
-        let coinbase_transaction: Transaction;
+        let blockchain_listener = BlockchainListener::new().with_requests(request_sender);
+        let listener_clone = blockchain_listener.clone();
+        let forwarder = blockchain_listener.clone();
+        let listener = Arc::new(Mutex::new(blockchain_listener));
 
-        // Scoped lock for mutex
-        {
-            coinbase_account = wallet
-                .accounts()
-                .first()
-                .expect("No coinbase error available.");
+        tokio::spawn(async move {
+            listener_clone.serve(tcp_listener).await;
+        });
 
-            let coinbase_address = coinbase_account.address();
+        let event_receiver = events.subscribe();
+        tokio::spawn(async move {
+            forwarder.forward_events(event_receiver).await;
+        });
 
-            coinbase_transaction = TransactionManager::create_coinbase_transaction(
-                wallet.private_key(),
-                wallet.public_key(),
-                coinbase_address,
-                BLOCKCHAIN_COINBASE_GENESIS_BLOCK_FEE,
-                coinbase_account.next_nonce(),
+        let wallet = open_node_wallet(&config, local_addr.to_string()).await?;
+
+        let miner = Miner::new(config.miner.clone());
+        let mining = MiningControl::new(config.miner.autostart);
+
+        let blocks = if stored_blocks.is_empty() {
+            let template = BlockTemplate {
+                previous_hash: "0".repeat(64),
+                bits: config.difficulty.initial_bits,
+                coinbase: coinbase_builder(&wallet, config.consensus.block_subsidy(0)),
+                transactions: vec![],
+            };
+            let genesis_block = miner.start(template)?.wait().await?.block;
+            storage.append_block(&genesis_block)?;
+            vec![genesis_block]
+        } else {
+            info!(
+                "Loaded {} blocks from {}",
+                stored_blocks.len(),
+                config.data_dir.display()
             );
-        }
-
-        let genesis_block = Block::create_genesis_block(coinbase_transaction);
+            stored_blocks
+        };
 
-        let blocks = vec![genesis_block.clone()]; // Clone it because it has to be borrowed to reward_block_finder
-        let mempool = vec![];
-        let utxo = HashMap::new();
+        let mempool = Mempool::new(config.mempool.clone(), events.clone());
+        let utxo = UtxoSet::new();
         let ledger = vec![];
+        let (tip, _) = watch::channel(String::new());
 
         let mut blockchain = Self {
-            blocks,
+            blocks: vec![],
+            tree: BlockTree::new(),
             config,
             mempool,
+            fees: FeeEstimator::new(FEE_ESTIMATOR_BLOCKS),
             utxo,
+            undo: vec![],
             ledger,
+            mined: HashMap::new(),
+            awaiting_parent: vec![],
             wallet,
+            storage: Arc::new(Mutex::new(storage)),
+            miner,
+            mining,
+            tip,
+            local_addr,
+            events,
+            requests: Some(requests),
             listener,
         };
 
-        blockchain.reward_block_finder(&genesis_block);
+        // Replay stored blocks to rebuild the block tree, ledger and UTXOs, following the branch
+        // with the most work. Blocks breaking consensus rules never join the active chain.
+        for (position, block) in blocks.into_iter().enumerate() {
+            let hash = block.header().current_hash().clone();
+            match blockchain
+                .check_block_header(&block)
+                .and_then(|_| blockchain.accept_block(block))
+            {
+                Ok(_) => {}
+                Err(e) if position == 0 => return Err(e.into()),
+                Err(e) => warn!("Skipping stored block {}: {}", hash, e),
+            }
+        }
 
         Ok(blockchain)
     }
@@ -136,108 +341,674 @@ impl Blockchain {
         &self.config
     }
 
+    /// Returns the address the node's WebSocket listener is bound to
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Returns the pending transaction pool
+    pub fn mempool(&self) -> &Mempool {
+        &self.mempool
+    }
+
+    /// Watches the hash of the active chain tip
+    pub fn subscribe_tip(&self) -> watch::Receiver<String> {
+        self.tip.subscribe()
+    }
+
+    /// Subscribes to events published by the node (new blocks, mempool changes, ...)
+    pub fn subscribe_events(&self) -> broadcast::Receiver<NodeEvent> {
+        self.events.subscribe()
+    }
+
+    /// Validates a transaction against the chain and submits it to the mempool,
+    /// returning its hash once accepted
+    pub fn submit_transaction(
+        &mut self,
+        transaction: Transaction,
+    ) -> Result<[u8; 32], MempoolError> {
+        self.mempool.expire(Instant::now());
+
+        let hash = transaction.metadata().transaction_hash;
+        let context = self.config.consensus.spend_context(self.blocks.len() as u64);
+        let fee = validate_transaction(&transaction, self, context)?;
+        self.mempool.add(transaction, fee)?;
+
+        info!("Transaction {} added to mempool", HashHelper::to_hex(&hash));
+        Ok(hash)
+    }
+
+    /// Status of the transaction with `hash`: mined in the active chain or pending in the
+    /// mempool. `None` if the node does not know it, e.g. it was rejected or dropped.
+    pub fn transaction_status(&self, hash: &[u8; 32]) -> Option<TransactionStatus> {
+        if let Some(&height) = self.mined.get(hash) {
+            Some(TransactionStatus::Mined { height })
+        } else if self.mempool.contains(hash) {
+            Some(TransactionStatus::Pending)
+        } else {
+            None
+        }
+    }
+
+    /// Fee rate, per 1000 bytes, a new transaction should pay to be mined
+    /// within `target_blocks` blocks
+    pub fn estimate_fee(&self, target_blocks: usize) -> FeeRate {
+        self.fees.estimate(target_blocks, &self.mempool, self.config.max_block_size)
+    }
+
+    /// Processes a client request routed by the listener and answers it
+    pub async fn handle_request(&mut self, request: NodeRequest) {
+        let result = match request.r#type {
+            RequestType::SubmitTransaction => {
+                match serde_json::from_value::<Transaction>(request.payload) {
+                    Ok(transaction) => self
+                        .submit_transaction(transaction)
+                        .map(|hash| json!({ "transaction_hash": HashHelper::to_hex(&hash) }))
+                        .map_err(|e| e.to_string()),
+                    Err(e) => Err(format!("Invalid transaction: {}", e)),
+                }
+            }
+            RequestType::GetMempool => {
+                serde_json::to_value(self.mempool.transactions()).map_err(|e| e.to_string())
+            }
+            RequestType::GetBlockTemplate => self
+                .get_block_template()
+                .map_err(|e| e.to_string())
+                .and_then(|template| {
+                    serde_json::to_value(template).map_err(|e| e.to_string())
+                }),
+            RequestType::SubmitBlock => match serde_json::from_value::<Block>(request.payload) {
+                Ok(block) => {
+                    let hash = block.header().current_hash().clone();
+                    self.submit_block(block)
+                        .await
+                        .map(|acceptance| json!({ "hash": hash, "acceptance": acceptance }))
+                        .map_err(|e| e.to_string())
+                }
+                Err(e) => Err(format!("Invalid block: {}", e)),
+            },
+            RequestType::EstimateFee => {
+                let target_blocks = request
+                    .payload
+                    .get("target_blocks")
+                    .and_then(|n| n.as_u64())
+                    .unwrap_or(1) as usize;
+                Ok(json!({
+                    "target_blocks": target_blocks,
+                    "fee_rate": self.estimate_fee(target_blocks),
+                    "min_relay_fee_rate": self.mempool.config().min_relay_fee_rate,
+                }))
+            }
+            RequestType::GetSupply => {
+                let tip_height = self.blocks.len().saturating_sub(1) as u64;
+                let height = request
+                    .payload
+                    .get("height")
+                    .and_then(|h| h.as_u64())
+                    .unwrap_or(tip_height);
+                Ok(json!({
+                    "height": height,
+                    "issued": self.issued_supply(height),
+                    "block_subsidy": self.config.consensus.block_subsidy(height),
+                    "max_supply": self.config.consensus.max_supply,
+                }))
+            }
+            RequestType::GetTransactionStatus => {
+                match request
+                    .payload
+                    .get("transaction_hash")
+                    .and_then(|h| h.as_str())
+                    .and_then(HashHelper::hash_from_hex)
+                {
+                    Some(hash) => Ok(json!({
+                        "transaction_hash": HashHelper::to_hex(&hash),
+                        "status": self.transaction_status(&hash),
+                    })),
+                    None => Err("Status request is missing a valid `transaction_hash`".to_string()),
+                }
+            }
+            RequestType::GetBalance => {
+                if let Some(address) = request.payload.get("address").and_then(|a| a.as_str()) {
+                    let balance = self.address_balance(address);
+                    Ok(json!({
+                        "address": address,
+                        "spendable": balance.spendable,
+                        "immature": balance.immature,
+                        "unconfirmed": balance.unconfirmed,
+                    }))
+                } else {
+                    addresses_of(&request.payload).and_then(|addresses| {
+                        serde_json::to_value(self.addresses_balance(&addresses))
+                            .map_err(|e| e.to_string())
+                    })
+                }
+            }
+            RequestType::GetUtxos => addresses_of(&request.payload).and_then(|addresses| {
+                serde_json::to_value(self.unspent_outputs(&addresses)).map_err(|e| e.to_string())
+            }),
+            RequestType::GetHistory => addresses_of(&request.payload).and_then(|addresses| {
+                serde_json::to_value(self.history(&addresses)).map_err(|e| e.to_string())
+            }),
+        };
+
+        if request.respond_to.send(result).is_err() {
+            warn!("Client went away before request {} was answered", request.id);
+        }
+    }
+
+    /// Runs the node: processes client requests, periodically expires mempool entries and,
+    /// while the mining service is enabled, mines a new block whenever the mining policy is met.
+    /// Returns once the listener stops routing requests.
+    pub async fn run(&mut self) {
+        let Some(mut requests) = self.requests.take() else {
+            warn!("Blockchain node is already running");
+            return;
+        };
+        let mut expiry = tokio::time::interval(MEMPOOL_EXPIRY_CHECK_INTERVAL);
+        let mut policy_check = tokio::time::interval(MINING_POLICY_CHECK_INTERVAL);
+        let mut mining_enabled = self.mining.subscribe();
+        let mut mining: Option<MiningFuture> = None;
+
+        loop {
+            tokio::select! {
+                request = requests.recv() => match request {
+                    Some(request) => self.handle_request(request).await,
+                    None => break,
+                },
+                _ = expiry.tick() => {
+                    self.mempool.expire(Instant::now());
+                }
+                Ok(()) = mining_enabled.changed() => {
+                    if *mining_enabled.borrow_and_update() {
+                        info!("Mining service started");
+                        policy_check.reset_immediately();
+                    } else {
+                        info!("Mining service stopped");
+                        // Dropping the job stops its workers
+                        mining = None;
+                    }
+                }
+                _ = policy_check.tick(), if mining.is_none() && self.mining.is_enabled() => {
+                    mining = self.start_mining_job();
+                }
+                mined = async { mining.as_mut().expect("polled while mining").await },
+                    if mining.is_some() => {
+                    mining = None;
+                    match mined {
+                        Ok(mined) => self.submit_mined_block(mined).await,
+                        Err(MinerError::Stale) => {
+                            debug!("Chain tip moved, mining on top of the new tip")
+                        }
+                        Err(e) => warn!("Mining stopped: {}", e),
+                    }
+                    policy_check.reset_immediately();
+                }
+            }
+        }
+
+        self.requests = Some(requests);
+    }
+
+    /// Handle starting and stopping the mining service of [`Blockchain::run`],
+    /// usable while the node runs
+    pub fn mining_control(&self) -> MiningControl {
+        self.mining.clone()
+    }
+
+    /// Node state the mining policy decides on
+    pub fn mining_state(&self) -> MiningState {
+        let since_last_block = self
+            .blocks
+            .last()
+            .and_then(|tip| block_time(tip).ok())
+            .and_then(|time| Utc::now().signed_duration_since(time).to_std().ok())
+            .unwrap_or_default();
+
+        MiningState {
+            pending_transactions: self.mempool.len(),
+            pending_size: self.mempool.total_size(),
+            max_block_size: self.config.max_block_size,
+            since_last_block,
+        }
+    }
+
+    /// Starts mining the next block on the miner worker threads, if the mining policy is met
+    fn start_mining_job(&mut self) -> Option<MiningFuture> {
+        if !self.config.miner.policy.should_mine(&self.mining_state()) {
+            return None;
+        }
+
+        let template = match self.block_template() {
+            Ok(template) => template,
+            Err(e) => {
+                warn!("Cannot build a block template: {}", e);
+                return None;
+            }
+        };
+        let (miner, tip) = (self.miner.clone(), self.subscribe_tip());
+        Some(Box::pin(async move { miner.mine(template, tip).await }))
+    }
+
     /// Mines a new block
-    /// Based on the previous block hash and transactions that will go inside the block
+    /// Based on the previous block hash and transactions that will go inside the block,
+    /// see [`Blockchain::block_template`].
+    ///
+    /// Proof of work runs on the miner worker threads, see [`Miner`].
     pub async fn add_block(&mut self) {
-        let last_block_header = &self.blocks.last().unwrap().header;
+        let height = self.blocks.len() as u64;
+        let template = match self.block_template() {
+            Ok(template) => template,
+            Err(e) => {
+                warn!("Cannot build a block template at height {}: {}", height, e);
+                return;
+            }
+        };
 
-        let coinbase_account: &Account;
-        let coinbase_transaction: Transaction;
+        match self.miner.mine(template, self.subscribe_tip()).await {
+            Ok(mined) => self.submit_mined_block(mined).await,
+            Err(e) => warn!("Mining block at height {} stopped: {}", height, e),
+        }
+    }
 
-        coinbase_account = self
-            .wallet
-            .accounts()
-            .first()
-            .expect("No coinbase error available.");
-        let coinbase_address = coinbase_account.address();
-        coinbase_transaction = TransactionManager::create_coinbase_transaction(
-            self.wallet.private_key(),
-            self.wallet.public_key(),
-            coinbase_address,
-            BLOCKCHAIN_COINBASE_BLOCK_FEE,
-            coinbase_account.next_nonce(),
+    /// Submits a block found by the node miner, publishing it as `NewBlock`
+    async fn submit_mined_block(&mut self, mined: MinedBlock) {
+        info!(
+            "Mined block {} at height {}: {} hashes in {:.2?} ({:.0} H/s)",
+            mined.block.header().current_hash(),
+            self.blocks.len(),
+            mined.hashes,
+            mined.elapsed,
+            mined.hashrate()
         );
 
-        // Get all transactions for the block
-        let transactions = vec![coinbase_transaction];
-        let new_block = Block::new(
-            &last_block_header.current_hash,
-            &transactions,
-            last_block_header.difficulty,
-        );
+        match self.submit_block(mined.block).await {
+            Ok(acceptance) => debug_assert_eq!(acceptance, BlockAcceptance::Extended),
+            Err(e) => warn!("Node mined a block it refuses: {}", e),
+        }
+    }
 
-        self.reward_block_finder(&new_block);
-        self.push_new_block(new_block);
+    /// Template of the next block on top of the active chain, mined by the node:
+    /// the node coinbase, followed by the transactions of
+    /// [`Blockchain::select_block_transactions`]. Collected fees are added to the coinbase output.
+    pub fn block_template(&mut self) -> Result<BlockTemplate, BlockTransactionError> {
+        let height = self.blocks.len() as u64;
+        let (transactions, fees) = self.select_block_transactions()?;
+
+        Ok(BlockTemplate {
+            previous_hash: self.blocks.last().unwrap().header().current_hash().clone(),
+            bits: self.next_bits().expect("Connected blocks have valid timestamps and bits."),
+            coinbase: coinbase_builder(
+                &self.wallet,
+                self.config.consensus.block_subsidy(height) + fees,
+            ),
+            transactions,
+        })
     }
 
-    /// Validates a single block by checking several factors
-    /// Returns a Result<(), BlockValidationError>
-    pub fn validate_single_block(&mut self, hash: &String) -> Result<(), BlockValidationError> {
-        let block = self
-            .blocks
-            .iter()
-            .find(|b| b.header.current_hash == *hash)
-            .ok_or(BlockValidationError::BlockNotFound)?;
+    /// Work for an external miner, see [`BlockTemplateResponse`]
+    pub fn get_block_template(&mut self) -> Result<BlockTemplateResponse, BlockTransactionError> {
+        let height = self.blocks.len() as u64;
+        let (transactions, fees) = self.select_block_transactions()?;
+        let bits = self.next_bits().expect("Connected blocks have valid timestamps and bits.");
+        let target = Target::from_compact(bits).expect("Expected bits encode a valid target.");
+
+        Ok(BlockTemplateResponse {
+            version: BLOCK_HEADER_VERSION,
+            height,
+            previous_hash: self.blocks.last().unwrap().header().current_hash().clone(),
+            bits,
+            target: HashHelper::to_hex(&target.to_be_bytes()),
+            coinbase_value: self.config.consensus.block_subsidy(height) + fees,
+            transactions,
+        })
+    }
 
-        if self.blocks.len() <= 1 {
-            return Err(BlockValidationError::InsufficientBlocks);
+    /// Mempool transactions for the next block, with the fees they pay: the highest fee rate
+    /// first, fitting into `max_block_size` next to the coinbase.
+    /// Pending transactions that are no longer valid are dropped from the mempool.
+    /// Fails if the node coinbase itself is invalid.
+    fn select_block_transactions(
+        &mut self,
+    ) -> Result<(Vec<Transaction>, u64), BlockTransactionError> {
+        let height = self.blocks.len() as u64;
+
+        // Reserve room for the coinbase, the fee amount and extra nonce can add a few more bytes
+        let coinbase_size = coinbase_builder(&self.wallet, u64::MAX)(u64::MAX).size();
+
+        loop {
+            let selection = self
+                .mempool
+                .select_for_block(self.config.max_block_size.saturating_sub(coinbase_size));
+
+            // Validated behind the node coinbase, external miners claim the same value
+            let coinbase = coinbase_builder(
+                &self.wallet,
+                self.config.consensus.block_subsidy(height) + selection.fees,
+            );
+            let mut transactions = vec![coinbase(0)];
+            transactions.extend(selection.transactions);
+
+            let context = self.config.consensus.spend_context(height);
+            match validate_block_transactions(&transactions, self, context) {
+                Ok(_) => {
+                    transactions.remove(0);
+                    return Ok((transactions, selection.fees));
+                }
+                Err(e) if e.index == 0 => return Err(e),
+                Err(e) => {
+                    warn!("Dropping invalid pending transaction: {}", e);
+                    let hash = transactions[e.index].metadata().transaction_hash;
+                    self.mempool.remove(&hash, RemovalReason::Invalid);
+                }
+            }
         }
+    }
 
-        if !HashHelper::is_valid_hash(block) {
-            info!("Block hash: {}", block.header.current_hash);
-            return Err(BlockValidationError::InvalidHash);
+    /// Submits a block mined by this node or received from elsewhere.
+    ///
+    /// The block is checked against its parent, which may be on a side branch, then added
+    /// to the block tree. The node follows the branch with the most cumulative work: if the
+    /// block makes a side branch heavier than the active chain, the chain is reorganized.
+    /// Transactions of disconnected blocks go back to the mempool.
+    ///
+    /// The block is persisted once its branch is validated and before it changes the active
+    /// chain, so an invalid block is never stored. If it cannot be stored, it is dropped from
+    /// the tree and the node is left as it was.
+    ///
+    /// Publishes `NewBlock` for every block joining the active chain and `ChainReorg`
+    /// when blocks were disconnected.
+    pub async fn submit_block(
+        &mut self,
+        block: Block,
+    ) -> Result<BlockAcceptance, SubmitBlockError> {
+        self.check_block_header(&block)?;
+        let branch = self.insert_block(block.clone())?;
+
+        if let Err(e) = self.storage.lock().await.append_block(&block) {
+            let hash = block.header().current_hash();
+            warn!("Cannot store block {}: {}", hash, e);
+            self.tree.remove_subtree(hash);
+            return Err(e.into());
         }
+        Ok(branch.map_or(BlockAcceptance::SideBranch, |branch| self.activate_branch(branch)))
+    }
 
-        let prev_block = self
-            .blocks
-            .iter()
-            .find(|b| b.header.current_hash == block.header.previous_hash)
+    /// Checks a block not yet in the tree against its parent, see
+    /// [`consensus::validate_header`]. The body is only checked once its branch is connected.
+    fn check_block_header(&self, block: &Block) -> Result<(), BlockValidationError> {
+        let header = block.header();
+        if self.tree.contains(header.current_hash()) {
+            return Err(BlockValidationError::AlreadyKnown);
+        }
+
+        if self.tree.is_empty() {
+            let bits = self.config.difficulty.initial_bits;
+            return consensus::validate_header(block, None, None, bits);
+        }
+
+        let parent = self
+            .tree
+            .get(header.previous_hash())
             .ok_or(BlockValidationError::PreviousBlockNotFound)?;
+        let ancestor = |height| {
+            self.tree
+                .ancestor(header.previous_hash(), height)
+                .map(|entry| &entry.block)
+        };
+        let expected_bits = next_bits_at(
+            parent.height + 1,
+            Some(&parent.block),
+            ancestor,
+            &self.config.difficulty,
+        )?;
+        let median_time_past = consensus::median_time_past(parent.height, ancestor)?;
+
+        consensus::validate_header(
+            block,
+            Some(&parent.block),
+            Some(median_time_past),
+            expected_bits,
+        )
+    }
 
-        if prev_block.header.current_hash != block.header.previous_hash {
-            return Err(BlockValidationError::PreviousHashMismatch);
+    /// Adds a block with a valid header to the tree and activates its branch
+    /// if it has more work than the active chain. On equal work the first seen branch stays.
+    fn accept_block(&mut self, block: Block) -> Result<BlockAcceptance, BlockValidationError> {
+        let branch = self.insert_block(block)?;
+        Ok(branch.map_or(BlockAcceptance::SideBranch, |branch| self.activate_branch(branch)))
+    }
+
+    /// Adds a block with a valid header to the tree. Returns its validated branch if it has
+    /// more work than the active chain, see [`Blockchain::validate_branch`].
+    fn insert_block(&mut self, block: Block) -> Result<Option<Branch>, BlockValidationError> {
+        let hash = block.header().current_hash().clone();
+        let chain_work = self.tree.insert(block)?.chain_work;
+
+        if chain_work <= self.chain_work() {
+            info!("Block {} stored on a side branch", hash);
+            return Ok(None);
         }
+        self.validate_branch(&hash).map(Some)
+    }
 
-        if block.header.timestamp <= prev_block.header.timestamp {
-            return Err(BlockValidationError::InvalidTimestamp);
+    /// Cumulative work of the active chain
+    pub fn chain_work(&self) -> u128 {
+        self.blocks
+            .last()
+            .and_then(|tip| self.tree.get(tip.header().current_hash()))
+            .map_or(0, |entry| entry.chain_work)
+    }
+
+    /// Validates the blocks of the branch ending with `tip` against the UTXO set at the point
+    /// it forks from the active chain, without changing the node.
+    ///
+    /// If a branch block is invalid, it is removed from the tree with its descendants.
+    fn validate_branch(&mut self, tip: &str) -> Result<Branch, BlockValidationError> {
+        let entries = self
+            .tree
+            .branch(tip, |entry| self.is_active(entry))
+            .ok_or(BlockValidationError::BlockNotFound)?;
+        // Number of active blocks kept, the height of the first branch block
+        let fork_height = entries
+            .first()
+            .map_or(self.blocks.len(), |entry| entry.height as usize);
+        let branch: Vec<Block> = entries.into_iter().map(|entry| entry.block.clone()).collect();
+
+        let mut utxos = self.utxo_before(fork_height);
+        for (offset, block) in branch.iter().enumerate() {
+            let height = (fork_height + offset) as u64;
+            if let Err(e) = consensus::validate_body(block, height, &self.config.consensus, &utxos)
+            {
+                let hash = block.header().current_hash();
+                warn!("Block {} at height {} is invalid: {}", hash, height, e);
+                self.tree.remove_subtree(hash);
+                return Err(e);
+            }
+            utxos
+                .connect_block(block, height)
+                .expect("Block transactions were validated against the UTXO set.");
         }
 
-        Ok(())
+        Ok(Branch {
+            tip: tip.to_string(),
+            fork_height,
+            blocks: branch,
+        })
     }
 
-    /// Validates the full chain by looping through every block
-    /// Returns a Result<(), BlockValidationError>
-    pub fn validate_full_chain(&mut self) -> Result<(), BlockValidationError> {
-        if self.blocks.len() <= 1 {
-            return Err(BlockValidationError::InsufficientBlocks);
+    /// Makes a validated branch the active chain: disconnects the active blocks above its fork
+    /// point and connects the branch.
+    fn activate_branch(&mut self, branch: Branch) -> BlockAcceptance {
+        let Branch {
+            tip,
+            fork_height,
+            blocks: branch,
+        } = branch;
+
+        let disconnected = self.disconnect_to(fork_height);
+        for block in &branch {
+            self.connect_block(block.clone())
+                .expect("Block transactions were validated against the UTXO set.");
+        }
+        self.tip.send_replace(tip);
+        self.readmit_awaiting_parent();
+        self.refresh_wallet_balances();
+
+        for (offset, block) in branch.iter().enumerate() {
+            self.publish(
+                EventTopic::NewBlock,
+                json!({
+                    "hash": block.header().current_hash(),
+                    "height": fork_height + offset,
+                    "transactions": block.body().transactions().len(),
+                }),
+            );
+            for transaction in block.body().transactions() {
+                if !transaction.is_coinbase() {
+                    self.publish(
+                        EventTopic::TxConfirmed,
+                        json!({
+                            "transaction_hash":
+                                HashHelper::to_hex(&transaction.metadata().transaction_hash),
+                            "block_hash": block.header().current_hash(),
+                            "height": fork_height + offset,
+                        }),
+                    );
+                }
+            }
+        }
+
+        if disconnected.is_empty() {
+            return BlockAcceptance::Extended;
+        }
+
+        let hashes = |blocks: &[Block]| -> Vec<String> {
+            blocks.iter().map(|b| b.header().current_hash().clone()).collect()
+        };
+        info!(
+            "Chain reorganized at height {}: {} blocks disconnected, {} connected",
+            fork_height,
+            disconnected.len(),
+            branch.len()
+        );
+        self.publish(
+            EventTopic::ChainReorg,
+            json!({
+                "fork_height": fork_height,
+                "disconnected": hashes(&disconnected),
+                "connected": hashes(&branch),
+            }),
+        );
+
+        self.return_to_mempool(&disconnected);
+
+        BlockAcceptance::Reorganized {
+            disconnected: disconnected.len(),
+            connected: branch.len(),
+        }
+    }
+
+    /// Whether the tree entry is a block of the active chain
+    fn is_active(&self, entry: &BlockTreeEntry) -> bool {
+        self.blocks.get(entry.height as usize).is_some_and(|block| {
+            block.header().current_hash() == entry.block.header().current_hash()
+        })
+    }
+
+    /// Disconnects active blocks down to `height` blocks, restoring spent outputs from
+    /// their undo data. Returns the disconnected blocks in ascending height.
+    fn disconnect_to(&mut self, height: usize) -> Vec<Block> {
+        let mut disconnected = Vec::new();
+        while self.blocks.len() > height {
+            let block = self.blocks.pop().expect("more than `height` blocks");
+            let undo = self.undo.pop().expect("Undo data is recorded for every connected block.");
+            self.utxo
+                .disconnect_block(&block, undo)
+                .expect("Undo data matches the block it was recorded for.");
+
+            let ledger_len = self.ledger.len() - block.body().transactions().len();
+            self.ledger.truncate(ledger_len);
+            for transaction in block.body().transactions() {
+                self.mined.remove(&transaction.metadata().transaction_hash);
+            }
+            self.fees.disconnect_from(self.blocks.len() as u64);
+            disconnected.push(block);
         }
+        disconnected.reverse();
+        disconnected
+    }
 
-        for (idx, block) in self.blocks.iter().enumerate() {
-            if !HashHelper::is_valid_hash(block) {
-                info!(
-                    "Failed on idx: {}, block hash: {}",
-                    idx, block.header.current_hash
-                );
-                return Err(BlockValidationError::InvalidHash);
+    /// Offers transactions of disconnected blocks back to the mempool.
+    /// Coinbases and transactions confirmed or conflicting in the new chain are dropped.
+    /// The mempool refuses spends of pending outputs, so a transaction spending one of a
+    /// returned parent waits until that parent is confirmed again.
+    fn return_to_mempool(&mut self, blocks: &[Block]) {
+        for transaction in blocks.iter().flat_map(|b| b.body().transactions()) {
+            if !transaction.is_coinbase() {
+                self.offer_to_mempool(transaction.clone());
             }
+        }
+    }
 
-            if idx != 0 {
-                let prev_block = self
-                    .blocks
+    /// Offers again the transactions waiting for their parent, once a block was connected.
+    /// Those whose parent left the mempool without being mined are dropped.
+    fn readmit_awaiting_parent(&mut self) {
+        for transaction in std::mem::take(&mut self.awaiting_parent) {
+            self.offer_to_mempool(transaction);
+        }
+    }
+
+    /// Submits a transaction of a disconnected block, or keeps it while it spends a pending one
+    fn offer_to_mempool(&mut self, transaction: Transaction) {
+        let awaits_parent = transaction.inputs().iter().any(|input| {
+            let parent = &input.previous_tx_hash;
+            self.mempool.contains(parent)
+                || self
+                    .awaiting_parent
                     .iter()
-                    .find(|b| b.header.current_hash == block.header.previous_hash)
-                    .ok_or(BlockValidationError::PreviousBlockNotFound)?;
+                    .any(|t| &t.metadata().transaction_hash == parent)
+        });
+        if awaits_parent {
+            self.awaiting_parent.push(transaction);
+        } else if let Err(e) = self.submit_transaction(transaction.clone()) {
+            info!(
+                "Transaction {} of a disconnected block dropped: {}",
+                HashHelper::to_hex(&transaction.metadata().transaction_hash),
+                e
+            );
+        }
+    }
 
-                if prev_block.header.current_hash != block.header.previous_hash {
-                    return Err(BlockValidationError::PreviousHashMismatch);
-                }
+    fn publish(&self, topic: EventTopic, data: serde_json::Value) {
+        // No receivers is not an error, events are simply dropped
+        let _ = self.events.send(NodeEvent { topic, data });
+    }
 
-                if block.header.timestamp <= prev_block.header.timestamp {
-                    return Err(BlockValidationError::InvalidTimestamp);
-                }
-            }
+    /// Validates a single block against its parent and the UTXO set as of its parent,
+    /// see [`consensus`](super::consensus) for the enforced rules
+    /// Returns a Result<(), BlockValidationError>
+    pub fn validate_single_block(&mut self, hash: &str) -> Result<(), BlockValidationError> {
+        let height = self
+            .chain_validator()
+            .index_of(hash)
+            .ok_or(BlockValidationError::BlockNotFound)?;
+
+        if self.blocks.len() <= 1 {
+            return Err(BlockValidationError::InsufficientBlocks);
         }
-        Ok(())
+
+        let utxos = self.utxo_before(height);
+        self.validate_blocks(height, height, utxos)
+    }
+
+    /// Validates the full chain by looping through every block
+    /// Returns a Result<(), BlockValidationError>
+    pub fn validate_full_chain(&mut self) -> Result<(), BlockValidationError> {
+        if self.blocks.len() <= 1 {
+            return Err(BlockValidationError::InsufficientBlocks);
+        }
+
+        self.validate_blocks(0, self.blocks.len() - 1, UtxoSet::new())
     }
 
     /// Validates a range between `from_hash` and `to_hash`
@@ -256,92 +1027,229 @@ impl Blockchain {
             return Err(BlockValidationError::InsufficientBlocks);
         }
 
-        for idx in from_index..=to_index {
-            let block = self
-                .blocks
-                .get(idx)
-                .ok_or(BlockValidationError::BlockNotFound)?;
+        let utxos = self.utxo_before(from_index);
+        self.validate_blocks(from_index, to_index, utxos)
+    }
 
-            if !HashHelper::is_valid_hash(block) {
-                info!(
-                    "Failed on idx: {}, block hash: {}",
-                    idx, block.header.current_hash
-                );
-                return Err(BlockValidationError::InvalidHash);
-            }
+    /// Validates blocks `from_index..=to_index`, returning the first failure
+    fn validate_blocks(
+        &self,
+        from_index: usize,
+        to_index: usize,
+        utxos: UtxoSet,
+    ) -> Result<(), BlockValidationError> {
+        self.chain_validator()
+            .validate_range(from_index, to_index, utxos)
+            .map_err(|failures| {
+                let failure = failures.into_iter().next().expect("at least one failure");
+                info!("{}", failure);
+                failure.error
+            })
+    }
 
-            if idx != 0 {
-                let prev_block = self
-                    .blocks
-                    .iter()
-                    .find(|b| b.header.current_hash == block.header.previous_hash)
-                    .ok_or(BlockValidationError::PreviousBlockNotFound)?;
+    /// Returns a validator over the chain blocks
+    pub fn chain_validator(&self) -> ChainValidator<'_> {
+        ChainValidator::new(
+            &self.blocks,
+            self.config.difficulty.clone(),
+            self.config.consensus.clone(),
+        )
+    }
 
-                if prev_block.header.current_hash != block.header.previous_hash {
-                    return Err(BlockValidationError::PreviousHashMismatch);
-                }
+    /// Validates the full chain without stopping at the first invalid block,
+    /// reporting every failure with the offending height and hash
+    pub fn validate_full_chain_report(&self) -> Result<(), Vec<ChainValidationFailure>> {
+        self.chain_validator().collect_all(true).validate_chain()
+    }
 
-                if block.header.timestamp <= prev_block.header.timestamp {
-                    return Err(BlockValidationError::InvalidTimestamp);
-                }
-            }
+    /// Compact target the next block must declare
+    pub fn next_bits(&self) -> Result<u32, BlockValidationError> {
+        next_bits(&self.blocks, &self.config.difficulty)
+    }
+
+    /// UTXO set as it was before the block at `height` was connected,
+    /// rebuilt by disconnecting the blocks above it with their undo data
+    fn utxo_before(&self, height: usize) -> UtxoSet {
+        let mut utxos = self.utxo.clone();
+        for idx in (height..self.blocks.len()).rev() {
+            utxos
+                .disconnect_block(&self.blocks[idx], self.undo[idx].clone())
+                .expect("Undo data is recorded for every connected block.");
         }
-        Ok(())
+        utxos
     }
 
     /// Finds indexes of:
     /// 1) the hash from where `validate_range_chain` should start validation
     /// 2) the hash to where `validate_range_chain` should end validation, inclusive
     pub fn find_hash_indices(&self, from_hash: &str, to_hash: &str) -> Option<(usize, usize)> {
-        let mut hash_to_index: HashMap<&str, usize> = HashMap::new();
+        let validator = self.chain_validator();
+        Some((validator.index_of(from_hash)?, validator.index_of(to_hash)?))
+    }
 
-        for (i, block) in self.blocks.iter().enumerate() {
-            hash_to_index.entry(&block.header.current_hash).or_insert(i);
-        }
+    /// Returns copy of the blocks of the active chain
+    pub fn blocks(&self) -> Vec<Block> {
+        self.blocks.clone()
+    }
+
+    /// Returns every known block, including side branches
+    pub fn block_tree(&self) -> &BlockTree {
+        &self.tree
+    }
+
+    /// Returns the set of unspent transaction outputs
+    pub fn utxo(&self) -> &UtxoSet {
+        &self.utxo
+    }
+
+    /// Confirmed balance of `address`
+    pub fn balance(&self, address: &str) -> u64 {
+        self.utxo.balance(address)
+    }
 
-        let from_index = hash_to_index.get(from_hash);
-        let to_index = hash_to_index.get(to_hash);
+    /// Balance of `address`, split into what the next block could spend, coinbase outputs
+    /// still maturing and outputs of pending transactions
+    pub fn address_balance(&self, address: &str) -> Balance {
+        self.addresses_balance(&[address.to_string()])
+    }
 
-        match (from_index, to_index) {
-            (Some(from_idx), Some(to_idx)) => Some((*from_idx, *to_idx)),
-            _ => None,
+    /// Balance of `addresses` together. Outputs spent by pending transactions no longer count,
+    /// the change they pay back counts as unconfirmed.
+    pub fn addresses_balance(&self, addresses: &[String]) -> Balance {
+        let utxos = self.unspent_outputs(addresses);
+        let context = SpendContext {
+            height: utxos.height,
+            coinbase_maturity: utxos.coinbase_maturity,
+        };
+        let mut balance = Balance::default();
+        for (_, entry) in utxos.utxos {
+            if entry.is_mature(context) {
+                balance.spendable += entry.output.amount;
+            } else {
+                balance.immature += entry.output.amount;
+            }
+        }
+
+        let addresses: HashSet<&String> = addresses.iter().collect();
+        for transaction in self.mempool.transactions() {
+            for output in transaction.outputs() {
+                if addresses.contains(&output.recipient_address) {
+                    balance.unconfirmed += output.amount;
+                }
+            }
         }
+        balance
     }
 
-    /// Returns copy of the blocks
-    pub fn blocks(&self) -> Vec<Block> {
-        self.blocks.clone()
+    /// Unspent outputs of `addresses` not spent by pending transactions yet
+    pub fn unspent_outputs(&self, addresses: &[String]) -> UtxosResponse {
+        let context = self.config.consensus.spend_context(self.blocks.len() as u64);
+        let utxos = addresses
+            .iter()
+            .flat_map(|address| self.utxo.outputs_for_address(address))
+            .filter(|(outpoint, _)| self.mempool.spender_of(outpoint).is_none())
+            .collect();
+        UtxosResponse {
+            height: context.height,
+            coinbase_maturity: context.coinbase_maturity,
+            utxos,
+        }
     }
 
-    fn reward_block_finder(&mut self, block: &Block) {
-        let coinbase_transaction = block
-            .body()
+    /// Transactions of the active chain, then pending ones, paying to or spending from
+    /// `addresses`
+    pub fn history(&self, addresses: &[String]) -> Vec<HistoryEntry> {
+        let addresses: HashSet<&String> = addresses.iter().collect();
+        let involves = |transaction: &Transaction| {
+            let outputs = transaction.outputs();
+            outputs.iter().any(|output| addresses.contains(&output.recipient_address))
+                || transaction.inputs().iter().any(|input| {
+                    let address = TransactionHelper::address_from_public_key(&input.public_key);
+                    addresses.contains(&address)
+                })
+        };
+
+        let mined = self.ledger.iter().filter_map(|transaction| {
+            let height = *self.mined.get(&transaction.metadata().transaction_hash)?;
+            Some((transaction.clone(), TransactionStatus::Mined { height }))
+        });
+        let pending = self
+            .mempool
             .transactions()
-            .first()
-            .unwrap_or_else(|| {
-                panic!(
-                    "{}",
-                    "No (coinbase) Transactions in the Transactions vector of a Block."
-                )
+            .into_iter()
+            .map(|transaction| (transaction, TransactionStatus::Pending));
+        mined
+            .chain(pending)
+            .filter(|(transaction, _)| involves(transaction))
+            .map(|(transaction, status)| HistoryEntry {
+                transaction,
+                status,
             })
-            .clone();
+            .collect()
+    }
 
-        let coinbase_transaction_output = coinbase_transaction
-            .outputs()
-            .first()
-            .unwrap_or_else(|| {
-                panic!(
-                    "{}",
-                    "No (coinbase) TransactionOutput in the TransactionOutputs vector."
-                )
-            })
-            .clone();
+    /// Coins issued by the blocks up to `height` included, following the subsidy schedule.
+    /// Heights above the tip give the supply once the chain reaches them.
+    pub fn issued_supply(&self, height: u64) -> u64 {
+        self.config.consensus.issued_supply(height)
+    }
 
-        let recipient_address = coinbase_transaction_output.recipient_address.to_string();
+    /// Returns the node wallet, receiving the mining rewards
+    pub fn wallet(&self) -> &Wallet {
+        &self.wallet
+    }
 
-        self.push_transaction_to_ledger(coinbase_transaction);
+    /// Returns the recovery mnemonic of a node wallet created by this start, only once.
+    /// A node wallet loaded from disk has none.
+    pub fn take_wallet_mnemonic(&mut self) -> Option<String> {
+        self.wallet.take_mnemonic()
+    }
+
+    /// Updates the balances, unspent outputs and history of the node wallet accounts to the
+    /// active chain, as [`Wallet::sync`] does for wallets connected to the node
+    fn refresh_wallet_balances(&mut self) {
+        let mut accounts = std::mem::take(&mut self.wallet.accounts);
+        for account in accounts.iter_mut() {
+            let addresses: Vec<String> = [Chain::External, Chain::Internal]
+                .into_iter()
+                .flat_map(|chain| account.addresses(chain))
+                .cloned()
+                .collect();
+            let utxos = self.unspent_outputs(&addresses);
+            let context = SpendContext {
+                height: utxos.height,
+                coinbase_maturity: utxos.coinbase_maturity,
+            };
+            account.set_balance(self.addresses_balance(&addresses));
+            account.set_utxos(utxos.utxos, context);
+            account.set_history(self.history(&addresses));
+        }
+        self.wallet.accounts = accounts;
+    }
+
+    /// Connects a block on top of the chain: spends its inputs and adds its outputs
+    /// to the UTXO set, records its transactions in the ledger and removes them from the mempool
+    fn connect_block(&mut self, block: Block) -> Result<(), UtxoError> {
+        let height = self.blocks.len() as u64;
+        let undo = self.utxo.connect_block(&block, height)?;
+        self.undo.push(undo);
+        self.fees.record_block(height, block.body().transactions(), self.config.max_block_size);
+
+        for transaction in block.body().transactions() {
+            if !transaction.is_coinbase() {
+                let hash = transaction.metadata().transaction_hash;
+                self.mempool.remove(&hash, RemovalReason::Mined);
+
+                // Pending transactions double-spending the mined one can never be confirmed
+                let spent: Vec<_> = transaction.inputs().iter().map(|i| i.outpoint()).collect();
+                self.mempool.remove_conflicts(&spent);
+            }
+            self.mined.insert(transaction.metadata().transaction_hash, height);
+            self.push_transaction_to_ledger(transaction.clone());
+        }
 
-        self.update_utxo_with_transaction(recipient_address, coinbase_transaction_output);
+        self.blocks.push(block);
+        Ok(())
     }
 
     /// Push the whole transaction to ledger
@@ -349,34 +1257,98 @@ impl Blockchain {
         self.ledger.push(transaction);
     }
 
-    /// Update UTXO hash map for that address with a new transaction output
-    fn update_utxo_with_transaction(
-        &mut self,
-        address: String,
-        transaction_output: TransactionOutput,
-    ) {
-        self.utxo
-            .entry(address)
-            .or_default()
-            .push(transaction_output);
+    pub async fn shutdown(&mut self) {
+        //self.listener.shutdown();
+        let path = self.config.data_dir.join(NODE_WALLET_FILE);
+        if let Err(e) = self.wallet.save(&path) {
+            warn!("Cannot save the node wallet to {}: {}", path.display(), e);
+        }
     }
+}
 
-    /// Push newly mined block to the blocks vector of a blockchain
-    fn push_new_block(&mut self, block: Block) {
-        self.blocks.push(block);
+/// Node wallet stored in `config.data_dir`, created with its coinbase account on first start.
+/// The node signs without anyone to unlock it, so the wallet stays unlocked.
+/// It is never created without a password, see [`BlockchainConfig::wallet_password`].
+async fn open_node_wallet(
+    config: &BlockchainConfig,
+    ws_uri: String,
+) -> Result<Wallet, Box<dyn Error>> {
+    let path = config.data_dir.join(NODE_WALLET_FILE);
+    if path.exists() {
+        let mut wallet = Wallet::load(&path, ws_uri).await?;
+        wallet.unlock(&config.wallet_password, Duration::MAX)?;
+        info!("Loaded node wallet {} from {}", wallet.id, path.display());
+        return Ok(wallet);
     }
 
-    pub async fn shutdown(&mut self) {
-        //self.listener.shutdown();
+    if config.wallet_password.is_empty() {
+        let message = format!("The node wallet needs a password, set {}", NODE_WALLET_PASSWORD_ENV);
+        return Err(message.into());
+    }
+    let mut wallet = Wallet::new("MiningFeeWallet#1".to_string(), ws_uri).await;
+    wallet.create_new_account("BlockchainNodeWalletAccount")?;
+    wallet.encrypt_with(&config.wallet_password, config.wallet_kdf)?;
+    wallet.save(&path)?;
+    info!("Created node wallet {} in {}", wallet.id, path.display());
+    Ok(wallet)
+}
+
+/// Addresses of a `{"addresses": [...]}` request payload
+fn addresses_of(payload: &serde_json::Value) -> Result<Vec<String>, String> {
+    payload
+        .get("addresses")
+        .and_then(|addresses| serde_json::from_value(addresses.clone()).ok())
+        .ok_or_else(|| "Request is missing the `addresses`".to_string())
+}
+
+/// Coinbase paying `amount` to the first account of `wallet`, the extra nonce set as its
+/// input nonce
+fn coinbase_builder(wallet: &Wallet, amount: u64) -> CoinbaseBuilder {
+    let account = wallet.accounts().first().expect("No coinbase account available.");
+    let address = account.address().clone();
+    let public_key = account
+        .public_key_of(&address)
+        .expect("The account hands out its first address.");
+
+    Arc::new(move |extra_nonce| {
+        TransactionManager::create_coinbase_transaction(&public_key, &address, amount, extra_nonce)
+    })
+}
+
+impl UtxoView for Blockchain {
+    fn unspent_output(&self, outpoint: &OutPoint) -> Option<UtxoEntry> {
+        self.utxo.unspent_output(outpoint)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::{BLOCKCHAIN_COINBASE_BLOCK_FEE, BLOCKCHAIN_COINBASE_GENESIS_BLOCK_FEE};
+    use crate::transaction::test_utils::keys;
+    use crate::transaction::{TransactionOutput, TransactionValidationError};
+    use serde_json::Value;
+
+    fn temp_data_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("oxidize-chain-{}", uuid::Uuid::new_v4()))
+    }
+
+    /// Node config with a fresh data dir, where coinbase outputs can be spent right away.
+    /// The node wallet key derivation is cheap, the default is too slow for unit tests.
+    fn test_config() -> BlockchainConfig {
+        let mut config = BlockchainConfig::new(true).with_data_dir(temp_data_dir());
+        config.consensus.coinbase_maturity = 0;
+        config.wallet_password = "node password".to_string();
+        config.wallet_kdf = KdfParams {
+            memory_kib: 64,
+            iterations: 1,
+            parallelism: 1,
+        };
+        config
+    }
 
     async fn build_blockchain() -> Blockchain {
-        let config = BlockchainConfig::new(true);
+        let config = test_config();
 
         match Blockchain::build(config).await {
             Ok(node) => node,
@@ -401,7 +1373,7 @@ mod tests {
             node.add_block().await;
         }
 
-        assert_eq!(node.blocks.len(), 3);
+        assert_eq!(node.blocks.len(), 4); // Genesis block + 3 mined blocks
 
         node.shutdown().await
     }
@@ -413,7 +1385,7 @@ mod tests {
         node.add_block().await;
 
         let blocks = node.blocks().clone();
-        let block_1 = blocks.get(0).unwrap().clone();
+        let block_1 = blocks.first().unwrap().clone();
 
         let validation = node.validate_single_block(block_1.header().current_hash());
 
@@ -458,4 +1430,747 @@ mod tests {
 
         node.shutdown().await
     }
+
+    #[tokio::test]
+    async fn it_refuses_to_create_the_node_wallet_without_password() {
+        let mut config = test_config();
+        config.wallet_password = String::new();
+
+        assert!(Blockchain::build(config.clone()).await.is_err());
+        assert!(!config.data_dir.join(NODE_WALLET_FILE).exists());
+        std::fs::remove_dir_all(&config.data_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn it_reloads_blockchain_from_disk() {
+        let data_dir = temp_data_dir();
+        let config = test_config().with_data_dir(&data_dir);
+        let mut node = Blockchain::build(config.clone()).await.unwrap();
+
+        for _ in 1..=2 {
+            node.add_block().await;
+        }
+        let tip = node.blocks().last().unwrap().header().current_hash().clone();
+        assert!(node.take_wallet_mnemonic().is_some());
+        assert_eq!(node.take_wallet_mnemonic(), None);
+        let wallet_id = node.wallet.id.clone();
+        let address = node.wallet.accounts()[0].address().clone();
+        node.shutdown().await;
+        drop(node);
+
+        let mut reloaded = Blockchain::build(config).await.unwrap();
+        assert_eq!(reloaded.blocks.len(), 3);
+        assert_eq!(reloaded.blocks().last().unwrap().header().current_hash(), &tip);
+        assert!(reloaded.validate_full_chain().is_ok());
+
+        // Mining rewards keep going to the same wallet, which can still spend them
+        assert_eq!(reloaded.wallet.id, wallet_id);
+        assert_eq!(reloaded.take_wallet_mnemonic(), None);
+        assert_eq!(reloaded.wallet.accounts()[0].address(), &address);
+        assert!(reloaded.wallet.key_pair(&address).is_ok());
+        assert_eq!(
+            reloaded.balance(&address),
+            BLOCKCHAIN_COINBASE_GENESIS_BLOCK_FEE + 2 * BLOCKCHAIN_COINBASE_BLOCK_FEE
+        );
+
+        reloaded.shutdown().await;
+        std::fs::remove_dir_all(data_dir).unwrap();
+    }
+
+    /// Spends the genesis coinbase output back to the node wallet, paying `fee`
+    fn spend_genesis_coinbase(node: &Blockchain, fee: u64) -> Transaction {
+        spend_first_output(node, &node.blocks[0].body().transactions()[0], fee)
+    }
+
+    /// Spends the first output of `previous`, paid to the node wallet, back to the node wallet
+    fn spend_first_output(node: &Blockchain, previous: &Transaction, fee: u64) -> Transaction {
+        let wallet = &node.wallet;
+        let account = wallet.accounts().first().unwrap();
+        let (public_key, private_key) = wallet.key_pair(account.address()).unwrap();
+        let amount = previous.outputs()[0].amount;
+
+        let inputs = vec![crate::transaction::TransactionInput {
+            previous_tx_hash: previous.metadata().transaction_hash,
+            index: 0,
+            signature: Vec::new(),
+            public_key,
+            amount,
+            nonce: 0,
+        }];
+        let outputs = vec![TransactionOutput {
+            recipient_address: account.address().to_string(),
+            amount: amount - fee,
+        }];
+        TransactionManager::create_transaction(inputs, outputs, private_key)
+    }
+
+    #[tokio::test]
+    async fn it_refuses_spending_immature_coinbase_outputs() {
+        let mut config = test_config();
+        config.consensus.coinbase_maturity = 2;
+        let mut node = Blockchain::build(config).await.unwrap();
+        let address = node.wallet().accounts()[0].address().clone();
+
+        let immature = node.address_balance(&address);
+        assert_eq!(immature.spendable, 0);
+        assert_eq!(immature.immature, BLOCKCHAIN_COINBASE_GENESIS_BLOCK_FEE);
+        assert_eq!(node.wallet().balance(), immature);
+
+        // The next block would be at height 1, only one block above the genesis coinbase
+        assert!(matches!(
+            node.submit_transaction(spend_genesis_coinbase(&node, 1)),
+            Err(MempoolError::Invalid(
+                TransactionValidationError::ImmatureCoinbaseSpend { depth: 1, required: 2, .. }
+            ))
+        ));
+
+        node.add_block().await;
+        let balance = node.wallet().balance();
+        assert_eq!(balance.spendable, BLOCKCHAIN_COINBASE_GENESIS_BLOCK_FEE);
+        assert_eq!(balance.immature, BLOCKCHAIN_COINBASE_BLOCK_FEE);
+        assert!(node.submit_transaction(spend_genesis_coinbase(&node, 1)).is_ok());
+
+        node.shutdown().await
+    }
+
+    #[tokio::test]
+    async fn it_pays_from_the_node_wallet_with_change() {
+        use crate::transaction::TransactionError;
+        use crate::wallet::WalletError;
+
+        let mut node = build_blockchain().await;
+        let account = node.wallet.accounts()[0].name().clone();
+        let fee_rate = FeeRate(50);
+
+        let payment = node.wallet.create_payment(&account, "bob", 100, fee_rate).unwrap();
+        assert_eq!(payment.inputs().len(), 1);
+        assert_eq!(payment.outputs()[0].recipient_address, "bob");
+        let change = &payment.outputs()[1].recipient_address;
+        let path = node.wallet.accounts()[0].path_of(change).unwrap();
+        assert_eq!((path.chain, path.index), (Chain::Internal, 0));
+        assert!(FeeRate::of(payment.fee(), payment.size()) >= fee_rate);
+        assert!(node.submit_transaction(payment.clone()).is_ok());
+
+        // Unrecorded, the payment leaves the nonce as is: the next one reuses it, yet its
+        // hash differs and spending the same output again is a conflict
+        let again = node.wallet.create_payment(&account, "bob", 100, fee_rate).unwrap();
+        assert_eq!(again.inputs()[0].nonce, payment.inputs()[0].nonce);
+        assert_ne!(again.metadata().transaction_hash, payment.metadata().transaction_hash);
+        assert!(matches!(node.submit_transaction(again), Err(MempoolError::Conflict(_))));
+
+        // Spending the whole balance leaves nothing for the fee
+        let amount = BLOCKCHAIN_COINBASE_GENESIS_BLOCK_FEE;
+        assert!(matches!(
+            node.wallet.create_payment(&account, "bob", amount, fee_rate),
+            Err(WalletError::Transaction(TransactionError::NotEnoughFunds { .. }))
+        ));
+
+        node.shutdown().await
+    }
+
+    #[tokio::test]
+    async fn it_syncs_wallets_with_the_node() {
+        let mut config = test_config();
+        config.miner.policy = crate::miner::MiningPolicy::MempoolNotEmpty;
+        let mut node = Blockchain::build(config).await.unwrap();
+        let control = node.mining_control();
+        let mut wallet = Wallet::new("synced".to_string(), node.local_addr().to_string()).await;
+        let address = wallet.create_new_account("main").unwrap().address().clone();
+
+        let account = node.wallet.accounts()[0].name().clone();
+        let payment = node.wallet.create_payment(&account, &address, 300, FeeRate(50)).unwrap();
+        node.submit_transaction(payment).unwrap();
+        assert_eq!(node.address_balance(&address).unconfirmed, 300);
+
+        let synced = async {
+            let mut updates = wallet.subscribe_updates().await.unwrap();
+            wallet.sync().await.unwrap();
+            let pending = Balance {
+                unconfirmed: 300,
+                ..Balance::default()
+            };
+            assert_eq!(wallet.balance(), pending);
+            let history = wallet.accounts()[0].transaction_history();
+            assert_eq!(history[0].status, TransactionStatus::Pending);
+
+            // Mining the payment announces it, the wallet syncs again
+            control.start();
+            wallet.sync_on_update(&mut updates).await.unwrap();
+        };
+        tokio::select! {
+            _ = node.run() => panic!("Node stopped running"),
+            synced = tokio::time::timeout(Duration::from_secs(10), synced) => synced.unwrap(),
+        }
+
+        let account = &wallet.accounts()[0];
+        let confirmed = Balance {
+            spendable: 300,
+            ..Balance::default()
+        };
+        assert_eq!(account.balance(), confirmed);
+        assert_eq!(account.utxos().len(), 1);
+        assert_eq!(
+            account.transaction_history()[0].status,
+            TransactionStatus::Mined { height: 1 }
+        );
+        node.shutdown().await
+    }
+
+    #[tokio::test]
+    async fn it_restores_wallet_accounts_from_the_chain() {
+        let mut node = build_blockchain().await;
+        let uri = node.local_addr().to_string();
+        let mut wallet = Wallet::new("restored".to_string(), uri.clone()).await;
+        let mnemonic = wallet.take_mnemonic().unwrap();
+        let first = wallet.create_new_account("first").unwrap().address().clone();
+        wallet.create_new_account("second").unwrap();
+        wallet.create_new_account("unused").unwrap();
+        let second = wallet.accounts[1].next_receive_address().unwrap();
+
+        // Paying the second address of the second account, the first one stays unused
+        let account = node.wallet.accounts()[0].name().clone();
+        for address in [&first, &second] {
+            let payment = node.wallet.create_payment(&account, address, 300, FeeRate(50)).unwrap();
+            node.submit_transaction(payment).unwrap();
+            node.add_block().await;
+        }
+
+        let restore = Wallet::restore("restored".to_string(), uri, &mnemonic, "");
+        let restored = tokio::select! {
+            _ = node.run() => panic!("Node stopped running"),
+            restored = tokio::time::timeout(Duration::from_secs(10), restore) => {
+                restored.unwrap().unwrap()
+            }
+        };
+
+        // Accounts after the last used one are not found again
+        assert_eq!(restored.accounts().len(), 2);
+        for (restored, account) in restored.accounts().iter().zip(wallet.accounts()) {
+            assert_eq!(restored.index(), account.index());
+            assert_eq!(restored.extended_public_key(), account.extended_public_key());
+            for chain in [Chain::External, Chain::Internal] {
+                assert_eq!(restored.addresses(chain), account.addresses(chain));
+            }
+        }
+        node.shutdown().await
+    }
+
+    #[tokio::test]
+    async fn it_records_payments_once_the_node_accepts_them() {
+        let mut node = build_blockchain().await;
+        let mut wallet = Wallet::new("payer".to_string(), node.local_addr().to_string()).await;
+        let address = wallet.create_new_account("main").unwrap().address().clone();
+        let account = node.wallet.accounts()[0].name().clone();
+        let payment = node.wallet.create_payment(&account, &address, 500, FeeRate(50)).unwrap();
+        node.submit_transaction(payment).unwrap();
+        node.add_block().await;
+
+        let paid = async {
+            wallet.sync().await.unwrap();
+
+            // Below the minimum relay fee rate, the node refuses the payment
+            let refused = wallet.initiate_payment("main", "bob", 100, FeeRate::ZERO).await;
+            assert!(refused.unwrap_err().to_string().contains("minimum relay fee"));
+            assert_eq!(wallet.accounts()[0].transaction_history().len(), 1);
+            assert_eq!(wallet.accounts()[0].spendable_utxos().len(), 1);
+
+            wallet.initiate_payment("main", "bob", 100, FeeRate(50)).await.unwrap();
+        };
+        tokio::select! {
+            _ = node.run() => panic!("Node stopped running"),
+            paid = tokio::time::timeout(Duration::from_secs(10), paid) => paid.unwrap(),
+        }
+
+        let account = &wallet.accounts()[0];
+        assert_eq!(account.transaction_history().len(), 2);
+        assert!(account.spendable_utxos().is_empty());
+        assert_eq!(node.mempool().len(), 1);
+        node.shutdown().await
+    }
+
+    /// Block on top of `parent` paying the block reward to a miner outside the node
+    fn side_block(node: &Blockchain, parent: &Block, nonce: u64) -> Block {
+        side_block_with(node, parent, nonce, BLOCKCHAIN_COINBASE_BLOCK_FEE, &[])
+    }
+
+    /// Block on top of `parent` paying `reward` to a miner outside the node, with `transactions`
+    fn side_block_with(
+        node: &Blockchain,
+        parent: &Block,
+        nonce: u64,
+        reward: u64,
+        transactions: &[Transaction],
+    ) -> Block {
+        let (_, public_key) = keys();
+        let coinbase = TransactionManager::create_coinbase_transaction(
+            &public_key,
+            "side-miner",
+            reward,
+            nonce,
+        );
+        let transactions: Vec<Transaction> =
+            std::iter::once(coinbase).chain(transactions.iter().cloned()).collect();
+        Block::new(
+            parent.header().current_hash(),
+            &transactions,
+            node.config().difficulty.initial_bits,
+        ).unwrap()
+    }
+
+    #[tokio::test]
+    async fn it_reorganizes_to_the_branch_with_most_work() {
+        let data_dir = temp_data_dir();
+        let config = test_config().with_data_dir(&data_dir);
+        let mut node = Blockchain::build(config.clone()).await.unwrap();
+        let mut events = node.subscribe_events();
+        let genesis = node.blocks[0].clone();
+
+        let transaction = spend_genesis_coinbase(&node, 2);
+        let hash = transaction.metadata().transaction_hash;
+        node.submit_transaction(transaction).unwrap();
+        node.add_block().await;
+        assert!(node.mempool().is_empty());
+
+        // Competing branch from genesis, without the transaction
+        let side_1 = side_block(&node, &genesis, 1);
+        let acceptance = node.submit_block(side_1.clone()).await.unwrap();
+        assert_eq!(acceptance, BlockAcceptance::SideBranch);
+        assert_eq!(node.blocks.len(), 2);
+
+        let side_2 = side_block(&node, &side_1, 2);
+        let acceptance = node.submit_block(side_2.clone()).await.unwrap();
+        assert_eq!(
+            acceptance,
+            BlockAcceptance::Reorganized {
+                disconnected: 1,
+                connected: 2
+            }
+        );
+        let tip = side_2.header().current_hash().clone();
+        assert_eq!(node.blocks().last().unwrap().header().current_hash(), &tip);
+        assert_eq!(node.block_tree().len(), 4);
+        assert!(node.validate_full_chain().is_ok());
+
+        // The transaction of the disconnected block is pending again, its input unspent
+        assert!(node.mempool().contains(&hash));
+        let address = node.wallet.accounts()[0].address().clone();
+        assert_eq!(node.balance(&address), BLOCKCHAIN_COINBASE_GENESIS_BLOCK_FEE);
+
+        let topics: Vec<EventTopic> = std::iter::from_fn(|| events.try_recv().ok())
+            .map(|event| event.topic)
+            .collect();
+        assert!(topics.contains(&EventTopic::ChainReorg));
+
+        assert!(matches!(
+            node.submit_block(side_2).await,
+            Err(SubmitBlockError::Invalid(BlockValidationError::AlreadyKnown))
+        ));
+        node.shutdown().await;
+        drop(node);
+
+        // Side branches are stored too, the reloaded node picks the same tip
+        let reloaded = Blockchain::build(config).await.unwrap();
+        assert_eq!(reloaded.blocks.len(), 3);
+        assert_eq!(reloaded.blocks().last().unwrap().header().current_hash(), &tip);
+        assert_eq!(reloaded.block_tree().len(), 4);
+
+        std::fs::remove_dir_all(data_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn it_returns_chained_transactions_of_disconnected_blocks() {
+        let mut node = build_blockchain().await;
+        let genesis = node.blocks[0].clone();
+        let parent = spend_genesis_coinbase(&node, 2);
+        let child = spend_first_output(&node, &parent, 2);
+        let child_hash = child.metadata().transaction_hash;
+        let block = side_block_with(&node, &genesis, 0, BLOCKCHAIN_COINBASE_BLOCK_FEE, &[
+            parent.clone(),
+            child,
+        ]);
+        assert_eq!(node.submit_block(block).await.unwrap(), BlockAcceptance::Extended);
+
+        let side_1 = side_block(&node, &genesis, 1);
+        node.submit_block(side_1.clone()).await.unwrap();
+        let side_2 = side_block(&node, &side_1, 2);
+        node.submit_block(side_2).await.unwrap();
+
+        // The parent is pending again, the child waits for it to be mined
+        assert!(node.mempool().contains(&parent.metadata().transaction_hash));
+        assert!(!node.mempool().contains(&child_hash));
+        node.add_block().await;
+        assert!(node.mempool().contains(&child_hash));
+        node.add_block().await;
+        let status = node.transaction_status(&child_hash);
+        assert_eq!(status, Some(TransactionStatus::Mined { height: 4 }));
+        node.shutdown().await
+    }
+
+    #[tokio::test]
+    async fn it_keeps_the_chain_and_mempool_when_a_branch_block_is_invalid() {
+        let mut node = build_blockchain().await;
+        let genesis = node.blocks[0].clone();
+        node.add_block().await;
+        let tip = node.blocks[1].header().current_hash().clone();
+
+        let transaction = spend_genesis_coinbase(&node, 2);
+        let hash = transaction.metadata().transaction_hash;
+        node.submit_transaction(transaction.clone()).unwrap();
+
+        // The first branch block mines the pending transaction, the second claims too much
+        let side_1 =
+            side_block_with(&node, &genesis, 1, BLOCKCHAIN_COINBASE_BLOCK_FEE, &[transaction]);
+        let acceptance = node.submit_block(side_1.clone()).await.unwrap();
+        assert_eq!(acceptance, BlockAcceptance::SideBranch);
+        let greedy = side_block_with(&node, &side_1, 2, BLOCKCHAIN_COINBASE_BLOCK_FEE + 1, &[]);
+        assert!(matches!(
+            node.submit_block(greedy).await,
+            Err(SubmitBlockError::Invalid(BlockValidationError::CoinbaseValueTooHigh { .. }))
+        ));
+
+        assert_eq!(node.blocks.len(), 2);
+        assert_eq!(node.blocks[1].header().current_hash(), &tip);
+        assert!(node.mempool().contains(&hash));
+        assert_eq!(node.transaction_status(&hash), Some(TransactionStatus::Pending));
+        assert!(node.validate_full_chain().is_ok());
+        node.shutdown().await
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn it_leaves_the_node_unchanged_when_a_block_cannot_be_stored() {
+        let mut node = build_blockchain().await;
+        let genesis = node.blocks[0].clone();
+        let transaction = spend_genesis_coinbase(&node, 2);
+        let hash = transaction.metadata().transaction_hash;
+        node.submit_transaction(transaction.clone()).unwrap();
+        let block =
+            side_block_with(&node, &genesis, 1, BLOCKCHAIN_COINBASE_BLOCK_FEE, &[transaction]);
+
+        // Writing the block file fails: /dev/full has no space left
+        let full_dir = temp_data_dir();
+        std::fs::create_dir_all(&full_dir).unwrap();
+        std::os::unix::fs::symlink("/dev/full", full_dir.join("blocks.dat")).unwrap();
+        let full = BlockStorage::open(&full_dir).unwrap();
+        let storage = std::mem::replace(&mut *node.storage.lock().await, full);
+
+        assert!(matches!(
+            node.submit_block(block.clone()).await,
+            Err(SubmitBlockError::Storage(_))
+        ));
+        assert_eq!(node.blocks.len(), 1);
+        assert!(!node.tree.contains(block.header().current_hash()));
+        assert_eq!(node.transaction_status(&hash), Some(TransactionStatus::Pending));
+
+        // Once the storage works again, the same block is accepted
+        *node.storage.lock().await = storage;
+        assert_eq!(node.submit_block(block).await.unwrap(), BlockAcceptance::Extended);
+        assert_eq!(node.transaction_status(&hash), Some(TransactionStatus::Mined { height: 1 }));
+        node.shutdown().await;
+        std::fs::remove_dir_all(full_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn it_mines_in_the_background_when_the_policy_is_met() {
+        let mut config = test_config();
+        config.miner.policy = crate::miner::MiningPolicy::MempoolNotEmpty;
+        let mut node = Blockchain::build(config).await.unwrap();
+        let control = node.mining_control();
+        let mut events = node.subscribe_events();
+
+        let transaction = spend_genesis_coinbase(&node, 1);
+        node.submit_transaction(transaction).unwrap();
+        control.start();
+
+        let new_block = async {
+            loop {
+                let event = events.recv().await.unwrap();
+                if event.topic == EventTopic::NewBlock {
+                    break event;
+                }
+            }
+        };
+        let event = tokio::select! {
+            _ = node.run() => panic!("Node stopped running"),
+            event = tokio::time::timeout(Duration::from_secs(10), new_block) => event.unwrap(),
+        };
+
+        assert_eq!(event.data["height"], 1);
+        assert_eq!(event.data["transactions"], 2);
+        assert_eq!(node.blocks.len(), 2);
+        assert!(node.mempool().is_empty());
+
+        // Nothing pending anymore, the policy is no longer met
+        assert!(!node.config().miner.policy.should_mine(&node.mining_state()));
+        control.stop();
+        assert!(!node.mining_control().is_enabled());
+    }
+
+    /// Sends a request to the node as the listener would, returning its answer
+    async fn request(
+        node: &mut Blockchain,
+        r#type: RequestType,
+        payload: Value,
+    ) -> Result<Value, String> {
+        let (respond_to, response) = tokio::sync::oneshot::channel();
+        node.handle_request(NodeRequest {
+            id: "1".to_string(),
+            r#type,
+            payload,
+            respond_to,
+        })
+        .await;
+        response.await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn it_accepts_blocks_from_external_miners() {
+        let mut node = build_blockchain().await;
+        let transaction = spend_genesis_coinbase(&node, 4);
+        node.submit_transaction(transaction.clone()).unwrap();
+
+        let template = request(&mut node, RequestType::GetBlockTemplate, Value::Null)
+            .await
+            .unwrap();
+        let template: BlockTemplateResponse = serde_json::from_value(template).unwrap();
+        assert_eq!(template.height, 1);
+        assert_eq!(template.coinbase_value, BLOCKCHAIN_COINBASE_BLOCK_FEE + 4);
+        assert_eq!(template.transactions.len(), 1);
+
+        // The external miner pays the whole coinbase value to itself
+        let (_, public_key) = keys();
+        let mut transactions = vec![TransactionManager::create_coinbase_transaction(
+            &public_key,
+            "external-miner",
+            template.coinbase_value,
+            0,
+        )];
+        transactions.extend(template.transactions);
+        let block = Block::new(&template.previous_hash, &transactions, template.bits).unwrap();
+
+        let answer = request(
+            &mut node,
+            RequestType::SubmitBlock,
+            serde_json::to_value(&block).unwrap(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(answer["acceptance"], "extended");
+        assert_eq!(
+            node.blocks().last().unwrap().header().current_hash(),
+            block.header().current_hash()
+        );
+        assert_eq!(
+            node.balance("external-miner"),
+            BLOCKCHAIN_COINBASE_BLOCK_FEE + 4
+        );
+        assert!(node.mempool().is_empty());
+
+        // Known blocks and blocks claiming too much are refused
+        let answer = request(
+            &mut node,
+            RequestType::SubmitBlock,
+            serde_json::to_value(&block).unwrap(),
+        )
+        .await;
+        assert!(answer.is_err());
+
+        let template = node.get_block_template().unwrap();
+        let greedy = TransactionManager::create_coinbase_transaction(
+            &public_key,
+            "external-miner",
+            template.coinbase_value + 1,
+            0,
+        );
+        let block = Block::new(&template.previous_hash, &[greedy], template.bits).unwrap();
+        let answer = request(
+            &mut node,
+            RequestType::SubmitBlock,
+            serde_json::to_value(&block).unwrap(),
+        )
+        .await;
+        assert!(answer.unwrap_err().contains("exceeds block subsidy"));
+        assert_eq!(node.blocks.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn it_stores_only_blocks_with_a_valid_body() {
+        let data_dir = temp_data_dir();
+        let config = test_config().with_data_dir(&data_dir);
+        let mut node = Blockchain::build(config.clone()).await.unwrap();
+
+        // Valid header, but the coinbase claims more than the subsidy
+        let template = node.get_block_template().unwrap();
+        let account = node.wallet.accounts()[0].clone();
+        let public_key = account.public_key_of(account.address()).unwrap();
+        let greedy = TransactionManager::create_coinbase_transaction(
+            &public_key,
+            account.address(),
+            template.coinbase_value + 1,
+            0,
+        );
+        let block = Block::new(&template.previous_hash, &[greedy], template.bits).unwrap();
+        assert!(matches!(
+            node.submit_block(block).await,
+            Err(SubmitBlockError::Invalid(BlockValidationError::CoinbaseValueTooHigh { .. }))
+        ));
+        node.shutdown().await;
+        drop(node);
+
+        let mut storage = BlockStorage::open(&data_dir).unwrap();
+        assert_eq!(storage.load_blocks().unwrap().len(), 1);
+        let reloaded = Blockchain::build(config).await.unwrap();
+        assert_eq!(reloaded.blocks.len(), 1);
+        assert_eq!(reloaded.block_tree().len(), 1);
+
+        std::fs::remove_dir_all(data_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn it_answers_supply_requests() {
+        let mut config = test_config();
+        config.consensus.halving_interval = 1;
+        let mut node = Blockchain::build(config).await.unwrap();
+        node.add_block().await;
+
+        let tip = request(&mut node, RequestType::GetSupply, Value::Null)
+            .await
+            .unwrap();
+        assert_eq!(tip["height"], 1);
+        assert_eq!(tip["issued"], BLOCKCHAIN_COINBASE_GENESIS_BLOCK_FEE + 20);
+
+        let later = request(&mut node, RequestType::GetSupply, json!({ "height": 3 }))
+            .await
+            .unwrap();
+        assert_eq!(later["block_subsidy"], 5);
+        assert_eq!(later["issued"], BLOCKCHAIN_COINBASE_GENESIS_BLOCK_FEE + 20 + 10 + 5);
+
+        node.shutdown().await
+    }
+
+    #[tokio::test]
+    async fn it_mines_blocks_once_issuance_stopped() {
+        let mut config = test_config();
+        config.consensus.max_supply = BLOCKCHAIN_COINBASE_GENESIS_BLOCK_FEE + 20;
+        let mut node = Blockchain::build(config).await.unwrap();
+        node.add_block().await;
+        assert_eq!(node.config.consensus.block_subsidy(2), 0);
+
+        // Nothing left to issue and no fees to collect, the coinbase claims nothing
+        let template = node.get_block_template().unwrap();
+        assert_eq!(template.coinbase_value, 0);
+        node.add_block().await;
+
+        assert_eq!(node.blocks.len(), 3);
+        let coinbase = &node.blocks[2].body().transactions()[0];
+        assert_eq!(coinbase.output_amount(), 0);
+        assert!(node.validate_full_chain().is_ok());
+
+        node.shutdown().await
+    }
+
+    #[tokio::test]
+    async fn it_prices_transactions_by_size() {
+        let mut node = build_blockchain().await;
+
+        let estimate = request(&mut node, RequestType::EstimateFee, json!({ "target_blocks": 2 }))
+            .await
+            .unwrap();
+        let min_relay_fee_rate = node.mempool().config().min_relay_fee_rate;
+        assert_eq!(estimate["fee_rate"], json!(min_relay_fee_rate));
+
+        let free = spend_genesis_coinbase(&node, 0);
+        assert!(matches!(
+            node.submit_transaction(free),
+            Err(MempoolError::FeeBelowMinimum { fee: 0, .. })
+        ));
+        let transaction = spend_genesis_coinbase(&node, 1);
+        assert_eq!(min_relay_fee_rate.fee_for(transaction.size()), 1);
+        assert!(node.submit_transaction(transaction).is_ok());
+
+        node.shutdown().await
+    }
+
+    #[tokio::test]
+    async fn it_answers_mempool_requests() {
+        let mut node = build_blockchain().await;
+
+        let transaction = spend_genesis_coinbase(&node, 1);
+        let hash = HashHelper::to_hex(&transaction.metadata().transaction_hash);
+
+        let (respond_to, response) = tokio::sync::oneshot::channel();
+        node.handle_request(NodeRequest {
+            id: "1".to_string(),
+            r#type: RequestType::SubmitTransaction,
+            payload: serde_json::to_value(&transaction).unwrap(),
+            respond_to,
+        })
+        .await;
+        let data = response.await.unwrap().unwrap();
+        assert_eq!(data["transaction_hash"], Value::String(hash));
+        assert_eq!(node.mempool().len(), 1);
+
+        let (respond_to, response) = tokio::sync::oneshot::channel();
+        node.handle_request(NodeRequest {
+            id: "2".to_string(),
+            r#type: RequestType::SubmitTransaction,
+            payload: serde_json::to_value(&transaction).unwrap(),
+            respond_to,
+        })
+        .await;
+        assert!(response.await.unwrap().is_err());
+
+        node.shutdown().await
+    }
+
+    #[tokio::test]
+    async fn it_mines_mempool_transactions_into_blocks() {
+        let mut node = build_blockchain().await;
+
+        let transaction = spend_genesis_coinbase(&node, 3);
+        let hash = node.submit_transaction(transaction.clone()).unwrap();
+        assert_eq!(node.transaction_status(&hash), Some(TransactionStatus::Pending));
+
+        node.add_block().await;
+        assert_eq!(node.transaction_status(&hash), Some(TransactionStatus::Mined { height: 1 }));
+        assert_eq!(node.transaction_status(&[7u8; 32]), None);
+
+        let block = node.blocks().last().unwrap().clone();
+        let transactions = block.body().transactions();
+        assert_eq!(transactions.len(), 2);
+        assert_eq!(
+            transactions[1].metadata().transaction_hash,
+            transaction.metadata().transaction_hash
+        );
+        // Block reward + 3 fee
+        assert_eq!(
+            transactions[0].outputs()[0].amount,
+            BLOCKCHAIN_COINBASE_BLOCK_FEE + 3
+        );
+        assert!(node.mempool().is_empty());
+
+        // Light clients can check inclusion against the header merkle root only
+        let proof = block
+            .merkle_proof(&transaction.metadata().transaction_hash)
+            .unwrap();
+        let root = HashHelper::hash_from_hex(block.header().merkle_root()).unwrap();
+        assert!(proof.verify(&root));
+
+        // Genesis output was spent back to the node, which also earned the block reward and fee
+        let address = node.wallet.accounts()[0].address().clone();
+        assert_eq!(
+            node.balance(&address),
+            BLOCKCHAIN_COINBASE_GENESIS_BLOCK_FEE + BLOCKCHAIN_COINBASE_BLOCK_FEE
+        );
+        assert_eq!(node.utxo().outputs_for_address(&address).len(), 2);
+
+        // The genesis coinbase output is now spent
+        let double_spend = spend_genesis_coinbase(&node, 5);
+        assert!(matches!(
+            node.submit_transaction(double_spend),
+            Err(MempoolError::Invalid(_))
+        ));
+
+        node.shutdown().await
+    }
 }
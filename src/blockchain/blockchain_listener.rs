@@ -7,8 +7,9 @@ use crate::{
 use anyhow::{Error, Result};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use tokio::sync::Mutex;
-use tracing::{error, info};
+use tokio::net::TcpListener;
+use tokio::sync::{broadcast, mpsc, oneshot, Mutex};
+use tracing::{error, info, warn};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub enum BlockchainWebsocketMessage {
@@ -19,10 +20,27 @@ pub enum BlockchainWebsocketMessage {
     Other,
 }
 
+/// Request received from a client, routed to the blockchain node for processing.
+/// The node answers through `respond_to`, and the answer is sent back to the client.
+#[derive(Debug)]
+pub struct NodeRequest {
+    pub id: String,
+    pub r#type: comms::RequestType,
+    pub payload: Value,
+    pub respond_to: oneshot::Sender<Result<Value, String>>,
+}
+
 #[derive(Debug, Clone)]
 pub struct BlockchainListener {
     server: WebSocketServer,
     subscription_manager: Arc<SubscriptionManager>,
+    requests: Option<mpsc::UnboundedSender<NodeRequest>>,
+}
+
+impl Default for BlockchainListener {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl BlockchainListener {
@@ -30,14 +48,27 @@ impl BlockchainListener {
         Self {
             server: WebSocketServer::new(),
             subscription_manager: Arc::new(SubscriptionManager::new()),
+            requests: None,
         }
     }
 
+    /// Routes client requests to `requests`, where the blockchain node processes them
+    pub fn with_requests(mut self, requests: mpsc::UnboundedSender<NodeRequest>) -> Self {
+        self.requests = Some(requests);
+        self
+    }
+
     pub async fn run(&self, addr: &str) {
+        let listener = TcpListener::bind(addr).await.unwrap();
+        self.serve(listener).await;
+    }
+
+    /// Serves wallet connections on an already bound TCP listener
+    pub async fn serve(&self, listener: TcpListener) {
         let self_ref = Arc::new(Mutex::new(self.clone()));
 
         self.server
-            .run(addr, move |message, client_id, _clients| {
+            .serve(listener, move |message, client_id, _clients| {
                 let self_ref = Arc::clone(&self_ref);
                 Box::pin(async move {
                     info!("Message from client {}: {}", client_id, message);
@@ -49,7 +80,7 @@ impl BlockchainListener {
                         }
                     };
                     // Add custom blockchain-related message handling logic here
-                    match &raw_message {
+                    match raw_message {
                         comms::Message::Request {
                             id,
                             r#type,
@@ -59,6 +90,8 @@ impl BlockchainListener {
                                 "Received request: id={}, type={:?}, payload={:?}",
                                 id, r#type, payload
                             );
+                            let listener = self_ref.lock().await.clone();
+                            listener.route_request(client_id, id, r#type, payload).await;
                         }
                         comms::Message::Response {
                             id,
@@ -80,7 +113,7 @@ impl BlockchainListener {
 
                             self_locked
                                 .subscription_manager
-                                .subscribe(client_id.clone(), topic.clone())
+                                .subscribe(client_id, topic)
                                 .await;
                         }
                     }
@@ -89,11 +122,11 @@ impl BlockchainListener {
             .await;
     }
 
-    pub async fn send<T>(&self, client_id: usize, message: comms::Message<T>) -> Result<(), Error>
+    pub async fn send<T>(&self, client_id: usize, message: &comms::Message<T>) -> Result<(), Error>
     where
         T: serde::Serialize,
     {
-        let serialized_message = serde_json::to_string(&message)?;
+        let serialized_message = serde_json::to_string(message)?;
         self.server.send(client_id, serialized_message).await;
         Ok(())
     }
@@ -102,4 +135,79 @@ impl BlockchainListener {
         self.server.broadcast(message).await;
     }
 
+    /// Sends an event to every client subscribed to its topic
+    pub async fn publish(&self, event: comms::NodeEvent) {
+        let message = comms::Message::Event {
+            id: uuid::Uuid::new_v4().to_string(),
+            topic: event.topic.clone(),
+            data: event.data,
+        };
+
+        for client_id in self.subscription_manager.get_subscribers(&event.topic).await {
+            if let Err(e) = self.send(client_id, &message).await {
+                error!("Failed to publish event to client {}: {}", client_id, e);
+            }
+        }
+    }
+
+    /// Publishes node events to subscribed clients until the event channel is closed
+    pub async fn forward_events(&self, mut events: broadcast::Receiver<comms::NodeEvent>) {
+        loop {
+            match events.recv().await {
+                Ok(event) => self.publish(event).await,
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!("Event forwarder lagged behind, skipped {} events", skipped);
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    }
+
+    /// Hands a request to the blockchain node and sends its answer back to the client
+    async fn route_request(
+        &self,
+        client_id: usize,
+        id: String,
+        r#type: comms::RequestType,
+        payload: Value,
+    ) {
+        let Some(requests) = &self.requests else {
+            warn!("No blockchain node attached, dropping request {}", id);
+            return;
+        };
+
+        let (respond_to, response) = oneshot::channel();
+        let request = NodeRequest {
+            id: id.clone(),
+            r#type,
+            payload,
+            respond_to,
+        };
+
+        let result = match requests.send(request) {
+            Ok(()) => response
+                .await
+                .unwrap_or_else(|_| Err("Blockchain node dropped the request".to_string())),
+            Err(_) => Err("Blockchain node is not running".to_string()),
+        };
+
+        let message = match result {
+            Ok(data) => comms::Message::Response {
+                id,
+                status: "ok".to_string(),
+                data: Some(data),
+                error: None,
+            },
+            Err(error) => comms::Message::Response {
+                id,
+                status: "error".to_string(),
+                data: None,
+                error: Some(error),
+            },
+        };
+
+        if let Err(e) = self.send(client_id, &message).await {
+            error!("Failed to send response to client {}: {}", client_id, e);
+        }
+    }
 }
@@ -0,0 +1,236 @@
+//! # Chain Validator
+//!
+//! Validates blocks of a chain against their parents, found through a hash → index map,
+//! so validating a whole chain runs in linear time.
+//!
+//! Failures carry the height and hash of the offending block. By default validation stops
+//! at the first failure; with [`ChainValidator::collect_all`] every block is checked and
+//! all failures are reported.
+
+use std::collections::HashMap;
+
+use thiserror::Error;
+
+use super::{
+    consensus, consensus::ConsensusParams, next_bits, Block, BlockValidationError,
+    DifficultyConfig, UtxoSet,
+};
+use crate::transaction::UtxoView;
+
+/// A block that failed validation
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("Block {height} ({hash}) is invalid: {error}")]
+pub struct ChainValidationFailure {
+    pub height: u64,
+    pub hash: String,
+    pub error: BlockValidationError,
+}
+
+/// Validator over a chain of blocks, where a block index is its height
+#[derive(Debug)]
+pub struct ChainValidator<'a> {
+    blocks: &'a [Block],
+    hash_to_index: HashMap<&'a str, usize>,
+    difficulty: DifficultyConfig,
+    params: ConsensusParams,
+    collect_all: bool,
+}
+
+impl<'a> ChainValidator<'a> {
+    pub fn new(blocks: &'a [Block], difficulty: DifficultyConfig, params: ConsensusParams) -> Self {
+        let mut hash_to_index = HashMap::with_capacity(blocks.len());
+        for (i, block) in blocks.iter().enumerate() {
+            hash_to_index.entry(block.header().current_hash().as_str()).or_insert(i);
+        }
+
+        Self {
+            blocks,
+            hash_to_index,
+            difficulty,
+            params,
+            collect_all: false,
+        }
+    }
+
+    /// Keeps validating after a failure, reporting every invalid block
+    pub fn collect_all(mut self, collect_all: bool) -> Self {
+        self.collect_all = collect_all;
+        self
+    }
+
+    /// Returns the index (height) of the block with `hash`
+    pub fn index_of(&self, hash: &str) -> Option<usize> {
+        self.hash_to_index.get(hash).copied()
+    }
+
+    /// Returns the parent of the block at `index`, `None` for the genesis block
+    pub fn parent_of(&self, index: usize) -> Result<Option<&'a Block>, BlockValidationError> {
+        let block = self
+            .blocks
+            .get(index)
+            .ok_or(BlockValidationError::BlockNotFound)?;
+
+        if index == 0 {
+            return Ok(None);
+        }
+
+        let parent_index = self
+            .index_of(block.header().previous_hash())
+            .ok_or(BlockValidationError::PreviousBlockNotFound)?;
+
+        // The parent has to be the block right below, or the chain forks within itself
+        if parent_index + 1 != index {
+            return Err(BlockValidationError::PreviousHashMismatch);
+        }
+
+        Ok(Some(&self.blocks[parent_index]))
+    }
+
+    /// Validates the block at `index` against its parent and `utxos`,
+    /// the UTXO set as of the parent block. Returns the fees paid in the block.
+    pub fn validate_block(
+        &self,
+        index: usize,
+        utxos: &impl UtxoView,
+    ) -> Result<u64, ChainValidationFailure> {
+        let fail = |error| ChainValidationFailure {
+            height: index as u64,
+            hash: self
+                .blocks
+                .get(index)
+                .map(|b| b.header().current_hash().clone())
+                .unwrap_or_default(),
+            error,
+        };
+
+        let block = self
+            .blocks
+            .get(index)
+            .ok_or_else(|| fail(BlockValidationError::BlockNotFound))?;
+        let parent = self.parent_of(index).map_err(fail)?;
+        let expected_bits = next_bits(&self.blocks[..index], &self.difficulty).map_err(fail)?;
+        let median_time_past = match index.checked_sub(1) {
+            Some(parent_index) => Some(
+                consensus::median_time_past(parent_index as u64, |height| {
+                    self.blocks.get(height as usize)
+                })
+                .map_err(fail)?,
+            ),
+            None => None,
+        };
+
+        consensus::validate_block(
+            block,
+            parent,
+            index as u64,
+            median_time_past,
+            expected_bits,
+            &self.params,
+            utxos,
+        )
+        .map_err(fail)
+    }
+
+    /// Validates blocks `from_index..=to_index` in order, starting from `utxos`,
+    /// the UTXO set as of the parent of the first block.
+    ///
+    /// Returns the first failure, or every failure in `collect_all` mode.
+    pub fn validate_range(
+        &self,
+        from_index: usize,
+        to_index: usize,
+        mut utxos: UtxoSet,
+    ) -> Result<(), Vec<ChainValidationFailure>> {
+        let mut failures = Vec::new();
+
+        for index in from_index..=to_index {
+            if let Err(failure) = self.validate_block(index, &utxos) {
+                failures.push(failure);
+                if !self.collect_all {
+                    break;
+                }
+            }
+
+            // Keep going with the block outputs where possible, so later blocks
+            // are not all reported because of a single invalid one
+            if let Some(block) = self.blocks.get(index) {
+                let _ = utxos.connect_block(block, index as u64);
+            }
+        }
+
+        if failures.is_empty() {
+            Ok(())
+        } else {
+            Err(failures)
+        }
+    }
+
+    /// Validates the whole chain, starting from an empty UTXO set
+    pub fn validate_chain(&self) -> Result<(), Vec<ChainValidationFailure>> {
+        if self.blocks.is_empty() {
+            return Ok(());
+        }
+        self.validate_range(0, self.blocks.len() - 1, UtxoSet::new())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::test_utils::coinbase;
+
+    const BITS: u32 = 0x200f_ffff;
+
+    fn difficulty() -> DifficultyConfig {
+        DifficultyConfig {
+            initial_bits: BITS,
+            ..DifficultyConfig::default()
+        }
+    }
+
+    fn chain(length: usize) -> Vec<Block> {
+        let mut blocks = vec![Block::new(
+            &"0".repeat(64),
+            &[coinbase(ConsensusParams::default().block_subsidy(0))],
+            BITS,
+        ).unwrap()];
+        for height in 1..length {
+            let parent = blocks.last().unwrap().header().current_hash().clone();
+            let subsidy = ConsensusParams::default().block_subsidy(height as u64);
+            blocks.push(Block::new(&parent, &[coinbase(subsidy)], BITS).unwrap());
+        }
+        blocks
+    }
+
+    #[test]
+    fn it_validates_a_chain() {
+        let blocks = chain(4);
+        let validator = ChainValidator::new(&blocks, difficulty(), ConsensusParams::default());
+
+        assert!(validator.validate_chain().is_ok());
+        assert_eq!(validator.index_of(blocks[2].header().current_hash()), Some(2));
+    }
+
+    #[test]
+    fn it_reports_first_or_all_failures_with_height_and_hash() {
+        let mut blocks = chain(5);
+        blocks[1].header.nonce += 1;
+        let greedy = coinbase(ConsensusParams::default().block_subsidy(3) + 1);
+        blocks[3].body.transactions = vec![greedy];
+
+        let first = ChainValidator::new(&blocks, difficulty(), ConsensusParams::default())
+            .validate_chain()
+            .unwrap_err();
+        assert_eq!(first.len(), 1);
+        assert_eq!(first[0].height, 1);
+        assert_eq!(&first[0].hash, blocks[1].header().current_hash());
+        assert_eq!(first[0].error, BlockValidationError::InvalidHash);
+
+        let all = ChainValidator::new(&blocks, difficulty(), ConsensusParams::default())
+            .collect_all(true)
+            .validate_chain()
+            .unwrap_err();
+        let heights: Vec<u64> = all.iter().map(|failure| failure.height).collect();
+        assert_eq!(heights, vec![1, 3]);
+    }
+}
@@ -0,0 +1,391 @@
+//! # Consensus Rules
+//!
+//! Rules a block must follow to be accepted on top of its parent:
+//! - the header hash is valid and meets the declared target
+//! - the header merkle root commits to the block transactions
+//! - the declared target is the one expected after the parent, see [`next_bits`](super::next_bits)
+//! - the block links to its parent, and its timestamp is later than the median time of the
+//!   previous [`BLOCKCHAIN_MEDIAN_TIME_SPAN`] blocks and at most
+//!   [`BLOCKCHAIN_MAX_FUTURE_BLOCK_TIME_SECS`] ahead of the node clock
+//! - exactly one coinbase is present, as the first transaction
+//! - the coinbase claims at most the block subsidy plus the fees of the block, where the
+//!   subsidy halves every [`ConsensusParams::halving_interval`] blocks and stops once
+//!   [`ConsensusParams::max_supply`] is issued
+//! - every other transaction is valid against the UTXO set as of the parent block,
+//!   and spends no coinbase output younger than [`ConsensusParams::coinbase_maturity`]
+
+use chrono::{DateTime, Duration, FixedOffset, Utc};
+
+use super::{
+    block_time, merkle::merkle_root, Block, Target, BlockValidationError, BLOCK_HEADER_VERSION,
+};
+use crate::{
+    config::{
+        BLOCKCHAIN_COINBASE_BLOCK_FEE, BLOCKCHAIN_COINBASE_GENESIS_BLOCK_FEE,
+        BLOCKCHAIN_COINBASE_MATURITY, BLOCKCHAIN_HALVING_INTERVAL,
+        BLOCKCHAIN_MAX_FUTURE_BLOCK_TIME_SECS, BLOCKCHAIN_MEDIAN_TIME_SPAN,
+        BLOCKCHAIN_OXCOIN_SUPPLY,
+    },
+    transaction::{validate_block_transactions, SpendContext, UtxoView},
+    utils::HashHelper,
+};
+
+/// Consensus rules that can be tuned per network
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConsensusParams {
+    pub coinbase_maturity: u64, // Blocks a coinbase output must be buried under before it is spent
+    pub genesis_subsidy: u64,   // Coins issued by the genesis block
+    pub initial_subsidy: u64,   // Coins issued by every other block, until the first halving
+    pub halving_interval: u64,  // Blocks after which the subsidy halves, at least 1
+    pub max_supply: u64,        // Total coins ever issued
+}
+
+impl Default for ConsensusParams {
+    fn default() -> Self {
+        Self {
+            coinbase_maturity: BLOCKCHAIN_COINBASE_MATURITY,
+            genesis_subsidy: BLOCKCHAIN_COINBASE_GENESIS_BLOCK_FEE,
+            initial_subsidy: BLOCKCHAIN_COINBASE_BLOCK_FEE,
+            halving_interval: BLOCKCHAIN_HALVING_INTERVAL,
+            max_supply: BLOCKCHAIN_OXCOIN_SUPPLY,
+        }
+    }
+}
+
+impl ConsensusParams {
+    /// Context in which the transactions of a block at `height` are validated
+    pub fn spend_context(&self, height: u64) -> SpendContext {
+        SpendContext {
+            height,
+            coinbase_maturity: self.coinbase_maturity,
+        }
+    }
+
+    /// Newly issued coins a block at `height` may claim in its coinbase, on top of the fees
+    pub fn block_subsidy(&self, height: u64) -> u64 {
+        match height.checked_sub(1) {
+            Some(parent) => self.issued_supply(height) - self.issued_supply(parent),
+            None => self.issued_supply(0),
+        }
+    }
+
+    /// Coins issued by the blocks up to `height` included, when every block claims its
+    /// full subsidy. Never exceeds `max_supply`.
+    pub fn issued_supply(&self, height: u64) -> u64 {
+        let max_supply = u128::from(self.max_supply);
+        let interval = u128::from(self.halving_interval.max(1));
+
+        let mut issued = u128::from(self.genesis_subsidy);
+        let mut remaining = u128::from(height); // Blocks after the genesis block
+        let mut subsidy = self.initial_subsidy;
+        while remaining > 0 && subsidy > 0 && issued < max_supply {
+            let blocks = remaining.min(interval);
+            issued += blocks * u128::from(subsidy);
+            remaining -= blocks;
+            subsidy >>= 1;
+        }
+
+        issued.min(max_supply) as u64
+    }
+}
+
+
+/// Validates `block` at `height` against its `parent` (`None` for the genesis block)
+/// and `utxos`, the UTXO set as of the parent block, following `params`.
+/// `median_time_past` is the [`median_time_past`] of the parent.
+/// Returns the total fees paid by the block transactions.
+pub fn validate_block(
+    block: &Block,
+    parent: Option<&Block>,
+    height: u64,
+    median_time_past: Option<DateTime<FixedOffset>>,
+    expected_bits: u32,
+    params: &ConsensusParams,
+    utxos: &impl UtxoView,
+) -> Result<u64, BlockValidationError> {
+    validate_header(block, parent, median_time_past, expected_bits)?;
+    validate_body(block, height, params, utxos)
+}
+
+/// Median timestamp of the last [`BLOCKCHAIN_MEDIAN_TIME_SPAN`] blocks up to the parent at
+/// `parent_height`, fewer near the genesis block. `ancestor` returns the block of the parent
+/// branch at a height.
+pub fn median_time_past<'a>(
+    parent_height: u64,
+    ancestor: impl Fn(u64) -> Option<&'a Block>,
+) -> Result<DateTime<FixedOffset>, BlockValidationError> {
+    let first = (parent_height + 1).saturating_sub(BLOCKCHAIN_MEDIAN_TIME_SPAN);
+    let mut times = (first..=parent_height)
+        .map(|height| {
+            ancestor(height)
+                .ok_or(BlockValidationError::PreviousBlockNotFound)
+                .and_then(block_time)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    times.sort();
+    Ok(times[times.len() / 2])
+}
+
+/// Checks the proof of work, target and timestamp of the block header, and its link to its
+/// parent. The timestamp must be later than `median_time_past`, the [`median_time_past`] of
+/// the parent, and not too far ahead of the node clock.
+pub fn validate_header(
+    block: &Block,
+    parent: Option<&Block>,
+    median_time_past: Option<DateTime<FixedOffset>>,
+    expected_bits: u32,
+) -> Result<(), BlockValidationError> {
+    let header = block.header();
+
+    if header.version() != BLOCK_HEADER_VERSION {
+        return Err(BlockValidationError::UnsupportedVersion(header.version()));
+    }
+
+    if !HashHelper::is_valid_hash(block) {
+        return Err(BlockValidationError::InvalidHash);
+    }
+
+    if header.bits() != expected_bits {
+        return Err(BlockValidationError::DifficultyMismatch {
+            expected: expected_bits,
+            found: header.bits(),
+        });
+    }
+
+    let target = Target::from_compact(header.bits())
+        .ok_or(BlockValidationError::InvalidBits(header.bits()))?;
+    if !target.is_met_by(header.current_hash()) {
+        return Err(BlockValidationError::InsufficientWork);
+    }
+
+    if let Some(parent) = parent {
+        if parent.header().current_hash() != header.previous_hash() {
+            return Err(BlockValidationError::PreviousHashMismatch);
+        }
+    }
+
+    let timestamp = block_time(block)?;
+    if median_time_past.is_some_and(|median| timestamp <= median) {
+        return Err(BlockValidationError::TimestampTooOld);
+    }
+    if timestamp > Utc::now() + Duration::seconds(BLOCKCHAIN_MAX_FUTURE_BLOCK_TIME_SECS) {
+        return Err(BlockValidationError::TimestampTooFarInFuture);
+    }
+
+    Ok(())
+}
+
+/// Checks the coinbase and transactions of the block at `height`
+/// against `utxos`, the UTXO set as of the parent block
+pub fn validate_body(
+    block: &Block,
+    height: u64,
+    params: &ConsensusParams,
+    utxos: &impl UtxoView,
+) -> Result<u64, BlockValidationError> {
+    let transactions = block.body().transactions();
+
+    if HashHelper::to_hex(&merkle_root(transactions)) != *block.header().merkle_root() {
+        return Err(BlockValidationError::MerkleRootMismatch);
+    }
+
+    let coinbase = match transactions.first() {
+        Some(transaction) if transaction.is_coinbase() => transaction,
+        _ => return Err(BlockValidationError::MissingCoinbase),
+    };
+
+    if let Some(index) = transactions.iter().skip(1).position(|t| t.is_coinbase()) {
+        return Err(BlockValidationError::MisplacedCoinbase(index + 1));
+    }
+
+    let fees = validate_block_transactions(transactions, utxos, params.spend_context(height))?;
+
+    let allowed = params.block_subsidy(height).saturating_add(fees);
+    let value = coinbase.output_amount();
+    if value > allowed {
+        return Err(BlockValidationError::CoinbaseValueTooHigh { value, allowed });
+    }
+
+    Ok(fees)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::UtxoSet;
+    use crate::transaction::test_utils::{coinbase, pay};
+    use crate::transaction::TransactionValidationError;
+
+    const BITS: u32 = 0x200f_ffff;
+    const PARAMS: ConsensusParams = ConsensusParams {
+        coinbase_maturity: 0,
+        genesis_subsidy: BLOCKCHAIN_COINBASE_GENESIS_BLOCK_FEE,
+        initial_subsidy: BLOCKCHAIN_COINBASE_BLOCK_FEE,
+        halving_interval: BLOCKCHAIN_HALVING_INTERVAL,
+        max_supply: BLOCKCHAIN_OXCOIN_SUPPLY,
+    };
+
+    /// Genesis block paying `block_subsidy(0)` to the miner, and the UTXO set after it
+    fn genesis() -> (Block, UtxoSet) {
+        let coinbase = coinbase(PARAMS.block_subsidy(0));
+        let genesis = Block::new(&"0".repeat(64), &[coinbase], BITS).unwrap();
+        let mut utxos = UtxoSet::new();
+        validate_block(&genesis, None, 0, None, BITS, &PARAMS, &utxos).unwrap();
+        utxos.connect_block(&genesis, 0).unwrap();
+        (genesis, utxos)
+    }
+
+    #[test]
+    fn it_accepts_coinbase_claiming_subsidy_and_fees() {
+        let (genesis, utxos) = genesis();
+        let payment = pay(&genesis.body().transactions()[0], "alice", PARAMS.block_subsidy(0) - 5);
+        let transactions = vec![coinbase(PARAMS.block_subsidy(1) + 5), payment];
+        let block = Block::new(genesis.header().current_hash(), &transactions, BITS).unwrap();
+
+        assert_eq!(
+            validate_block(&block, Some(&genesis), 1, None, BITS, &PARAMS, &utxos),
+            Ok(5)
+        );
+
+        let greedy = Block::new(
+            genesis.header().current_hash(),
+            &[coinbase(PARAMS.block_subsidy(1) + 6), transactions[1].clone()],
+            BITS,
+        ).unwrap();
+        assert_eq!(
+            validate_block(&greedy, Some(&genesis), 1, None, BITS, &PARAMS, &utxos),
+            Err(BlockValidationError::CoinbaseValueTooHigh {
+                value: PARAMS.block_subsidy(1) + 6,
+                allowed: PARAMS.block_subsidy(1) + 5,
+            })
+        );
+    }
+
+    #[test]
+    fn it_refuses_misplaced_coinbase_and_invalid_transactions() {
+        let (genesis, utxos) = genesis();
+        let parent_hash = genesis.header().current_hash();
+        let payment = pay(&genesis.body().transactions()[0], "alice", 10);
+
+        let no_coinbase = Block::new(parent_hash, std::slice::from_ref(&payment), BITS).unwrap();
+        assert_eq!(
+            validate_block(&no_coinbase, Some(&genesis), 1, None, BITS, &PARAMS, &utxos),
+            Err(BlockValidationError::MissingCoinbase)
+        );
+
+        let two_coinbases = Block::new(parent_hash, &[coinbase(1), coinbase(2)], BITS).unwrap();
+        assert_eq!(
+            validate_block(&two_coinbases, Some(&genesis), 1, None, BITS, &PARAMS, &utxos),
+            Err(BlockValidationError::MisplacedCoinbase(1))
+        );
+
+        // Spends the genesis coinbase twice
+        let double_spend = Block::new(
+            parent_hash,
+            &[coinbase(1), payment.clone(), pay(&genesis.body().transactions()[0], "alice", 20)],
+            BITS,
+        ).unwrap();
+        assert!(matches!(
+            validate_block(&double_spend, Some(&genesis), 1, None, BITS, &PARAMS, &utxos),
+            Err(BlockValidationError::InvalidTransaction(e))
+                if e.index == 2 && e.error == TransactionValidationError::MissingOutput(0)
+        ));
+    }
+
+    #[test]
+    fn it_checks_declared_bits() {
+        let (genesis, utxos) = genesis();
+        let block = Block::new(genesis.header().current_hash(), &[coinbase(1)], BITS).unwrap();
+
+        assert_eq!(
+            validate_block(&block, Some(&genesis), 1, None, BITS - 1, &PARAMS, &utxos),
+            Err(BlockValidationError::DifficultyMismatch {
+                expected: BITS - 1,
+                found: BITS,
+            })
+        );
+    }
+
+    /// Block on top of `previous_hash` with the given timestamp, mined at `BITS`
+    fn block_at(previous_hash: &str, timestamp: DateTime<FixedOffset>) -> Block {
+        let mut block = Block::candidate(previous_hash, &[coinbase(1)], BITS);
+        block.header.timestamp = timestamp.to_rfc3339();
+        let target = Target::from_compact(BITS).unwrap();
+        loop {
+            let hash = block.header().hash().unwrap();
+            if target.is_met_by(&hash) {
+                block.header.current_hash = hash;
+                return block;
+            }
+            block.header.nonce += 1;
+        }
+    }
+
+    #[test]
+    fn it_requires_timestamps_later_than_the_median_time_past() {
+        let start = DateTime::parse_from_rfc3339("2025-01-01T00:00:00+00:00").unwrap();
+        let minutes = |n: i64| start + Duration::minutes(n);
+
+        let mut chain = vec![block_at(&"0".repeat(64), minutes(0))];
+        assert_eq!(median_time_past(0, |height| chain.get(height as usize)), Ok(minutes(0)));
+        for n in 1..12 {
+            let parent_hash = chain.last().unwrap().header().current_hash().clone();
+            chain.push(block_at(&parent_hash, minutes(n)));
+        }
+
+        // The last 11 blocks are timestamped 1 to 11 minutes after the start
+        let median = median_time_past(11, |height| chain.get(height as usize)).unwrap();
+        assert_eq!(median, minutes(6));
+
+        let parent = chain.last().unwrap();
+        let at_median = block_at(parent.header().current_hash(), median);
+        assert_eq!(
+            validate_header(&at_median, Some(parent), Some(median), BITS),
+            Err(BlockValidationError::TimestampTooOld)
+        );
+
+        // Older than its parent, but later than the median
+        let after_median = block_at(parent.header().current_hash(), minutes(7));
+        assert_eq!(validate_header(&after_median, Some(parent), Some(median), BITS), Ok(()));
+    }
+
+    #[test]
+    fn it_refuses_timestamps_too_far_in_the_future() {
+        let now = Utc::now().fixed_offset();
+        let drift = Duration::seconds(BLOCKCHAIN_MAX_FUTURE_BLOCK_TIME_SECS);
+
+        let ahead = block_at(&"0".repeat(64), now + drift - Duration::minutes(1));
+        assert_eq!(validate_header(&ahead, None, None, BITS), Ok(()));
+
+        let too_far = block_at(&"0".repeat(64), now + drift + Duration::minutes(1));
+        assert_eq!(
+            validate_header(&too_far, None, None, BITS),
+            Err(BlockValidationError::TimestampTooFarInFuture)
+        );
+    }
+
+    #[test]
+    fn it_halves_the_subsidy_up_to_the_max_supply() {
+        let params = ConsensusParams {
+            genesis_subsidy: 100,
+            initial_subsidy: 8,
+            halving_interval: 2,
+            max_supply: 1_000,
+            ..ConsensusParams::default()
+        };
+        let subsidies: Vec<u64> = (0..10).map(|h| params.block_subsidy(h)).collect();
+        assert_eq!(subsidies, vec![100, 8, 8, 4, 4, 2, 2, 1, 1, 0]);
+        assert_eq!(params.issued_supply(8), 130);
+        assert_eq!(params.issued_supply(u64::MAX), 130);
+
+        // Issuance stops right at the max supply, even mid-era
+        let capped = ConsensusParams {
+            max_supply: 118,
+            ..params
+        };
+        assert_eq!(capped.block_subsidy(3), 2);
+        assert_eq!(capped.block_subsidy(4), 0);
+        assert_eq!(capped.issued_supply(u64::MAX), 118);
+    }
+}
@@ -0,0 +1,368 @@
+//! # Difficulty
+//!
+//! Proof-of-work targets and difficulty retargeting.
+//!
+//! A block hash, read as a 256-bit big-endian number, must not exceed the block target.
+//! Headers store the target in compact form (`bits`): the highest byte is the size of the
+//! target in bytes, the lower three bytes are its most significant bytes.
+//!
+//! Every `retarget_window` blocks, the target is scaled by the time the last window of
+//! blocks actually took over the time it should have taken at `target_block_time` per block.
+//! The adjustment is clamped to a factor of 4 either way, and the target never exceeds
+//! the proof-of-work limit.
+
+use std::cmp::Ordering;
+use std::time::Duration;
+
+use chrono::{DateTime, FixedOffset};
+
+use super::{Block, BlockValidationError};
+use crate::config::{
+    BLOCKCHAIN_INITIAL_BITS, BLOCKCHAIN_POW_LIMIT_BITS, BLOCKCHAIN_RETARGET_WINDOW,
+    BLOCKCHAIN_TARGET_BLOCK_TIME_SECS,
+};
+
+/// Maximum factor the target changes by in a single retarget
+const MAX_ADJUSTMENT_FACTOR: u64 = 4;
+
+/// Difficulty parameters of a chain
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DifficultyConfig {
+    pub initial_bits: u32,       // Target of the genesis block and the first window
+    pub pow_limit_bits: u32,     // Easiest target allowed
+    pub target_block_time: Duration,
+    pub retarget_window: u64,    // Blocks between two adjustments, at least 2
+}
+
+impl Default for DifficultyConfig {
+    fn default() -> Self {
+        Self {
+            initial_bits: BLOCKCHAIN_INITIAL_BITS,
+            pow_limit_bits: BLOCKCHAIN_POW_LIMIT_BITS,
+            target_block_time: Duration::from_secs(BLOCKCHAIN_TARGET_BLOCK_TIME_SECS),
+            retarget_window: BLOCKCHAIN_RETARGET_WINDOW,
+        }
+    }
+}
+
+/// 256-bit proof-of-work target
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Target {
+    limbs: [u64; 4], // Least significant limb first
+}
+
+impl Target {
+    pub const MAX: Target = Target {
+        limbs: [u64::MAX; 4],
+    };
+
+    pub fn from_be_bytes(bytes: [u8; 32]) -> Self {
+        let mut limbs = [0u64; 4];
+        for (i, limb) in limbs.iter_mut().enumerate() {
+            let start = 32 - (i + 1) * 8;
+            *limb = u64::from_be_bytes(bytes[start..start + 8].try_into().unwrap());
+        }
+        Self { limbs }
+    }
+
+    pub fn to_be_bytes(&self) -> [u8; 32] {
+        let mut bytes = [0u8; 32];
+        for (i, limb) in self.limbs.iter().enumerate() {
+            let start = 32 - (i + 1) * 8;
+            bytes[start..start + 8].copy_from_slice(&limb.to_be_bytes());
+        }
+        bytes
+    }
+
+    /// Decodes compact `bits`, `None` if they are negative or overflow 256 bits
+    pub fn from_compact(bits: u32) -> Option<Self> {
+        let size = bits >> 24;
+        let mantissa = bits & 0x007f_ffff;
+
+        if mantissa != 0 && bits & 0x0080_0000 != 0 {
+            return None;
+        }
+        if mantissa != 0
+            && (size > 34 || (mantissa > 0xff && size > 33) || (mantissa > 0xffff && size > 32))
+        {
+            return None;
+        }
+
+        let target = Self::from_u64(mantissa as u64);
+        Some(if size <= 3 {
+            target.shr(8 * (3 - size))
+        } else {
+            target.shl(8 * (size - 3))
+        })
+    }
+
+    /// Encodes the target in compact form, dropping all but its three most significant bytes
+    pub fn to_compact(&self) -> u32 {
+        let mut size = self.bit_len().div_ceil(8);
+        let mut mantissa = if size <= 3 {
+            self.limbs[0] << (8 * (3 - size))
+        } else {
+            self.shr(8 * (size - 3)).limbs[0]
+        } as u32;
+
+        // The sign bit of the mantissa must stay clear
+        if mantissa & 0x0080_0000 != 0 {
+            mantissa >>= 8;
+            size += 1;
+        }
+        mantissa | (size << 24)
+    }
+
+    /// Checks if a block hash, hex encoded, meets the target
+    pub fn is_met_by(&self, hash: &str) -> bool {
+        crate::utils::HashHelper::hash_from_hex(hash)
+            .is_some_and(|hash| Target::from_be_bytes(hash) <= *self)
+    }
+
+    /// Expected number of hashes to meet the target, `2^256 / (target + 1)`.
+    ///
+    /// Computed from the 64 most significant bits of `target + 1`, which is exact enough
+    /// to compare chains, and saturating for targets below `2^128`.
+    pub fn work(&self) -> u128 {
+        let bit_len = self.bit_len();
+        if bit_len <= 128 {
+            return u128::MAX;
+        }
+        // target + 1 ≈ mantissa * 2^shift, so work ≈ 2^exponent / mantissa
+        let shift = bit_len - 64;
+        let mantissa = self.shr(shift).limbs[0] as u128 + 1;
+        let exponent = 256 - shift;
+        if exponent <= 127 {
+            (1u128 << exponent) / mantissa
+        } else {
+            ((1u128 << 127) / mantissa) << (exponent - 127)
+        }
+    }
+
+    /// `self * factor / divisor`, saturating at `Target::MAX`
+    pub fn scale(&self, factor: u64, divisor: u64) -> Self {
+        match self.mul_u64(factor) {
+            Some(product) => product.div_u64(divisor),
+            // Large targets lose nothing meaningful by dividing first
+            None => self.div_u64(divisor).mul_u64(factor).unwrap_or(Self::MAX),
+        }
+    }
+
+    fn from_u64(value: u64) -> Self {
+        Self {
+            limbs: [value, 0, 0, 0],
+        }
+    }
+
+    fn bit_len(&self) -> u32 {
+        for i in (0..4).rev() {
+            if self.limbs[i] != 0 {
+                return 64 * i as u32 + (64 - self.limbs[i].leading_zeros());
+            }
+        }
+        0
+    }
+
+    fn shl(&self, shift: u32) -> Self {
+        let mut limbs = [0u64; 4];
+        let (limb_shift, bit_shift) = ((shift / 64) as usize, shift % 64);
+        for i in (limb_shift..4).rev() {
+            limbs[i] = self.limbs[i - limb_shift] << bit_shift;
+            if bit_shift > 0 && i > limb_shift {
+                limbs[i] |= self.limbs[i - limb_shift - 1] >> (64 - bit_shift);
+            }
+        }
+        Self { limbs }
+    }
+
+    fn shr(&self, shift: u32) -> Self {
+        let mut limbs = [0u64; 4];
+        let (limb_shift, bit_shift) = ((shift / 64) as usize, shift % 64);
+        for (i, limb) in limbs.iter_mut().take(4usize.saturating_sub(limb_shift)).enumerate() {
+            *limb = self.limbs[i + limb_shift] >> bit_shift;
+            if bit_shift > 0 && i + limb_shift + 1 < 4 {
+                *limb |= self.limbs[i + limb_shift + 1] << (64 - bit_shift);
+            }
+        }
+        Self { limbs }
+    }
+
+    fn mul_u64(&self, factor: u64) -> Option<Self> {
+        let mut limbs = [0u64; 4];
+        let mut carry = 0u128;
+        for (i, limb) in limbs.iter_mut().enumerate() {
+            let product = self.limbs[i] as u128 * factor as u128 + carry;
+            *limb = product as u64;
+            carry = product >> 64;
+        }
+        (carry == 0).then_some(Self { limbs })
+    }
+
+    fn div_u64(&self, divisor: u64) -> Self {
+        let mut limbs = [0u64; 4];
+        let mut remainder = 0u128;
+        for i in (0..4).rev() {
+            let dividend = (remainder << 64) | self.limbs[i] as u128;
+            limbs[i] = (dividend / divisor as u128) as u64;
+            remainder = dividend % divisor as u128;
+        }
+        Self { limbs }
+    }
+}
+
+impl Ord for Target {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.limbs.iter().rev().cmp(other.limbs.iter().rev())
+    }
+}
+
+impl PartialOrd for Target {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Proof-of-work of a block with compact target `bits`, 0 for invalid bits
+pub fn block_work(bits: u32) -> u128 {
+    Target::from_compact(bits).map_or(0, |target| target.work())
+}
+
+/// Parsed block timestamp
+pub fn block_time(block: &Block) -> Result<DateTime<FixedOffset>, BlockValidationError> {
+    DateTime::parse_from_rfc3339(block.header().timestamp())
+        .map_err(|_| BlockValidationError::InvalidTimestamp)
+}
+
+/// Block timestamp, in milliseconds since the Unix epoch
+pub fn block_time_millis(block: &Block) -> Result<i64, BlockValidationError> {
+    Ok(block_time(block)?.timestamp_millis())
+}
+
+/// Compact target required for the block following `chain`, its ancestors from genesis
+pub fn next_bits(chain: &[Block], config: &DifficultyConfig) -> Result<u32, BlockValidationError> {
+    next_bits_at(
+        chain.len() as u64,
+        chain.last(),
+        |height| chain.get(height as usize),
+        config,
+    )
+}
+
+/// Compact target required for a block at `height` with `parent` (`None` for the genesis block),
+/// where `ancestor(h)` returns the ancestor of the block at height `h`
+pub fn next_bits_at<'a>(
+    height: u64,
+    parent: Option<&'a Block>,
+    ancestor: impl FnOnce(u64) -> Option<&'a Block>,
+    config: &DifficultyConfig,
+) -> Result<u32, BlockValidationError> {
+    let Some(parent) = parent else {
+        return Ok(config.initial_bits);
+    };
+
+    let window = config.retarget_window.max(2);
+    if !height.is_multiple_of(window) {
+        return Ok(parent.header().bits());
+    }
+
+    let first = ancestor(height - window).ok_or(BlockValidationError::PreviousBlockNotFound)?;
+    let expected = (window - 1)
+        .saturating_mul(config.target_block_time.as_millis() as u64)
+        .max(1);
+    let actual = (block_time_millis(parent)? - block_time_millis(first)?).max(0) as u64;
+    let actual = actual.clamp(expected / MAX_ADJUSTMENT_FACTOR, expected * MAX_ADJUSTMENT_FACTOR);
+
+    let parent_bits = parent.header().bits();
+    let target = Target::from_compact(parent_bits)
+        .ok_or(BlockValidationError::InvalidBits(parent_bits))?;
+    let limit = Target::from_compact(config.pow_limit_bits)
+        .ok_or(BlockValidationError::InvalidBits(config.pow_limit_bits))?;
+
+    Ok(target.scale(actual, expected).min(limit).to_compact())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::BLOCKCHAIN_POW_LIMIT_BITS;
+
+    #[test]
+    fn it_converts_compact_targets() {
+        let target = Target::from_compact(0x1d00ffff).unwrap();
+        let mut expected = [0u8; 32];
+        expected[4] = 0xff;
+        expected[5] = 0xff;
+        assert_eq!(target.to_be_bytes(), expected);
+        assert_eq!(target.to_compact(), 0x1d00ffff);
+
+        assert_eq!(Target::from_compact(0x2000ffff).unwrap().to_compact(), 0x2000ffff);
+        assert_eq!(Target::from_compact(0x05009234).unwrap().to_compact(), 0x05009234);
+        assert_eq!(Target::from_compact(0x01003456).unwrap().to_compact(), 0x00000000);
+        // Negative and overflowing targets
+        assert_eq!(Target::from_compact(0x04923456), None);
+        assert_eq!(Target::from_compact(0xff123456), None);
+
+        assert!(target.is_met_by(&format!("00000000ffff{}", "00".repeat(26))));
+        assert!(!target.is_met_by(&format!("00000000ffff{}01", "00".repeat(25))));
+    }
+
+    #[test]
+    fn it_scales_targets() {
+        let target = Target::from_compact(0x1d00ffff).unwrap();
+        assert_eq!(target.scale(1, 4).to_compact(), 0x1c3fffc0);
+        assert_eq!(target.scale(4, 1).to_compact(), 0x1d03fffc);
+        assert_eq!(Target::MAX.scale(4, 1), Target::MAX);
+        assert_eq!(Target::MAX.scale(1, 2).to_compact(), 0x207fffff);
+    }
+
+    #[test]
+    fn it_computes_work() {
+        assert_eq!(Target::MAX.work(), 1);
+        // 0x00ffff00.. is met by about 1 in 256 hashes
+        assert_eq!(block_work(0x2000ffff), 256);
+        // Bitcoin genesis target
+        assert_eq!(block_work(0x1d00ffff), 0x1_0001_0001);
+        assert!(block_work(0x1c7fff80) > block_work(0x1d00ffff));
+        assert_eq!(block_work(0x04923456), 0);
+    }
+
+    fn chain_with_spacing(length: usize, spacing_secs: i64, bits: u32) -> Vec<Block> {
+        let start = DateTime::parse_from_rfc3339("2025-01-01T00:00:00+00:00").unwrap();
+        (0..length)
+            .map(|height| {
+                let mut block =
+                    Block::new(&"0".repeat(64), &[], BLOCKCHAIN_POW_LIMIT_BITS).unwrap();
+                block.header.bits = bits;
+                block.header.timestamp =
+                    (start + chrono::Duration::seconds(spacing_secs * height as i64)).to_rfc3339();
+                block
+            })
+            .collect()
+    }
+
+    #[test]
+    fn it_retargets_every_window() {
+        let config = DifficultyConfig {
+            initial_bits: 0x1d00ffff,
+            pow_limit_bits: 0x1d00ffff,
+            target_block_time: Duration::from_secs(10),
+            retarget_window: 4,
+        };
+        assert_eq!(next_bits(&[], &config), Ok(0x1d00ffff));
+
+        // Blocks twice as fast as targeted: the target halves
+        let fast = chain_with_spacing(4, 5, 0x1d00ffff);
+        assert_eq!(next_bits(&fast[..3], &config), Ok(0x1d00ffff));
+        assert_eq!(next_bits(&fast, &config), Ok(0x1c7fff80));
+
+        // Adjustments are clamped to a factor of 4
+        let very_fast = chain_with_spacing(4, 0, 0x1d00ffff);
+        assert_eq!(next_bits(&very_fast, &config), Ok(0x1c3fffc0));
+
+        // Slow blocks ease the target, up to the proof-of-work limit
+        let slow = chain_with_spacing(4, 20, 0x1c3fffc0);
+        assert_eq!(next_bits(&slow, &config), Ok(0x1c7fff80));
+        let very_slow = chain_with_spacing(4, 100, 0x1c3fffc0);
+        assert_eq!(next_bits(&very_slow, &config), Ok(0x1d00ffff));
+    }
+}
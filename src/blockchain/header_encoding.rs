@@ -0,0 +1,194 @@
+//! # Block Header Encoding
+//!
+//! Canonical byte encoding of a [`BlockHeader`], hashed with SHA-256 to get the block hash.
+//! The same encoding is used when mining and when validating blocks.
+//!
+//! Version 1 layout, integers little-endian:
+//!
+//! | Field | Size | Description |
+//! |-------|------|-------------|
+//! | version | 4 | Header encoding version, `1` |
+//! | previous_hash | 32 | Hash of the parent block |
+//! | merkle_root | 32 | Merkle root of the block transactions |
+//! | timestamp length | 2 | Length of the timestamp, in bytes |
+//! | timestamp | n | RFC 3339 timestamp, UTF-8 |
+//! | bits | 4 | Proof-of-work target, in compact form |
+//! | nonce | 8 | Proof-of-work nonce, always the last field |
+//!
+//! `current_hash` is not part of the encoding, it is the hash of it.
+
+use thiserror::Error;
+
+use super::BlockHeader;
+use crate::utils::HashHelper;
+
+/// Current header encoding version
+pub const BLOCK_HEADER_VERSION: u32 = 1;
+
+/// Size of the nonce, which ends the encoded header
+const NONCE_SIZE: usize = 8;
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum HeaderEncodingError {
+    #[error("Unsupported block header version {0}")]
+    UnsupportedVersion(u32),
+    #[error("Header field `{0}` is not a hex encoded 32-byte hash")]
+    InvalidHash(&'static str),
+    #[error("Header timestamp is too long or not valid UTF-8")]
+    InvalidTimestamp,
+    #[error("Encoded header is truncated")]
+    Truncated,
+    #[error("Encoded header has trailing bytes")]
+    TrailingBytes,
+}
+
+impl BlockHeader {
+    /// Canonical encoding of the header, without `current_hash`
+    pub fn encode(&self) -> Result<Vec<u8>, HeaderEncodingError> {
+        if self.version != BLOCK_HEADER_VERSION {
+            return Err(HeaderEncodingError::UnsupportedVersion(self.version));
+        }
+
+        let previous_hash = HashHelper::hash_from_hex(&self.previous_hash)
+            .ok_or(HeaderEncodingError::InvalidHash("previous_hash"))?;
+        let merkle_root = HashHelper::hash_from_hex(&self.merkle_root)
+            .ok_or(HeaderEncodingError::InvalidHash("merkle_root"))?;
+        let timestamp_len = u16::try_from(self.timestamp.len())
+            .map_err(|_| HeaderEncodingError::InvalidTimestamp)?;
+
+        let mut bytes = Vec::with_capacity(82 + self.timestamp.len());
+        bytes.extend_from_slice(&self.version.to_le_bytes());
+        bytes.extend_from_slice(&previous_hash);
+        bytes.extend_from_slice(&merkle_root);
+        bytes.extend_from_slice(&timestamp_len.to_le_bytes());
+        bytes.extend_from_slice(self.timestamp.as_bytes());
+        bytes.extend_from_slice(&self.bits.to_le_bytes());
+        bytes.extend_from_slice(&self.nonce.to_le_bytes());
+        Ok(bytes)
+    }
+
+    /// Decodes a canonically encoded header, computing its `current_hash`
+    pub fn decode(bytes: &[u8]) -> Result<Self, HeaderEncodingError> {
+        let mut reader = Reader { bytes };
+
+        let version = u32::from_le_bytes(reader.take()?);
+        if version != BLOCK_HEADER_VERSION {
+            return Err(HeaderEncodingError::UnsupportedVersion(version));
+        }
+        let previous_hash: [u8; 32] = reader.take()?;
+        let merkle_root: [u8; 32] = reader.take()?;
+        let timestamp_len = u16::from_le_bytes(reader.take()?) as usize;
+        let timestamp = String::from_utf8(reader.take_slice(timestamp_len)?.to_vec())
+            .map_err(|_| HeaderEncodingError::InvalidTimestamp)?;
+        let bits = u32::from_le_bytes(reader.take()?);
+        let nonce = u64::from_le_bytes(reader.take()?);
+
+        if !reader.bytes.is_empty() {
+            return Err(HeaderEncodingError::TrailingBytes);
+        }
+
+        Ok(BlockHeader {
+            version,
+            timestamp,
+            previous_hash: HashHelper::to_hex(&previous_hash),
+            merkle_root: HashHelper::to_hex(&merkle_root),
+            current_hash: HashHelper::generate_hash(bytes),
+            nonce,
+            bits,
+        })
+    }
+
+    /// Hash of the canonical encoding of the header
+    pub fn hash(&self) -> Result<String, HeaderEncodingError> {
+        Ok(HashHelper::generate_hash(&self.encode()?))
+    }
+}
+
+/// Overwrites the nonce of a canonically encoded header, so miners can
+/// iterate nonces without re-encoding the whole header
+pub fn set_encoded_nonce(encoded_header: &mut [u8], nonce: u64) {
+    let start = encoded_header.len() - NONCE_SIZE;
+    encoded_header[start..].copy_from_slice(&nonce.to_le_bytes());
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl Reader<'_> {
+    fn take_slice(&mut self, len: usize) -> Result<&[u8], HeaderEncodingError> {
+        if self.bytes.len() < len {
+            return Err(HeaderEncodingError::Truncated);
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], HeaderEncodingError> {
+        Ok(self.take_slice(N)?.try_into().expect("slice of length N"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Test vector header, see README "Block header encoding"
+    fn vector_header() -> BlockHeader {
+        BlockHeader {
+            version: 1,
+            timestamp: "2025-01-01T00:00:00+00:00".to_string(),
+            previous_hash: "00".repeat(32),
+            merkle_root: "11".repeat(32),
+            current_hash: String::new(),
+            nonce: 42,
+            bits: 0x2000ffff,
+        }
+    }
+
+    #[test]
+    fn it_matches_test_vectors() {
+        let header = vector_header();
+        let encoded = header.encode().unwrap();
+
+        assert_eq!(
+            HashHelper::to_hex(&encoded),
+            concat!(
+                "01000000",
+                "0000000000000000000000000000000000000000000000000000000000000000",
+                "1111111111111111111111111111111111111111111111111111111111111111",
+                "1900",
+                "323032352d30312d30315430303a30303a30302b30303a3030",
+                "ffff0020",
+                "2a00000000000000",
+            )
+        );
+        assert_eq!(
+            header.hash().unwrap(),
+            "d7211daeabd700331c7d14521fc99a81f85fa0d18ca1771c14de3e1aaf3d99a5"
+        );
+    }
+
+    #[test]
+    fn it_round_trips_and_patches_nonce() {
+        let mut header = vector_header();
+        let mut encoded = header.encode().unwrap();
+
+        set_encoded_nonce(&mut encoded, 7);
+        header.nonce = 7;
+        assert_eq!(encoded, header.encode().unwrap());
+
+        let decoded = BlockHeader::decode(&encoded).unwrap();
+        assert_eq!(decoded.nonce, 7);
+        assert_eq!(decoded.timestamp, header.timestamp);
+        assert_eq!(decoded.current_hash, header.hash().unwrap());
+
+        assert_eq!(
+            BlockHeader::decode(&encoded[..encoded.len() - 1]).unwrap_err(),
+            HeaderEncodingError::Truncated
+        );
+        header.version = 2;
+        assert_eq!(header.encode(), Err(HeaderEncodingError::UnsupportedVersion(2)));
+    }
+}
@@ -0,0 +1,190 @@
+//! # Merkle Tree
+//!
+//! Binary Merkle tree over the `transaction_hash` values of a block, in block order.
+//! The root is committed to in [`BlockHeader`](super::BlockHeader), so an inclusion
+//! proof is enough to show a transaction is part of a block without the whole body.
+//!
+//! Leaves and inner nodes are hashed with distinct prefixes, and a node without a sibling
+//! is carried up to the next level unchanged, so no two transaction lists share a root.
+//! The root of an empty tree is all zeros.
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::transaction::Transaction;
+
+const LEAF_PREFIX: u8 = 0x00;
+const NODE_PREFIX: u8 = 0x01;
+
+/// Merkle tree, storing every level from the leaves up to the root
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MerkleTree {
+    levels: Vec<Vec<[u8; 32]>>,
+}
+
+/// Sibling hash on the path from a leaf to the root
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MerkleProofStep {
+    pub sibling: [u8; 32],
+    pub sibling_is_left: bool,
+}
+
+/// Proof that `transaction_hash` is the leaf at `index` of a tree
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MerkleProof {
+    pub transaction_hash: [u8; 32],
+    pub index: usize,
+    pub path: Vec<MerkleProofStep>,
+}
+
+impl MerkleTree {
+    /// Builds the tree over `transaction_hashes`, in order
+    pub fn new(transaction_hashes: &[[u8; 32]]) -> Self {
+        let mut levels = vec![transaction_hashes.iter().map(hash_leaf).collect::<Vec<_>>()];
+
+        while levels.last().is_some_and(|level| level.len() > 1) {
+            let next = levels
+                .last()
+                .unwrap()
+                .chunks(2)
+                .map(|pair| match pair {
+                    [left, right] => hash_node(left, right),
+                    [single] => *single,
+                    _ => unreachable!(),
+                })
+                .collect();
+            levels.push(next);
+        }
+
+        Self { levels }
+    }
+
+    /// Builds the tree over the hashes of `transactions`
+    pub fn from_transactions(transactions: &[Transaction]) -> Self {
+        let hashes: Vec<[u8; 32]> = transactions
+            .iter()
+            .map(|transaction| transaction.metadata().transaction_hash)
+            .collect();
+        Self::new(&hashes)
+    }
+
+    /// Root of the tree, all zeros when there are no leaves
+    pub fn root(&self) -> [u8; 32] {
+        self.levels
+            .last()
+            .and_then(|level| level.first())
+            .copied()
+            .unwrap_or([0u8; 32])
+    }
+
+    /// Number of leaves
+    pub fn len(&self) -> usize {
+        self.levels[0].len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.levels[0].is_empty()
+    }
+
+    /// Inclusion proof for the leaf at `index`, which hashes `transaction_hash`
+    pub fn proof(&self, index: usize, transaction_hash: [u8; 32]) -> Option<MerkleProof> {
+        if index >= self.len() || self.levels[0][index] != hash_leaf(&transaction_hash) {
+            return None;
+        }
+
+        let mut path = Vec::new();
+        let mut position = index;
+        for level in &self.levels[..self.levels.len() - 1] {
+            let sibling_position = position ^ 1;
+            if let Some(sibling) = level.get(sibling_position) {
+                path.push(MerkleProofStep {
+                    sibling: *sibling,
+                    sibling_is_left: sibling_position < position,
+                });
+            }
+            position /= 2;
+        }
+
+        Some(MerkleProof {
+            transaction_hash,
+            index,
+            path,
+        })
+    }
+}
+
+impl MerkleProof {
+    /// Checks that the proof leads from its transaction hash to `root`
+    pub fn verify(&self, root: &[u8; 32]) -> bool {
+        let computed = self.path.iter().fold(hash_leaf(&self.transaction_hash), |hash, step| {
+            if step.sibling_is_left {
+                hash_node(&step.sibling, &hash)
+            } else {
+                hash_node(&hash, &step.sibling)
+            }
+        });
+        &computed == root
+    }
+}
+
+/// Merkle root over the hashes of `transactions`
+pub fn merkle_root(transactions: &[Transaction]) -> [u8; 32] {
+    MerkleTree::from_transactions(transactions).root()
+}
+
+fn hash_leaf(transaction_hash: &[u8; 32]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update([LEAF_PREFIX]);
+    hasher.update(transaction_hash);
+    hasher.finalize().into()
+}
+
+fn hash_node(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update([NODE_PREFIX]);
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hashes(count: u8) -> Vec<[u8; 32]> {
+        (0..count).map(|i| [i; 32]).collect()
+    }
+
+    #[test]
+    fn it_proves_inclusion_of_every_leaf() {
+        for count in 1..=7 {
+            let leaves = hashes(count);
+            let tree = MerkleTree::new(&leaves);
+            let root = tree.root();
+
+            for (index, leaf) in leaves.iter().enumerate() {
+                let proof = tree.proof(index, *leaf).unwrap();
+                assert!(proof.verify(&root), "leaf {} of {}", index, count);
+
+                let mut forged = proof.clone();
+                forged.transaction_hash = [0xff; 32];
+                assert!(!forged.verify(&root));
+            }
+        }
+    }
+
+    #[test]
+    fn it_commits_to_leaf_order_and_count() {
+        let leaves = hashes(3);
+        let mut swapped = leaves.clone();
+        swapped.swap(0, 1);
+        let mut duplicated = leaves.clone();
+        duplicated.push(leaves[2]);
+
+        let root = MerkleTree::new(&leaves).root();
+        assert_ne!(root, MerkleTree::new(&swapped).root());
+        assert_ne!(root, MerkleTree::new(&duplicated).root());
+        assert_eq!(MerkleTree::new(&[]).root(), [0u8; 32]);
+        assert!(MerkleTree::new(&leaves).proof(0, leaves[1]).is_none());
+    }
+}
@@ -34,7 +34,15 @@
 //! |------------|-------------|
 //! | [`block`] | Defines the [`Block`], [`BlockHeader`], and [`BlockBody`] data structures, along with genesis and data block creation logic. |
 //! | [`blockchain`] | Implements the [`Blockchain`] struct — the core chain management logic including block addition, validation, and reward assignment. |
+//! | [`consensus`] | Consensus rules every block must follow: proof of work, difficulty, coinbase placement and value, transaction validity. |
+//! | [`block_tree`] | [`BlockTree`] of every known block, main chain and side branches, with the cumulative work of each branch. |
+//! | [`chain_validator`] | [`ChainValidator`] validating blocks against their parents through a hash → index map, in linear time, stopping at the first failure or collecting all of them. |
+//! | [`difficulty`] | Compact proof-of-work targets and retargeting every window of blocks towards the target block time. |
+//! | [`header_encoding`] | Canonical, versioned byte encoding of the block header, hashed for proof of work. |
+//! | [`merkle`] | Merkle tree over block transaction hashes, its root committed in the block header, and inclusion proofs. |
 //! | [`blockchain_listener`] | Provides asynchronous WebSocket-based event listening and broadcasting for blockchain-related messages. |
+//! | [`utxo_set`] | Outpoint-keyed set of unspent outputs, connected and disconnected block by block with undo data. |
+//! | [`storage`] | Append-only on-disk block file plus index, with crash recovery, used to reload the chain on restart. |
 //!
 //! ## Example
 //!
@@ -62,8 +70,6 @@
 //!
 //! ## Future Improvements
 //! - Peer-to-peer network synchronization  
-//! - Dynamic difficulty adjustment  
-//! - Persistent storage backend (RocksDB or SQLite)  
 //! - Smart contract execution layer  
 //!
 //! ---
@@ -71,16 +77,40 @@
 //!
 //! - [`blockchain`]: Blockchain structure, validation, and lifecycle management  
 //! - [`block`]: Block and block header definitions  
+//! - [`consensus`]: Block validation rules  
+//! - [`block_tree`]: Known blocks and branch work for fork choice  
+//! - [`chain_validator`]: Linear-time chain validation  
+//! - [`difficulty`]: Proof-of-work targets and retargeting  
+//! - [`header_encoding`]: Canonical block header encoding  
+//! - [`merkle`]: Merkle trees and inclusion proofs  
 //! - [`blockchain_listener`]: Real-time blockchain event server  
+//! - [`storage`]: Persistent block storage  
+//! - [`utxo_set`]: Unspent transaction outputs and block undo data  
 //!
 //! ---
 
 
 
+#[allow(clippy::module_inception)]
 mod blockchain;
 mod blockchain_listener;
 mod block;
+mod block_tree;
+mod chain_validator;
+mod difficulty;
+mod header_encoding;
+mod merkle;
+pub mod consensus;
+mod storage;
+mod utxo_set;
 
 pub use blockchain::*;
 pub use blockchain_listener::*;
-pub use block::*;
\ No newline at end of file
+pub use block::*;
+pub use block_tree::*;
+pub use chain_validator::*;
+pub use difficulty::*;
+pub use header_encoding::*;
+pub use merkle::*;
+pub use storage::*;
+pub use utxo_set::*;
\ No newline at end of file
@@ -0,0 +1,395 @@
+//! # Block Storage
+//!
+//! Append-only, on-disk persistence for every accepted block, on the active chain or
+//! on a side branch, in arrival order. A block is always stored after its parent.
+//!
+//! Blocks are written to `blocks.dat` as length-prefixed, checksummed records:
+//!
+//! ```text
+//! | magic (4 bytes) | payload length (u32 LE) | checksum (4 bytes) | payload (JSON) |
+//! ```
+//!
+//! `blocks.idx` stores the byte offset (u64 LE) of every record in `blocks.dat`,
+//! so a block can be read by its position without scanning the whole file.
+//! The index is only a cache: on open, the tail of the data file is re-verified and
+//! a half-written final record (e.g. after a crash or power loss) is truncated away.
+//!
+//! `FORMAT` holds the [`STORAGE_FORMAT_VERSION`] the blocks were written with. Blocks of an
+//! older format cannot be validated anymore (e.g. format 1 hashed the transaction status into
+//! the transaction id), so on open they are moved to `legacy-format-<version>/` and the chain
+//! starts over from a new genesis block. Newer formats are refused.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use tracing::warn;
+
+use super::Block;
+
+/// Name of the append-only block data file.
+const BLOCKS_FILE: &str = "blocks.dat";
+
+/// Name of the block offset index file.
+const INDEX_FILE: &str = "blocks.idx";
+
+/// Marks the start of every block record.
+const RECORD_MAGIC: [u8; 4] = *b"OXBK";
+
+/// Name of the file holding the storage format version.
+const FORMAT_FILE: &str = "FORMAT";
+
+/// Format of stored blocks, bumped whenever stored blocks stop being valid:
+/// 2 since transaction ids are versioned and no longer hash the transaction status,
+/// 3 since the transaction type is part of transaction ids and signatures.
+pub const STORAGE_FORMAT_VERSION: u32 = 3;
+
+/// Format of a data directory written before `FORMAT` existed.
+const LEGACY_FORMAT_VERSION: u32 = 1;
+
+/// Magic + payload length + checksum.
+const RECORD_HEADER_SIZE: u64 = 12;
+
+/// Errors raised by the block storage.
+#[derive(Error, Debug)]
+pub enum StorageError {
+    #[error("Storage I/O error: {0}")]
+    Io(#[from] io::Error),
+    #[error("Cannot (de)serialize block: {0}")]
+    Serialization(#[from] serde_json::Error),
+    #[error("Corrupted block record at offset {0}")]
+    CorruptedRecord(u64),
+    #[error("Storage format {found} is newer than the supported format {supported}")]
+    UnsupportedFormat { found: u32, supported: u32 },
+}
+
+/// Append-only block file plus offset index, living in a single data directory.
+#[derive(Debug)]
+pub struct BlockStorage {
+    dir: PathBuf,
+    data: File,
+    index: File,
+    offsets: Vec<u64>,
+}
+
+impl BlockStorage {
+    /// Opens (or creates) the block storage inside `dir` and runs crash recovery.
+    pub fn open(dir: &Path) -> Result<Self, StorageError> {
+        fs::create_dir_all(dir)?;
+        Self::migrate(dir)?;
+
+        let data = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(dir.join(BLOCKS_FILE))?;
+        let index = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(dir.join(INDEX_FILE))?;
+
+        let mut storage = Self {
+            dir: dir.to_path_buf(),
+            data,
+            index,
+            offsets: vec![],
+        };
+        storage.recover()?;
+
+        Ok(storage)
+    }
+
+    /// Returns the directory the storage lives in
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Number of blocks stored on disk
+    pub fn len(&self) -> usize {
+        self.offsets.len()
+    }
+
+    /// Returns true if no block has been stored yet
+    pub fn is_empty(&self) -> bool {
+        self.offsets.is_empty()
+    }
+
+    /// Durably appends a block to the end of the block file and records its offset in the index.
+    /// The data file is synced before the index, so the index never points to a missing record.
+    pub fn append_block(&mut self, block: &Block) -> Result<(), StorageError> {
+        let payload = serde_json::to_vec(block)?;
+        let offset = self.data.seek(SeekFrom::End(0))?;
+
+        let mut record = Vec::with_capacity(RECORD_HEADER_SIZE as usize + payload.len());
+        record.extend_from_slice(&RECORD_MAGIC);
+        record.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        record.extend_from_slice(&Self::checksum(&payload));
+        record.extend_from_slice(&payload);
+
+        self.data.write_all(&record)?;
+        self.data.sync_data()?;
+
+        self.index.seek(SeekFrom::End(0))?;
+        self.index.write_all(&offset.to_le_bytes())?;
+        self.index.sync_data()?;
+
+        self.offsets.push(offset);
+
+        Ok(())
+    }
+
+    /// Reads the block stored at `position`, if any
+    pub fn read_block(&mut self, position: usize) -> Result<Option<Block>, StorageError> {
+        let Some(&offset) = self.offsets.get(position) else {
+            return Ok(None);
+        };
+
+        match self.read_record(offset)? {
+            Some((payload, _)) => Ok(Some(serde_json::from_slice(&payload)?)),
+            None => Err(StorageError::CorruptedRecord(offset)),
+        }
+    }
+
+    /// Loads every stored block, in the order they were appended
+    pub fn load_blocks(&mut self) -> Result<Vec<Block>, StorageError> {
+        let mut blocks = Vec::with_capacity(self.offsets.len());
+        for position in 0..self.offsets.len() {
+            if let Some(block) = self.read_block(position)? {
+                blocks.push(block);
+            }
+        }
+        Ok(blocks)
+    }
+
+    /// Brings the data directory to the current format, archiving blocks of an older format
+    fn migrate(dir: &Path) -> Result<(), StorageError> {
+        let format_path = dir.join(FORMAT_FILE);
+        let found = match fs::read_to_string(&format_path) {
+            Ok(content) => content.trim().parse().unwrap_or(LEGACY_FORMAT_VERSION),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => LEGACY_FORMAT_VERSION,
+            Err(e) => return Err(e.into()),
+        };
+
+        if found == STORAGE_FORMAT_VERSION {
+            return Ok(());
+        }
+        if found > STORAGE_FORMAT_VERSION {
+            return Err(StorageError::UnsupportedFormat {
+                found,
+                supported: STORAGE_FORMAT_VERSION,
+            });
+        }
+
+        let data_path = dir.join(BLOCKS_FILE);
+        if fs::metadata(&data_path).is_ok_and(|m| m.len() > 0) {
+            let archive = dir.join(format!("legacy-format-{}", found));
+            warn!(
+                "Blocks in {} use storage format {}, moving them to {}",
+                dir.display(),
+                found,
+                archive.display()
+            );
+            fs::create_dir_all(&archive)?;
+            fs::rename(&data_path, archive.join(BLOCKS_FILE))?;
+            let index_path = dir.join(INDEX_FILE);
+            if index_path.exists() {
+                fs::rename(&index_path, archive.join(INDEX_FILE))?;
+            }
+        }
+
+        fs::write(&format_path, format!("{}\n", STORAGE_FORMAT_VERSION))?;
+        Ok(())
+    }
+
+    /// Rebuilds the offset index and truncates a half-written final record.
+    ///
+    /// Offsets from the index file are trusted up to the last one, which is re-verified
+    /// together with everything written after it.
+    fn recover(&mut self) -> Result<(), StorageError> {
+        let data_len = self.data.metadata()?.len();
+
+        let mut raw_index = vec![];
+        self.index.seek(SeekFrom::Start(0))?;
+        self.index.read_to_end(&mut raw_index)?;
+        let indexed: Vec<u64> = raw_index
+            .chunks_exact(8)
+            .map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap()))
+            .take_while(|offset| *offset < data_len)
+            .collect();
+
+        let mut offsets = indexed.clone();
+        let mut position = offsets.pop().unwrap_or(0);
+
+        while position < data_len {
+            match self.read_record(position)? {
+                Some((_, next)) => {
+                    offsets.push(position);
+                    position = next;
+                }
+                None => break,
+            }
+        }
+
+        if position < data_len {
+            warn!(
+                "Truncating {} trailing bytes of a half-written block record at offset {}",
+                data_len - position,
+                position
+            );
+            self.data.set_len(position)?;
+            self.data.sync_all()?;
+        }
+
+        if offsets != indexed || raw_index.len() != offsets.len() * 8 {
+            self.rewrite_index(&offsets)?;
+        }
+
+        self.offsets = offsets;
+
+        Ok(())
+    }
+
+    /// Reads a record at `offset`, returning its payload and the offset of the next record.
+    /// Returns `None` if the record is incomplete or does not pass the checksum.
+    fn read_record(&mut self, offset: u64) -> Result<Option<(Vec<u8>, u64)>, StorageError> {
+        let data_len = self.data.metadata()?.len();
+        if offset + RECORD_HEADER_SIZE > data_len {
+            return Ok(None);
+        }
+
+        let mut header = [0u8; RECORD_HEADER_SIZE as usize];
+        self.data.seek(SeekFrom::Start(offset))?;
+        self.data.read_exact(&mut header)?;
+
+        if header[0..4] != RECORD_MAGIC {
+            return Ok(None);
+        }
+
+        let payload_len = u32::from_le_bytes(header[4..8].try_into().unwrap()) as u64;
+        let next = offset + RECORD_HEADER_SIZE + payload_len;
+        if next > data_len {
+            return Ok(None);
+        }
+
+        let mut payload = vec![0u8; payload_len as usize];
+        self.data.read_exact(&mut payload)?;
+
+        if header[8..12] != Self::checksum(&payload) {
+            return Ok(None);
+        }
+
+        Ok(Some((payload, next)))
+    }
+
+    fn rewrite_index(&mut self, offsets: &[u64]) -> Result<(), StorageError> {
+        let bytes: Vec<u8> = offsets.iter().flat_map(|o| o.to_le_bytes()).collect();
+        self.index.set_len(0)?;
+        self.index.seek(SeekFrom::Start(0))?;
+        self.index.write_all(&bytes)?;
+        self.index.sync_all()?;
+        Ok(())
+    }
+
+    /// First 4 bytes of SHA-256 over the record payload
+    fn checksum(payload: &[u8]) -> [u8; 4] {
+        let hash = Sha256::digest(payload);
+        [hash[0], hash[1], hash[2], hash[3]]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::BLOCKCHAIN_POW_LIMIT_BITS;
+
+    fn temp_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("oxidize-storage-{}", uuid::Uuid::new_v4()))
+    }
+
+    fn test_block(previous_hash: &str) -> Block {
+        Block::new(previous_hash, &[], BLOCKCHAIN_POW_LIMIT_BITS).unwrap()
+    }
+
+    #[test]
+    fn it_reloads_appended_blocks() {
+        let dir = temp_dir();
+        let genesis = test_block(&"0".repeat(64));
+        let block = test_block(genesis.header().current_hash());
+
+        {
+            let mut storage = BlockStorage::open(&dir).unwrap();
+            storage.append_block(&genesis).unwrap();
+            storage.append_block(&block).unwrap();
+        }
+
+        let mut storage = BlockStorage::open(&dir).unwrap();
+        let blocks = storage.load_blocks().unwrap();
+
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[1].header().current_hash(), block.header().current_hash());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn it_truncates_half_written_final_block() {
+        let dir = temp_dir();
+        let genesis = test_block(&"0".repeat(64));
+        let block = test_block(genesis.header().current_hash());
+
+        {
+            let mut storage = BlockStorage::open(&dir).unwrap();
+            storage.append_block(&genesis).unwrap();
+            storage.append_block(&block).unwrap();
+        }
+
+        // Simulate a crash in the middle of writing the second record
+        let data_path = dir.join(BLOCKS_FILE);
+        let full_len = fs::metadata(&data_path).unwrap().len();
+        let file = OpenOptions::new().write(true).open(&data_path).unwrap();
+        file.set_len(full_len - 10).unwrap();
+
+        let mut storage = BlockStorage::open(&dir).unwrap();
+        assert_eq!(storage.len(), 1);
+        assert_eq!(storage.load_blocks().unwrap().len(), 1);
+
+        // The storage is usable again after recovery
+        storage.append_block(&block).unwrap();
+        let mut storage = BlockStorage::open(&dir).unwrap();
+        assert_eq!(storage.load_blocks().unwrap().len(), 2);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn it_archives_blocks_of_older_formats() {
+        let dir = temp_dir();
+        {
+            let mut storage = BlockStorage::open(&dir).unwrap();
+            storage.append_block(&test_block(&"0".repeat(64))).unwrap();
+        }
+
+        // Directory written before the format file existed
+        fs::remove_file(dir.join(FORMAT_FILE)).unwrap();
+        let storage = BlockStorage::open(&dir).unwrap();
+        assert!(storage.is_empty());
+        assert!(dir.join("legacy-format-1").join(BLOCKS_FILE).exists());
+        drop(storage);
+
+        let newer = STORAGE_FORMAT_VERSION + 1;
+        fs::write(dir.join(FORMAT_FILE), format!("{}\n", newer)).unwrap();
+        assert!(matches!(
+            BlockStorage::open(&dir),
+            Err(StorageError::UnsupportedFormat { found, .. }) if found == newer
+        ));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,334 @@
+//! # UTXO Set
+//!
+//! Unspent transaction outputs keyed by [`OutPoint`] (`transaction_hash`, `output_index`).
+//!
+//! Connecting a block removes the outputs it spends and adds the outputs it creates,
+//! returning [`BlockUndo`] data. Disconnecting the block with that undo data restores
+//! the set to exactly the state it had before the block was connected.
+
+use std::{
+    collections::{HashMap, HashSet},
+    ops::Add,
+};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use super::Block;
+use crate::{
+    transaction::{OutPoint, SpendContext, UtxoEntry, UtxoView},
+    utils::HashHelper,
+};
+
+/// Errors raised while connecting or disconnecting blocks.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum UtxoError {
+    #[error("Output {0}:{1} is unknown or already spent")]
+    MissingOutput(String, u32),
+    #[error("Undo data does not match the disconnected block")]
+    UndoMismatch,
+}
+
+impl UtxoError {
+    fn missing(outpoint: &OutPoint) -> Self {
+        UtxoError::MissingOutput(HashHelper::to_hex(&outpoint.transaction_hash), outpoint.index)
+    }
+}
+
+/// Outputs spent by a block, in spending order, needed to disconnect it again.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockUndo {
+    pub spent: Vec<(OutPoint, UtxoEntry)>,
+}
+
+/// Balance of an address: confirmed outputs, split by whether they can be spent yet, and
+/// outputs of transactions still pending
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Balance {
+    pub spendable: u64,
+    pub immature: u64, // Coinbase outputs not buried under enough blocks yet
+    #[serde(default)]
+    pub unconfirmed: u64, // Outputs of transactions in the mempool
+}
+
+impl Balance {
+    pub fn total(&self) -> u64 {
+        self.spendable.saturating_add(self.immature).saturating_add(self.unconfirmed)
+    }
+}
+
+/// Both balances together, e.g. of the addresses of an account
+impl Add for Balance {
+    type Output = Balance;
+
+    fn add(self, other: Balance) -> Balance {
+        Balance {
+            spendable: self.spendable.saturating_add(other.spendable),
+            immature: self.immature.saturating_add(other.immature),
+            unconfirmed: self.unconfirmed.saturating_add(other.unconfirmed),
+        }
+    }
+}
+
+/// Set of unspent outputs, with a per-address index for balance lookups.
+#[derive(Debug, Clone, Default)]
+pub struct UtxoSet {
+    entries: HashMap<OutPoint, UtxoEntry>,
+    by_address: HashMap<String, HashSet<OutPoint>>,
+}
+
+impl UtxoSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the unspent output referenced by `outpoint`
+    pub fn get(&self, outpoint: &OutPoint) -> Option<&UtxoEntry> {
+        self.entries.get(outpoint)
+    }
+
+    pub fn contains(&self, outpoint: &OutPoint) -> bool {
+        self.entries.contains_key(outpoint)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Unspent outputs paying to `address`
+    pub fn outputs_for_address(&self, address: &str) -> Vec<(OutPoint, UtxoEntry)> {
+        self.by_address
+            .get(address)
+            .into_iter()
+            .flatten()
+            .filter_map(|outpoint| Some((*outpoint, self.entries.get(outpoint)?.clone())))
+            .collect()
+    }
+
+    /// Sum of the unspent outputs paying to `address`
+    pub fn balance(&self, address: &str) -> u64 {
+        self.outputs_for_address(address)
+            .iter()
+            .map(|(_, entry)| entry.output.amount)
+            .sum()
+    }
+
+    /// Balance of `address`, split by whether its outputs could be spent by
+    /// the block described by `context`
+    pub fn balance_at(&self, address: &str, context: SpendContext) -> Balance {
+        let mut balance = Balance::default();
+        for (_, entry) in self.outputs_for_address(address) {
+            if entry.is_mature(context) {
+                balance.spendable += entry.output.amount;
+            } else {
+                balance.immature += entry.output.amount;
+            }
+        }
+        balance
+    }
+
+    /// Spends the inputs and adds the outputs of every transaction of `block`, at `height`.
+    ///
+    /// The block is connected atomically: if any input references a missing output,
+    /// the set is left untouched and an error is returned.
+    pub fn connect_block(&mut self, block: &Block, height: u64) -> Result<BlockUndo, UtxoError> {
+        // Dry run, taking outputs created earlier in the same block into account
+        let mut created = HashSet::new();
+        let mut spent = HashSet::new();
+        for transaction in block.body().transactions() {
+            if !transaction.is_coinbase() {
+                for input in transaction.inputs() {
+                    let outpoint = input.outpoint();
+                    let available = created.remove(&outpoint)
+                        || (self.entries.contains_key(&outpoint) && !spent.contains(&outpoint));
+                    if !available {
+                        return Err(UtxoError::missing(&outpoint));
+                    }
+                    spent.insert(outpoint);
+                }
+            }
+            for index in 0..transaction.outputs().len() {
+                created.insert(OutPoint {
+                    transaction_hash: transaction.metadata().transaction_hash,
+                    index: index as u32,
+                });
+            }
+        }
+
+        let mut undo = BlockUndo::default();
+        for transaction in block.body().transactions() {
+            if !transaction.is_coinbase() {
+                for input in transaction.inputs() {
+                    let outpoint = input.outpoint();
+                    let entry = self.remove(&outpoint).expect("checked by the dry run");
+                    undo.spent.push((outpoint, entry));
+                }
+            }
+
+            for (index, output) in transaction.outputs().iter().enumerate() {
+                let outpoint = OutPoint {
+                    transaction_hash: transaction.metadata().transaction_hash,
+                    index: index as u32,
+                };
+                let entry = UtxoEntry {
+                    output: output.clone(),
+                    height,
+                    is_coinbase: transaction.is_coinbase(),
+                };
+                self.insert(outpoint, entry);
+            }
+        }
+
+        Ok(undo)
+    }
+
+    /// Reverts `connect_block`: removes the outputs created by `block`
+    /// and restores the outputs it spent from `undo`.
+    pub fn disconnect_block(&mut self, block: &Block, undo: BlockUndo) -> Result<(), UtxoError> {
+        let spent_count: usize = block
+            .body()
+            .transactions()
+            .iter()
+            .filter(|transaction| !transaction.is_coinbase())
+            .map(|transaction| transaction.inputs().len())
+            .sum();
+        if spent_count != undo.spent.len() {
+            return Err(UtxoError::UndoMismatch);
+        }
+
+        let created_by_block: HashSet<[u8; 32]> = block
+            .body()
+            .transactions()
+            .iter()
+            .map(|transaction| transaction.metadata().transaction_hash)
+            .collect();
+
+        for transaction in block.body().transactions() {
+            for index in 0..transaction.outputs().len() {
+                self.remove(&OutPoint {
+                    transaction_hash: transaction.metadata().transaction_hash,
+                    index: index as u32,
+                });
+            }
+        }
+
+        // Outputs both created and spent within the block stay gone
+        for (outpoint, entry) in undo.spent {
+            if !created_by_block.contains(&outpoint.transaction_hash) {
+                self.insert(outpoint, entry);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn insert(&mut self, outpoint: OutPoint, entry: UtxoEntry) {
+        self.by_address
+            .entry(entry.output.recipient_address.clone())
+            .or_default()
+            .insert(outpoint);
+        self.entries.insert(outpoint, entry);
+    }
+
+    fn remove(&mut self, outpoint: &OutPoint) -> Option<UtxoEntry> {
+        let entry = self.entries.remove(outpoint)?;
+        if let Some(outpoints) = self.by_address.get_mut(&entry.output.recipient_address) {
+            outpoints.remove(outpoint);
+            if outpoints.is_empty() {
+                self.by_address.remove(&entry.output.recipient_address);
+            }
+        }
+        Some(entry)
+    }
+}
+
+impl UtxoView for UtxoSet {
+    fn unspent_output(&self, outpoint: &OutPoint) -> Option<UtxoEntry> {
+        self.entries.get(outpoint).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::BLOCKCHAIN_POW_LIMIT_BITS;
+    use crate::transaction::test_utils::{coinbase, owner, pay};
+
+    #[test]
+    fn it_connects_and_disconnects_blocks() {
+        let miner = owner();
+        let genesis_coinbase = coinbase(50);
+        let genesis = Block::new(
+            &"0".repeat(64),
+            std::slice::from_ref(&genesis_coinbase),
+            BLOCKCHAIN_POW_LIMIT_BITS,
+        ).unwrap();
+
+        let mut utxos = UtxoSet::new();
+        utxos.connect_block(&genesis, 0).unwrap();
+        let before = utxos.clone();
+        assert_eq!(utxos.balance(&miner), 50);
+
+        // Spends the genesis coinbase, and spends that output again within the block
+        let payment = pay(&genesis_coinbase, "alice", 40);
+        let chained = pay(&payment, "bob", 35);
+        let block = Block::new(
+            genesis.header().current_hash(),
+            &[coinbase(20), payment, chained],
+            BLOCKCHAIN_POW_LIMIT_BITS,
+        ).unwrap();
+
+        let undo = utxos.connect_block(&block, 1).unwrap();
+        assert_eq!(undo.spent.len(), 2);
+        assert_eq!(utxos.balance(&miner), 20);
+        assert_eq!(utxos.balance("alice"), 0);
+        assert_eq!(utxos.balance("bob"), 35);
+
+        // The block coinbase is one block deep at height 2
+        let context = SpendContext {
+            height: 2,
+            coinbase_maturity: 2,
+        };
+        let reward = utxos.balance_at(&miner, context);
+        assert_eq!((reward.spendable, reward.immature), (0, 20));
+        assert_eq!(utxos.balance_at("bob", context).spendable, 35);
+
+        utxos.disconnect_block(&block, undo).unwrap();
+        assert_eq!(utxos.entries, before.entries);
+        assert_eq!(utxos.balance(&miner), 50);
+        assert_eq!(utxos.balance("bob"), 0);
+    }
+
+    #[test]
+    fn it_refuses_blocks_spending_missing_outputs_atomically() {
+        let miner = owner();
+        let genesis_coinbase = coinbase(50);
+        let genesis = Block::new(
+            &"0".repeat(64),
+            std::slice::from_ref(&genesis_coinbase),
+            BLOCKCHAIN_POW_LIMIT_BITS,
+        ).unwrap();
+
+        let mut utxos = UtxoSet::new();
+        utxos.connect_block(&genesis, 0).unwrap();
+
+        let payment = pay(&genesis_coinbase, "alice", 40);
+        let double_spend = pay(&genesis_coinbase, "bob", 30);
+        let block = Block::new(
+            genesis.header().current_hash(),
+            &[coinbase(20), payment, double_spend],
+            BLOCKCHAIN_POW_LIMIT_BITS,
+        ).unwrap();
+
+        assert!(matches!(
+            utxos.connect_block(&block, 1),
+            Err(UtxoError::MissingOutput(_, 0))
+        ));
+        assert_eq!(utxos.len(), 1);
+        assert_eq!(utxos.balance(&miner), 50);
+    }
+}
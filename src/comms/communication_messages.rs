@@ -1,6 +1,7 @@
 use std::fmt;
 
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(tag = "r#type", rename_all = "snake_case")]
@@ -35,7 +36,8 @@ pub enum EventTopic {
     NewBlock,
     TxConfirmed,
     MempoolTxAdded,
-    MempoolTxRemoved
+    MempoolTxRemoved,
+    ChainReorg
 }
 
 impl fmt::Display for EventTopic {
@@ -47,14 +49,29 @@ impl fmt::Display for EventTopic {
             EventTopic::TxConfirmed => write!(f, "tx_confirmed"),
             EventTopic::MempoolTxAdded => write!(f, "mempool_tx_added"),
             EventTopic::MempoolTxRemoved => write!(f, "mempool_tx_removed"),
+            EventTopic::ChainReorg => write!(f, "chain_reorg"),
         }
     }
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+/// Event published by the node and forwarded to clients subscribed to its topic.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NodeEvent {
+    pub topic: EventTopic,
+    pub data: Value,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
 pub enum RequestType {
-    GetBalance,
+    GetBalance, // Balance of `{"address": ...}`, or of `{"addresses": [...]}` together
+    GetUtxos,   // Unspent outputs of `{"addresses": [...]}`, answered with a `UtxosResponse`
+    GetHistory, // Mined and pending transactions of `{"addresses": [...]}`, as `HistoryEntry`s
     SubmitTransaction,
-    GetMempool
+    GetMempool,
+    GetTransactionStatus, // Pending or mined status of `{"transaction_hash": ...}`
+    EstimateFee, // Fee rate to be mined within `{"target_blocks": ...}`, per 1000 bytes
+    GetSupply, // Coins issued up to `{"height": ...}`, the chain tip by default
+    GetBlockTemplate, // Work for an external miner, answered with a `BlockTemplateResponse`
+    SubmitBlock       // Block solved by an external miner
 }
    
@@ -4,23 +4,95 @@
 //! These are static, deterministic, and compile-time fixed.
 //! 
 
-/// Initial proof-of-work difficulty.
-pub const BLOCKCHAIN_INITIAL_DIFFICULTY: u8 = 2;
+/// Initial proof-of-work target, in compact form (about 1 in 256 hashes).
+pub const BLOCKCHAIN_INITIAL_BITS: u32 = 0x2000_ffff;
+
+/// Easiest proof-of-work target allowed, in compact form.
+pub const BLOCKCHAIN_POW_LIMIT_BITS: u32 = 0x207f_ffff;
+
+/// Time the network aims to take to mine a block, in seconds.
+pub const BLOCKCHAIN_TARGET_BLOCK_TIME_SECS: u64 = 60;
+
+/// Number of blocks between two difficulty adjustments.
+pub const BLOCKCHAIN_RETARGET_WINDOW: u64 = 10;
+
+/// Number of previous blocks whose median timestamp a new block must be later than.
+pub const BLOCKCHAIN_MEDIAN_TIME_SPAN: u64 = 11;
+
+/// How far ahead of the node clock a block timestamp may be, in seconds.
+pub const BLOCKCHAIN_MAX_FUTURE_BLOCK_TIME_SECS: i64 = 2 * 60 * 60;
 
 /// Starting nonce for mining.
 pub const BLOCKCHAIN_INITIAL_NONCE: u64 = 0;
 
-/// Total supply of Oxcoin.
+/// Total supply of Oxcoin, block subsidies stop once it is issued.
 pub const BLOCKCHAIN_OXCOIN_SUPPLY: u64 = 1_000_000;
 
-/// Reward for mining a standard block.
+/// Reward for mining a standard block, before the first halving.
 pub const BLOCKCHAIN_COINBASE_BLOCK_FEE: u64 = 20;
 
+/// Number of blocks after which the block reward is halved.
+pub const BLOCKCHAIN_HALVING_INTERVAL: u64 = 25_000;
+
 /// Reward for mining the genesis block.
 pub const BLOCKCHAIN_COINBASE_GENESIS_BLOCK_FEE: u64 = 1_000;
 
-/// Transaction fee for standard transactions.
-pub const BLOCKCHAIN_TRANSACTION_FEE: u8 = 1;
+/// Blocks a coinbase output must be buried under before it can be spent.
+pub const BLOCKCHAIN_COINBASE_MATURITY: u64 = 100;
+
+/// Maximum size of a block's transactions (including coinbase), in bytes.
+pub const BLOCKCHAIN_MAX_BLOCK_SIZE: usize = 1_000_000;
+
+/// Share of the block size limit pending transactions should fill before mining, in percent.
+pub const MINING_MIN_BLOCK_FILL_PERCENT: u8 = 70;
+
+/// Nonces tried per header before the miner refreshes its timestamp and extra nonce.
+pub const MINING_NONCE_SPACE: u64 = u32::MAX as u64;
+
+/// Maximum size of a single transaction, in bytes.
+pub const TRANSACTION_MAX_SIZE: usize = 100_000;
+
+/// Outputs paying less than this amount are considered dust and refused.
+pub const TRANSACTION_DUST_THRESHOLD: u64 = 1;
+
+/// Minimum fee rate relayed by the mempool, per 1000 bytes of serialized transaction.
+pub const BLOCKCHAIN_TRANSACTION_FEE: u64 = 1;
+
+/// Number of recent blocks the fee estimator learns from.
+pub const FEE_ESTIMATOR_BLOCKS: usize = 20;
+
+/// Maximum number of transactions kept in the mempool.
+pub const MEMPOOL_MAX_TRANSACTIONS: usize = 5_000;
+
+/// Maximum total size of the mempool transactions, in bytes.
+pub const MEMPOOL_MAX_SIZE_BYTES: usize = 5_000_000;
+
+/// Time after which a pending transaction is dropped from the mempool, in seconds.
+pub const MEMPOOL_EXPIRY_SECS: u64 = 60 * 60 * 24;
+
+/// BIP44 coin type of Oxcoin keys, in `m/44'/coin_type'/account'/change/index`.
+pub const WALLET_BIP44_COIN_TYPE: u32 = 1;
+
+/// Unused addresses a wallet account may hand out in a row, on each chain.
+pub const WALLET_GAP_LIMIT: u32 = 20;
+
+/// Memory used by Argon2id to derive a wallet file key from its password, in KiB.
+pub const WALLET_KDF_MEMORY_KIB: u32 = 19 * 1024;
+
+/// Argon2id passes over that memory.
+pub const WALLET_KDF_ITERATIONS: u32 = 2;
+
+/// Argon2id lanes.
+pub const WALLET_KDF_PARALLELISM: u32 = 1;
+
+/// Time a wallet waits for the node to answer a request, in seconds.
+pub const WALLET_REQUEST_TIMEOUT_SECS: u64 = 30;
+
+/// Default directory where the node persists its chain data.
+pub const BLOCKCHAIN_DATA_DIR: &str = "./data";
+
+/// Environment variable holding the password of the node wallet file.
+pub const NODE_WALLET_PASSWORD_ENV: &str = "OXIDIZE_WALLET_PASSWORD";
 
 /// WebSocket URI for blockchain network communication.
 pub const WEBSOCKET_URI: &str = "localhost:8080";
@@ -6,7 +6,7 @@
 //!
 //! ```rust
 //! use oxidize::config::*;
-//! println!("Difficulty: {}", BLOCKCHAIN_INITIAL_DIFFICULTY);
+//! println!("Initial target bits: {:#010x}", BLOCKCHAIN_INITIAL_BITS);
 //! ```
 //!
 //! ## Exports
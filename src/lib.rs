@@ -5,4 +5,6 @@ pub mod wallet;
 pub mod transaction;
 pub mod websockets;
 pub mod comms;
-pub mod logger;
\ No newline at end of file
+pub mod logger;
+pub mod mempool;
+pub mod miner;
\ No newline at end of file
@@ -12,6 +12,7 @@
 //! ## Exports
 //! - [`init_logging`]: Initializes the global tracing subscriber.
 //! 
+#[allow(clippy::module_inception)]
 mod logger;
 
 pub use logger::init_logging;
\ No newline at end of file
@@ -1,17 +1,18 @@
 use std::error::Error;
 
 use oxidize::{
-    blockchain::{Blockchain, BlockchainConfig}, config::WEBSOCKET_URI, logger::init_logging, wallet::Wallet
+    blockchain::{Blockchain, BlockchainConfig}, config::{BLOCKCHAIN_TRANSACTION_FEE, WEBSOCKET_URI},
+    logger::init_logging, mempool::FeeRate, wallet::Wallet
 };
-use tracing::info;
-
-const NUMBER_OF_BLOCKS: u16 = 4;
+use tracing::{info, warn};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
     let _guard = init_logging();
     
-    let config = BlockchainConfig::new(false);
+    let mut config = BlockchainConfig::new(false);
+    // Mine in the background once the node runs, see `MinerConfig::policy`
+    config.miner.autostart = true;
 
     let mut node = Blockchain::build(config)
         .await
@@ -21,58 +22,60 @@ async fn main() -> Result<(), Box<dyn Error>> {
         "Initiated blockchain, with genesis block"
     );
     info!("{:?}", node.config());
-
-    // let ws_port = node.config().
-
-    info!("Creating {} blocks", NUMBER_OF_BLOCKS);
-    for _ in 1..=NUMBER_OF_BLOCKS {
-        node.add_block().await;
+    if let Some(mnemonic) = node.take_wallet_mnemonic() {
+        warn!("Created the node wallet, write down its recovery phrase: {}", mnemonic);
     }
 
+    // Validate the chain reloaded from disk, from the first mined block to the tip
     let blocks = node.blocks().clone();
-    let block_1 = blocks.get(1).unwrap().clone();
-    let block_2 = blocks.get(3).unwrap().clone();
-
-    match node.validate_single_block(block_1.header().current_hash()) {
-        Err(e) => info!(
-            "Error on validating the block {} with error {}",
-            block_1.header().current_hash(),
-            e
-        ),
-        Ok(_) => info!("{}", "Block validated"),
-    }
+    if let (Some(block_1), Some(tip)) = (blocks.get(1), blocks.last()) {
+        match node.validate_single_block(block_1.header().current_hash()) {
+            Err(e) => info!(
+                "Error on validating the block {} with error {}",
+                block_1.header().current_hash(),
+                e
+            ),
+            Ok(_) => info!("{}", "Block validated"),
+        }
 
-    match node.validate_full_chain() {
-        Err(e) => info!("Error validating full chain with error {}", e),
-        Ok(_) => info!("{}", "Full chain validated"),
-    }
+        match node.validate_full_chain() {
+            Err(e) => info!("Error validating full chain with error {}", e),
+            Ok(_) => info!("{}", "Full chain validated"),
+        }
 
-    match node.validate_range_chain(
-        block_1.header().current_hash(),
-        block_2.header().current_hash(),
-    ) {
-        Err(e) => info!("Error validating chain range with error {}", e),
-        Ok(_) => info!(
-            "{} from: {} to {}",
-            "Chain range validated",
+        match node.validate_range_chain(
             block_1.header().current_hash(),
-            block_2.header().current_hash()
-        ),
+            tip.header().current_hash(),
+        ) {
+            Err(e) => info!("Error validating chain range with error {}", e),
+            Ok(_) => info!(
+                "{} from: {} to {}",
+                "Chain range validated",
+                block_1.header().current_hash(),
+                tip.header().current_hash()
+            ),
+        }
     }
 
     info!("{}", "Creating 2 wallets");
     let mut wallet1 = Wallet::new("Wallet#1".to_string(), WEBSOCKET_URI.to_string()).await;
-    wallet1.create_new_account("MainAccount");
-    wallet1.create_new_account("SecondAccount");
+    wallet1.create_new_account("MainAccount")?;
+    wallet1.create_new_account("SecondAccount")?;
 
     let mut wallet2 = Wallet::new("Wallet#2".to_string(), WEBSOCKET_URI.to_string()).await;
-    wallet2.create_new_account("MiceAccount");
-    wallet2.create_new_account("CheeseAccount");
+    wallet2.create_new_account("MiceAccount")?;
+    wallet2.create_new_account("CheeseAccount")?;
     let wallet2_account = wallet2.find_account("MiceAccount")?.address().to_string();
     
-    wallet1.initiate_payment("MainAccount", &wallet2_account, 5).await?;
-    wallet1.initiate_payment("SecondAccount", &wallet2_account, 25).await?;
-    wallet1.initiate_payment("SecondAccount", &wallet2_account, 15).await?;
+
+    // New wallets have no funds yet, so these payments are refused until the wallet is paid
+    let fee_rate = FeeRate(BLOCKCHAIN_TRANSACTION_FEE);
+    for (account, amount) in [("MainAccount", 5), ("SecondAccount", 25), ("SecondAccount", 15)] {
+        let payment = wallet1.initiate_payment(account, &wallet2_account, amount, fee_rate);
+        if let Err(e) = payment.await {
+            info!("Payment of {} from {} not sent: {}", amount, account, e);
+        }
+    }
     // dbg!(node);
     // dbg!(
     //     wallet1.id,
@@ -87,7 +90,12 @@ async fn main() -> Result<(), Box<dyn Error>> {
     //     wallet2.accounts
     // );
 
-    loop {
-        // DO STUFF
+    // Process wallet requests and mine until the node is stopped
+    tokio::select! {
+        _ = node.run() => {}
+        _ = tokio::signal::ctrl_c() => {}
     }
+    node.shutdown().await;
+
+    Ok(())
 }
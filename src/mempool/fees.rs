@@ -0,0 +1,202 @@
+//! # Fees
+//!
+//! Fee rates and fee estimation.
+//!
+//! A transaction fee is what its inputs leave after paying its outputs. Fees are compared
+//! by [`FeeRate`], the fee per 1000 bytes of serialized transaction, since that is what a
+//! transaction costs in block space.
+//!
+//! The [`FeeEstimator`] suggests the fee rate a new transaction should pay to be mined
+//! within a number of blocks, from two sources:
+//! - recent blocks: the lowest fee rate each full block accepted. Paying the `n`-th highest
+//!   of these would have been enough for all but `n - 1` of the recent blocks.
+//! - the mempool: pending transactions paying more would fill the next `n` blocks first.
+//!
+//! The suggestion is never below the minimum relay fee rate of the mempool.
+
+use std::collections::VecDeque;
+
+use serde::{Deserialize, Serialize};
+
+use super::Mempool;
+use crate::transaction::Transaction;
+
+/// Share of the block size limit a block must fill for its lowest fee rate to matter,
+/// below that any transaction paying the relay fee would have fit, in percent.
+const FULL_BLOCK_PERCENT: usize = 90;
+
+/// Fee per 1000 bytes of serialized transaction
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(transparent)]
+pub struct FeeRate(pub u64);
+
+impl FeeRate {
+    pub const ZERO: FeeRate = FeeRate(0);
+
+    /// Fee rate of a transaction of `size` bytes paying `fee`, rounded down
+    pub fn of(fee: u64, size: usize) -> Self {
+        let rate = u128::from(fee) * 1_000 / size.max(1) as u128;
+        FeeRate(u64::try_from(rate).unwrap_or(u64::MAX))
+    }
+
+    /// Fee a transaction of `size` bytes pays at this rate, rounded up
+    pub fn fee_for(&self, size: usize) -> u64 {
+        let fee = (u128::from(self.0) * size as u128).div_ceil(1_000);
+        u64::try_from(fee).unwrap_or(u64::MAX)
+    }
+}
+
+/// Lowest fee rate a block accepted, `ZERO` when the block had room to spare
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct BlockFeeStats {
+    height: u64,
+    threshold: FeeRate,
+}
+
+/// Suggests fee rates from the last `window` blocks of the active chain and the mempool
+#[derive(Debug, Clone)]
+pub struct FeeEstimator {
+    window: usize,
+    blocks: VecDeque<BlockFeeStats>, // Ascending height
+}
+
+impl FeeEstimator {
+    pub fn new(window: usize) -> Self {
+        Self {
+            window: window.max(1),
+            blocks: VecDeque::new(),
+        }
+    }
+
+    /// Records the fee rates accepted by the block at `height`, given its `transactions`
+    /// (coinbase first) and the block size limit
+    pub fn record_block(
+        &mut self,
+        height: u64,
+        transactions: &[Transaction],
+        max_block_size: usize,
+    ) {
+        let size: usize = transactions.iter().map(|t| t.size()).sum();
+        let full = size.saturating_mul(100) >= max_block_size.saturating_mul(FULL_BLOCK_PERCENT);
+        let threshold = if full {
+            transactions
+                .iter()
+                .filter(|t| !t.is_coinbase())
+                .map(|t| FeeRate::of(t.fee(), t.size()))
+                .min()
+                .unwrap_or(FeeRate::ZERO)
+        } else {
+            FeeRate::ZERO
+        };
+
+        self.disconnect_from(height);
+        self.blocks.push_back(BlockFeeStats { height, threshold });
+        while self.blocks.len() > self.window {
+            self.blocks.pop_front();
+        }
+    }
+
+    /// Forgets the blocks from `height` up, once they left the active chain
+    pub fn disconnect_from(&mut self, height: u64) {
+        while self.blocks.back().is_some_and(|stats| stats.height >= height) {
+            self.blocks.pop_back();
+        }
+    }
+
+    /// Fee rate a transaction should pay to be mined within `target_blocks` blocks
+    pub fn estimate(
+        &self,
+        target_blocks: usize,
+        mempool: &Mempool,
+        max_block_size: usize,
+    ) -> FeeRate {
+        let target_blocks = target_blocks.max(1);
+        let relay = mempool.config().min_relay_fee_rate;
+
+        let mut thresholds: Vec<FeeRate> = self.blocks.iter().map(|s| s.threshold).collect();
+        thresholds.sort_unstable_by(|a, b| b.cmp(a));
+        let recent = thresholds
+            .get(target_blocks - 1)
+            .or(thresholds.last())
+            .copied()
+            .unwrap_or(FeeRate::ZERO);
+
+        // Outbid the first pending transaction that would not fit in the next blocks
+        let space = max_block_size.saturating_mul(target_blocks);
+        let mut pending_size = 0;
+        let mut pending = FeeRate::ZERO;
+        for entry in mempool.entries_by_fee_rate() {
+            pending_size += entry.size();
+            if pending_size > space {
+                pending = FeeRate(entry.fee_rate().0.saturating_add(1));
+                break;
+            }
+        }
+
+        relay.max(recent).max(pending)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::broadcast;
+
+    use super::*;
+    use crate::mempool::MempoolConfig;
+    use crate::transaction::{test_utils::spend, OutPoint};
+
+    /// Spends 1000 from the transaction with `transaction_hash`, paying `fee`
+    fn transaction(transaction_hash: [u8; 32], fee: u64) -> Transaction {
+        let outpoint = OutPoint {
+            transaction_hash,
+            index: 0,
+        };
+        spend(outpoint, 1_000, &[1_000 - fee])
+    }
+
+    #[test]
+    fn it_converts_fees_and_rates() {
+        assert_eq!(FeeRate::of(3, 1_500), FeeRate(2));
+        assert_eq!(FeeRate(2).fee_for(1_500), 3);
+        assert_eq!(FeeRate(1).fee_for(1), 1);
+        assert_eq!(FeeRate::ZERO.fee_for(1_000), 0);
+    }
+
+    #[test]
+    fn it_estimates_from_recent_blocks_and_the_mempool() {
+        let (events, _) = broadcast::channel(16);
+        let mut mempool = Mempool::new(MempoolConfig::default(), events);
+        let relay = mempool.config().min_relay_fee_rate;
+        let mut estimator = FeeEstimator::new(10);
+        assert_eq!(estimator.estimate(1, &mempool, 1_000), relay);
+
+        // Full blocks, the lowest accepted rates being those of the only transaction
+        let cheap = transaction([1u8; 32], 10);
+        let expensive = transaction([2u8; 32], 50);
+        estimator.record_block(1, std::slice::from_ref(&cheap), cheap.size());
+        estimator.record_block(2, std::slice::from_ref(&expensive), expensive.size());
+        let expensive_rate = FeeRate::of(50, expensive.size());
+        assert_eq!(estimator.estimate(1, &mempool, 1_000_000), expensive_rate);
+        assert_eq!(
+            estimator.estimate(2, &mempool, 1_000_000),
+            FeeRate::of(10, cheap.size())
+        );
+
+        // Only one pending transaction fits a block, the next one has to be outbid
+        mempool.add(transaction([3u8; 32], 100), 100).unwrap();
+        mempool.add(transaction([4u8; 32], 100), 100).unwrap();
+        let (first, next) = {
+            let entries = mempool.entries_by_fee_rate();
+            (entries[0].size(), entries[1].fee_rate())
+        };
+        assert_eq!(estimator.estimate(1, &mempool, first), FeeRate(next.0 + 1));
+
+        estimator.disconnect_from(2);
+        assert_eq!(
+            estimator.estimate(1, &mempool, 1_000_000),
+            FeeRate::of(10, cheap.size())
+        );
+    }
+}
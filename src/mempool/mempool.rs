@@ -0,0 +1,473 @@
+//! # Mempool
+//!
+//! Holds pending transactions until they are included in a block.
+
+use std::{
+    cmp::Ordering,
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+use serde_json::json;
+use thiserror::Error;
+use tokio::sync::broadcast;
+
+use super::FeeRate;
+
+use crate::{
+    comms::{EventTopic, NodeEvent},
+    config::{
+        BLOCKCHAIN_TRANSACTION_FEE, MEMPOOL_EXPIRY_SECS, MEMPOOL_MAX_SIZE_BYTES,
+        MEMPOOL_MAX_TRANSACTIONS,
+    },
+    transaction::{OutPoint, Transaction, TransactionValidationError},
+    utils::HashHelper,
+};
+
+/// Limits of the mempool.
+#[derive(Debug, Clone)]
+pub struct MempoolConfig {
+    pub max_transactions: usize,
+    pub max_size_bytes: usize,
+    pub expiry: Duration,
+    pub min_relay_fee_rate: FeeRate, // Lowest fee rate admitted
+}
+
+impl Default for MempoolConfig {
+    fn default() -> Self {
+        Self {
+            max_transactions: MEMPOOL_MAX_TRANSACTIONS,
+            max_size_bytes: MEMPOOL_MAX_SIZE_BYTES,
+            expiry: Duration::from_secs(MEMPOOL_EXPIRY_SECS),
+            min_relay_fee_rate: FeeRate(BLOCKCHAIN_TRANSACTION_FEE),
+        }
+    }
+}
+
+/// Errors returned when a transaction is refused by the mempool.
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum MempoolError {
+    #[error("Transaction is already in the mempool")]
+    AlreadyExists,
+    #[error("Transaction spends an output already spent by pending transaction {0}")]
+    Conflict(String),
+    #[error("Transaction spends an output of pending transaction {0}")]
+    UnconfirmedParent(String),
+    #[error("Transaction is larger than the whole mempool")]
+    Oversize,
+    #[error("Transaction fee {fee} is below the minimum relay fee {required}")]
+    FeeBelowMinimum { fee: u64, required: u64 },
+    #[error("Mempool is full and the transaction fee rate is too low")]
+    FeeTooLow,
+    #[error("Invalid transaction: {0}")]
+    Invalid(#[from] TransactionValidationError),
+}
+
+/// Why a transaction left the mempool.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RemovalReason {
+    Mined,
+    Expired,
+    Evicted,
+    Conflict,
+    Invalid,
+}
+
+/// A pending transaction together with its fee and admission time.
+#[derive(Debug, Clone)]
+pub struct MempoolEntry {
+    transaction: Transaction,
+    fee: u64,
+    size: usize,
+    added_at: Instant,
+}
+
+impl MempoolEntry {
+    pub fn transaction(&self) -> &Transaction {
+        &self.transaction
+    }
+
+    pub fn fee(&self) -> u64 {
+        self.fee
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn added_at(&self) -> Instant {
+        self.added_at
+    }
+
+    /// Fee rate of the transaction, per 1000 bytes
+    pub fn fee_rate(&self) -> FeeRate {
+        FeeRate::of(self.fee, self.size)
+    }
+
+    /// Compares fee rates without floating point rounding (`fee_a / size_a` vs `fee_b / size_b`)
+    pub fn cmp_fee_rate(&self, other: &MempoolEntry) -> Ordering {
+        let lhs = self.fee as u128 * other.size.max(1) as u128;
+        let rhs = other.fee as u128 * self.size.max(1) as u128;
+        lhs.cmp(&rhs)
+    }
+}
+
+/// Pending transactions chosen for a new block, in inclusion order.
+#[derive(Debug, Clone, Default)]
+pub struct BlockSelection {
+    pub transactions: Vec<Transaction>,
+    pub fees: u64,
+    pub size: usize,
+}
+
+/// Pending transactions keyed by `transaction_hash`, with spent outpoint tracking.
+#[derive(Debug, Clone)]
+pub struct Mempool {
+    entries: HashMap<[u8; 32], MempoolEntry>,
+    spent_outpoints: HashMap<OutPoint, [u8; 32]>, // Outpoint -> hash of its pending spender
+    total_size: usize,
+    config: MempoolConfig,
+    events: broadcast::Sender<NodeEvent>,
+}
+
+impl Mempool {
+    /// Creates an empty mempool publishing its events to `events`
+    pub fn new(config: MempoolConfig, events: broadcast::Sender<NodeEvent>) -> Self {
+        Self {
+            entries: HashMap::new(),
+            spent_outpoints: HashMap::new(),
+            total_size: 0,
+            config,
+            events,
+        }
+    }
+
+    /// Adds a transaction paying `fee` to the mempool.
+    /// If the mempool is full, lower fee-rate transactions are evicted to make room.
+    /// Transactions spending outputs of other pending transactions are refused, so no entry
+    /// ever depends on another one and entries can be removed on their own.
+    pub fn add(&mut self, transaction: Transaction, fee: u64) -> Result<(), MempoolError> {
+        let hash = transaction.metadata().transaction_hash;
+
+        if self.entries.contains_key(&hash) {
+            return Err(MempoolError::AlreadyExists);
+        }
+
+        for input in transaction.inputs() {
+            if let Some(spender) = self.spent_outpoints.get(&input.outpoint()) {
+                return Err(MempoolError::Conflict(HashHelper::to_hex(spender)));
+            }
+            if self.entries.contains_key(&input.previous_tx_hash) {
+                return Err(MempoolError::UnconfirmedParent(HashHelper::to_hex(
+                    &input.previous_tx_hash,
+                )));
+            }
+        }
+
+        let entry = MempoolEntry {
+            size: transaction.size(),
+            transaction,
+            fee,
+            added_at: Instant::now(),
+        };
+
+        if entry.size > self.config.max_size_bytes {
+            return Err(MempoolError::Oversize);
+        }
+
+        let required = self.config.min_relay_fee_rate.fee_for(entry.size);
+        if fee < required {
+            return Err(MempoolError::FeeBelowMinimum { fee, required });
+        }
+
+        // Find which entries would have to go, before touching the pool
+        let mut evicted = vec![];
+        let mut count = self.entries.len() + 1;
+        let mut size = self.total_size + entry.size;
+        let mut by_fee_rate: Vec<&MempoolEntry> = self.entries.values().collect();
+        by_fee_rate.sort_by(|a, b| a.cmp_fee_rate(b));
+
+        for lowest in by_fee_rate {
+            if count <= self.config.max_transactions && size <= self.config.max_size_bytes {
+                break;
+            }
+            if entry.cmp_fee_rate(lowest) != Ordering::Greater {
+                return Err(MempoolError::FeeTooLow);
+            }
+            evicted.push(lowest.transaction.metadata().transaction_hash);
+            count -= 1;
+            size -= lowest.size;
+        }
+
+        if count > self.config.max_transactions || size > self.config.max_size_bytes {
+            return Err(MempoolError::FeeTooLow);
+        }
+
+        for evicted_hash in evicted {
+            self.remove(&evicted_hash, RemovalReason::Evicted);
+        }
+
+        for input in entry.transaction.inputs() {
+            self.spent_outpoints.insert(input.outpoint(), hash);
+        }
+        self.total_size += entry.size;
+
+        self.publish(
+            EventTopic::MempoolTxAdded,
+            json!({
+                "transaction_hash": HashHelper::to_hex(&hash),
+                "fee": entry.fee,
+                "size": entry.size,
+            }),
+        );
+        self.entries.insert(hash, entry);
+
+        Ok(())
+    }
+
+    /// Removes a transaction from the mempool, returning it if it was present
+    pub fn remove(&mut self, hash: &[u8; 32], reason: RemovalReason) -> Option<Transaction> {
+        let entry = self.entries.remove(hash)?;
+
+        for input in entry.transaction.inputs() {
+            self.spent_outpoints.remove(&input.outpoint());
+        }
+        self.total_size -= entry.size;
+
+        self.publish(
+            EventTopic::MempoolTxRemoved,
+            json!({
+                "transaction_hash": HashHelper::to_hex(hash),
+                "reason": reason,
+            }),
+        );
+
+        Some(entry.transaction)
+    }
+
+    /// Removes pending transactions spending any of the given outpoints
+    /// (e.g. because a block spent them first)
+    pub fn remove_conflicts(&mut self, outpoints: &[OutPoint]) -> Vec<Transaction> {
+        let conflicting: Vec<[u8; 32]> = outpoints
+            .iter()
+            .filter_map(|outpoint| self.spent_outpoints.get(outpoint).copied())
+            .collect();
+
+        conflicting
+            .iter()
+            .filter_map(|hash| self.remove(hash, RemovalReason::Conflict))
+            .collect()
+    }
+
+    /// Drops every entry older than the configured expiry
+    pub fn expire(&mut self, now: Instant) -> Vec<Transaction> {
+        let expired: Vec<[u8; 32]> = self
+            .entries
+            .iter()
+            .filter(|(_, entry)| now.duration_since(entry.added_at) >= self.config.expiry)
+            .map(|(hash, _)| *hash)
+            .collect();
+
+        expired
+            .iter()
+            .filter_map(|hash| self.remove(hash, RemovalReason::Expired))
+            .collect()
+    }
+
+    /// Returns the entry with the given transaction hash
+    pub fn get(&self, hash: &[u8; 32]) -> Option<&MempoolEntry> {
+        self.entries.get(hash)
+    }
+
+    pub fn contains(&self, hash: &[u8; 32]) -> bool {
+        self.entries.contains_key(hash)
+    }
+
+    /// Returns the hash of the pending transaction spending `outpoint`, if any
+    pub fn spender_of(&self, outpoint: &OutPoint) -> Option<&[u8; 32]> {
+        self.spent_outpoints.get(outpoint)
+    }
+
+    /// Pending entries, highest fee rate first
+    pub fn entries_by_fee_rate(&self) -> Vec<&MempoolEntry> {
+        let mut entries: Vec<&MempoolEntry> = self.entries.values().collect();
+        entries.sort_by(|a, b| b.cmp_fee_rate(a));
+        entries
+    }
+
+    /// Chooses pending transactions for a block, highest fee rate first, up to `max_size` bytes.
+    /// Transactions that do not fit are skipped for smaller ones with a lower fee rate.
+    pub fn select_for_block(&self, max_size: usize) -> BlockSelection {
+        let mut selection = BlockSelection::default();
+
+        for entry in self.entries_by_fee_rate() {
+            if selection.size + entry.size > max_size {
+                continue;
+            }
+            selection.transactions.push(entry.transaction.clone());
+            selection.fees += entry.fee;
+            selection.size += entry.size;
+        }
+
+        selection
+    }
+
+    /// Returns copies of all pending transactions
+    pub fn transactions(&self) -> Vec<Transaction> {
+        self.entries
+            .values()
+            .map(|entry| entry.transaction.clone())
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Total size of pending transactions, in bytes
+    pub fn total_size(&self) -> usize {
+        self.total_size
+    }
+
+    pub fn config(&self) -> &MempoolConfig {
+        &self.config
+    }
+
+    fn publish(&self, topic: EventTopic, data: serde_json::Value) {
+        // No receivers is not an error, events are simply dropped
+        let _ = self.events.send(NodeEvent { topic, data });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::test_utils::spend;
+
+    /// Spends the first output of the transaction with `transaction_hash`, holding `input`
+    fn transaction(transaction_hash: [u8; 32], input: u64, output: u64) -> Transaction {
+        let outpoint = OutPoint {
+            transaction_hash,
+            index: 0,
+        };
+        spend(outpoint, input, &[output])
+    }
+
+    fn mempool(max_transactions: usize) -> (Mempool, broadcast::Receiver<NodeEvent>) {
+        let (events, receiver) = broadcast::channel(16);
+        let config = MempoolConfig {
+            max_transactions,
+            ..MempoolConfig::default()
+        };
+        (Mempool::new(config, events), receiver)
+    }
+
+    #[test]
+    fn it_rejects_duplicates_and_conflicts() {
+        let (mut mempool, mut events) = mempool(10);
+        let tx = transaction([1u8; 32], 10, 9);
+
+        assert!(mempool.add(tx.clone(), 1).is_ok());
+        assert_eq!(events.try_recv().unwrap().topic, EventTopic::MempoolTxAdded);
+        assert_eq!(mempool.add(tx, 1), Err(MempoolError::AlreadyExists));
+
+        // Spends the same outpoint as the first transaction
+        let double_spend = transaction([1u8; 32], 10, 5);
+        assert!(matches!(
+            mempool.add(double_spend, 5),
+            Err(MempoolError::Conflict(_))
+        ));
+        assert_eq!(mempool.len(), 1);
+    }
+
+    #[test]
+    fn it_rejects_spends_of_pending_outputs() {
+        let (mut mempool, _events) = mempool(10);
+        let parent = transaction([1u8; 32], 10, 9);
+        mempool.add(parent.clone(), 1).unwrap();
+
+        let child = transaction(parent.metadata().transaction_hash, 9, 8);
+        assert!(matches!(
+            mempool.add(child, 1),
+            Err(MempoolError::UnconfirmedParent(_))
+        ));
+        assert_eq!(mempool.len(), 1);
+    }
+
+    #[test]
+    fn it_refuses_fees_below_the_minimum_relay_fee_rate() {
+        let (mut mempool, _events) = mempool(10);
+        let tx = transaction([1u8; 32], 10, 10);
+        let required = mempool.config().min_relay_fee_rate.fee_for(tx.size());
+
+        assert_eq!(
+            mempool.add(tx.clone(), required - 1),
+            Err(MempoolError::FeeBelowMinimum {
+                fee: required - 1,
+                required
+            })
+        );
+        assert!(mempool.add(tx, required).is_ok());
+    }
+
+    #[test]
+    fn it_evicts_lowest_fee_rate_when_full() {
+        let (mut mempool, _events) = mempool(2);
+        let cheap = transaction([1u8; 32], 10, 9);
+        let medium = transaction([2u8; 32], 10, 5);
+        let expensive = transaction([3u8; 32], 10, 1);
+
+        mempool.add(cheap.clone(), 1).unwrap();
+        mempool.add(medium.clone(), 5).unwrap();
+        mempool.add(expensive.clone(), 9).unwrap();
+
+        assert_eq!(mempool.len(), 2);
+        assert!(!mempool.contains(&cheap.metadata().transaction_hash));
+        assert!(mempool.contains(&expensive.metadata().transaction_hash));
+
+        // Not better than anything in the full pool
+        let too_cheap = transaction([4u8; 32], 10, 9);
+        assert_eq!(mempool.add(too_cheap, 1), Err(MempoolError::FeeTooLow));
+    }
+
+    #[test]
+    fn it_selects_transactions_by_fee_rate_within_size() {
+        let (mut mempool, _events) = mempool(10);
+        let cheap = transaction([1u8; 32], 10, 9);
+        let expensive = transaction([2u8; 32], 10, 1);
+        mempool.add(cheap.clone(), 1).unwrap();
+        mempool.add(expensive.clone(), 9).unwrap();
+
+        let selection = mempool.select_for_block(usize::MAX);
+        assert_eq!(selection.transactions.len(), 2);
+        assert_eq!(selection.fees, 10);
+        assert_eq!(
+            selection.transactions[0].metadata().transaction_hash,
+            expensive.metadata().transaction_hash
+        );
+
+        let selection = mempool.select_for_block(expensive.size());
+        assert_eq!(selection.transactions.len(), 1);
+        assert_eq!(selection.fees, 9);
+    }
+
+    #[test]
+    fn it_expires_old_transactions() {
+        let (mut mempool, _events) = mempool(10);
+        let tx = transaction([1u8; 32], 10, 9);
+        mempool.add(tx, 1).unwrap();
+
+        assert!(mempool.expire(Instant::now()).is_empty());
+
+        let later = Instant::now() + mempool.config().expiry;
+        assert_eq!(mempool.expire(later).len(), 1);
+        assert!(mempool.is_empty());
+        assert_eq!(mempool.total_size(), 0);
+    }
+}
@@ -0,0 +1,29 @@
+//! # Mempool
+//!
+//! Temporary store for pending (not yet mined) transactions.
+//!
+//! Transactions are keyed by their `transaction_hash`. The mempool rejects duplicates
+//! and transactions spending an output already spent by another pending transaction,
+//! enforces count and size limits by evicting the lowest fee-rate entries,
+//! and drops entries older than the configured expiry.
+//!
+//! Transactions paying less than the minimum relay fee rate for their size are refused.
+//! The [`FeeEstimator`] suggests fee rates from recent blocks and pending transactions.
+//!
+//! Every admission and removal is published as a [`NodeEvent`](crate::comms::NodeEvent)
+//! on the `MempoolTxAdded` / `MempoolTxRemoved` topics.
+//!
+//! ## Exports
+//! - [`Mempool`]: Pending transaction pool.
+//! - [`MempoolConfig`]: Limits and expiry of the pool.
+//! - [`MempoolError`]: Reasons a transaction is refused.
+//! - [`FeeRate`]: Fee per 1000 bytes of serialized transaction.
+//! - [`FeeEstimator`]: Suggests fee rates for confirmation within a number of blocks.
+//!
+
+#[allow(clippy::module_inception)]
+mod mempool;
+mod fees;
+
+pub use fees::*;
+pub use mempool::*;
@@ -0,0 +1,413 @@
+//! # Miner
+//!
+//! Proof-of-work search over block templates, on worker threads.
+
+use std::{
+    ops::Range,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use thiserror::Error;
+use tokio::{sync::watch, task::JoinHandle};
+use tracing::debug;
+
+use super::MiningPolicy;
+use crate::{
+    blockchain::{set_encoded_nonce, Block, HeaderEncodingError, Target},
+    config::MINING_NONCE_SPACE,
+    transaction::Transaction,
+    utils::HashHelper,
+};
+
+/// Hashes tried between two checks of the stop flag
+const CHECK_INTERVAL: u64 = 4_096;
+
+/// Mining parameters of a node
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MinerConfig {
+    pub threads: usize,       // Worker threads, at least 1
+    pub nonce_space: u64,     // Nonces tried per header before refreshing time and extra nonce
+    pub policy: MiningPolicy, // When the mining service starts on a new block
+    pub autostart: bool,      // Whether the mining service is enabled when the node starts
+}
+
+impl Default for MinerConfig {
+    fn default() -> Self {
+        Self {
+            threads: thread::available_parallelism().map_or(1, |n| n.get()),
+            nonce_space: MINING_NONCE_SPACE,
+            policy: MiningPolicy::default(),
+            autostart: false,
+        }
+    }
+}
+
+/// Builds the coinbase of a block for a given extra nonce
+pub type CoinbaseBuilder = Arc<dyn Fn(u64) -> Transaction + Send + Sync>;
+
+/// Everything a block needs but the proof of work
+#[derive(Clone)]
+pub struct BlockTemplate {
+    pub previous_hash: String,
+    pub bits: u32,
+    pub coinbase: CoinbaseBuilder,
+    pub transactions: Vec<Transaction>, // Transactions following the coinbase
+}
+
+impl BlockTemplate {
+    /// Unsolved block for `extra_nonce`, timestamped now
+    pub fn candidate(&self, extra_nonce: u64) -> Block {
+        let mut transactions = Vec::with_capacity(self.transactions.len() + 1);
+        transactions.push((self.coinbase)(extra_nonce));
+        transactions.extend(self.transactions.iter().cloned());
+
+        Block::candidate(&self.previous_hash, &transactions, self.bits)
+    }
+}
+
+/// Reasons mining stopped without a block
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum MinerError {
+    #[error("Chain tip moved away from the template parent")]
+    Stale,
+    #[error("Mining was cancelled")]
+    Cancelled,
+    #[error("Template bits {0:#010x} do not encode a valid target")]
+    InvalidBits(u32),
+    #[error("Template header cannot be encoded: {0}")]
+    InvalidTemplate(#[from] HeaderEncodingError),
+    #[error("Mining worker failed: {0}")]
+    Worker(String),
+}
+
+/// Outcome of a nonce search over a range
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NonceSearch {
+    Found { nonce: u64, hash: [u8; 32] },
+    Exhausted,
+    Stopped,
+}
+
+/// Tries the `nonces` on a canonically encoded header until its hash meets `target`.
+/// Checks `stop` every few thousand hashes and adds the hashes tried to `hashes`.
+pub fn search_nonce(
+    encoded_header: &mut [u8],
+    target: &Target,
+    nonces: Range<u64>,
+    stop: &AtomicBool,
+    hashes: &AtomicU64,
+) -> NonceSearch {
+    let mut tried = 0;
+    for nonce in nonces {
+        set_encoded_nonce(encoded_header, nonce);
+        let hash = HashHelper::hash_bytes(encoded_header);
+        tried += 1;
+
+        if Target::from_be_bytes(hash) <= *target {
+            hashes.fetch_add(tried, Ordering::Relaxed);
+            return NonceSearch::Found { nonce, hash };
+        }
+        if tried == CHECK_INTERVAL {
+            hashes.fetch_add(tried, Ordering::Relaxed);
+            tried = 0;
+            if stop.load(Ordering::Relaxed) {
+                return NonceSearch::Stopped;
+            }
+        }
+    }
+    hashes.fetch_add(tried, Ordering::Relaxed);
+    NonceSearch::Exhausted
+}
+
+/// A block found by the miner
+#[derive(Debug, Clone)]
+pub struct MinedBlock {
+    pub block: Block,
+    pub hashes: u64,
+    pub elapsed: Duration,
+}
+
+impl MinedBlock {
+    /// Hashes per second it took to find the block
+    pub fn hashrate(&self) -> f64 {
+        hashrate(self.hashes, self.elapsed)
+    }
+}
+
+fn hashrate(hashes: u64, elapsed: Duration) -> f64 {
+    let secs = elapsed.as_secs_f64();
+    if secs > 0.0 {
+        hashes as f64 / secs
+    } else {
+        0.0
+    }
+}
+
+/// State shared by the workers of a job
+#[derive(Debug, Default)]
+struct JobState {
+    stop: AtomicBool,
+    hashes: AtomicU64,
+    extra_nonce: AtomicU64, // Next extra nonce handed to a worker refreshing its header
+}
+
+/// Handle of a running search. Dropping it cancels the search.
+#[derive(Debug)]
+pub struct MiningJob {
+    previous_hash: String,
+    state: Arc<JobState>,
+    started: Instant,
+    workers: Vec<JoinHandle<Option<Block>>>,
+}
+
+impl MiningJob {
+    /// Hash of the block the job mines on top of
+    pub fn previous_hash(&self) -> &str {
+        &self.previous_hash
+    }
+
+    /// Stops every worker, `wait` then returns `MinerError::Cancelled`
+    pub fn cancel(&self) {
+        self.state.stop.store(true, Ordering::Relaxed);
+    }
+
+    /// Hashes tried so far
+    pub fn hashes(&self) -> u64 {
+        self.state.hashes.load(Ordering::Relaxed)
+    }
+
+    /// Hashes per second since the job started
+    pub fn hashrate(&self) -> f64 {
+        hashrate(self.hashes(), self.started.elapsed())
+    }
+
+    /// Waits for a worker to find a block, or for every worker to stop
+    pub async fn wait(mut self) -> Result<MinedBlock, MinerError> {
+        let mut found = None;
+        for worker in std::mem::take(&mut self.workers) {
+            match worker.await {
+                Ok(Some(block)) => found = Some(block),
+                Ok(None) => {}
+                Err(e) => {
+                    self.cancel();
+                    return Err(MinerError::Worker(e.to_string()));
+                }
+            }
+        }
+
+        let block = found.ok_or(MinerError::Cancelled)?;
+        Ok(MinedBlock {
+            block,
+            hashes: self.hashes(),
+            elapsed: self.started.elapsed(),
+        })
+    }
+}
+
+impl Drop for MiningJob {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
+
+/// Proof-of-work miner, splitting the nonce space across worker threads
+#[derive(Debug, Clone, Default)]
+pub struct Miner {
+    config: MinerConfig,
+}
+
+impl Miner {
+    pub fn new(config: MinerConfig) -> Self {
+        Self { config }
+    }
+
+    pub fn config(&self) -> &MinerConfig {
+        &self.config
+    }
+
+    /// Starts mining `template` on the worker threads
+    pub fn start(&self, template: BlockTemplate) -> Result<MiningJob, MinerError> {
+        let target =
+            Target::from_compact(template.bits).ok_or(MinerError::InvalidBits(template.bits))?;
+        let state = Arc::new(JobState::default());
+
+        // Every worker starts from the same header, with its own slice of the nonce space
+        let candidate = template.candidate(state.extra_nonce.fetch_add(1, Ordering::Relaxed));
+        candidate.header().encode()?;
+
+        let nonce_space = self.config.nonce_space.max(1);
+        let threads = (self.config.threads.max(1) as u64).min(nonce_space);
+        let span = nonce_space / threads;
+        let template = Arc::new(template);
+
+        let workers = (0..threads)
+            .map(|worker| {
+                let start = worker * span;
+                let end = if worker + 1 == threads { nonce_space } else { start + span };
+                let (template, state, block) = (template.clone(), state.clone(), candidate.clone());
+                tokio::task::spawn_blocking(move || {
+                    run_worker(worker, &template, block, target, start..end, &state)
+                })
+            })
+            .collect();
+
+        Ok(MiningJob {
+            previous_hash: template.previous_hash.clone(),
+            state,
+            started: Instant::now(),
+            workers,
+        })
+    }
+
+    /// Mines `template` until a block is found, or until `tip` moves away from the template parent
+    pub async fn mine(
+        &self,
+        template: BlockTemplate,
+        mut tip: watch::Receiver<String>,
+    ) -> Result<MinedBlock, MinerError> {
+        if *tip.borrow_and_update() != template.previous_hash {
+            return Err(MinerError::Stale);
+        }
+
+        let job = self.start(template)?;
+        let previous_hash = job.previous_hash.clone();
+        let tip_moved = async move {
+            while tip.changed().await.is_ok() {
+                if *tip.borrow_and_update() != previous_hash {
+                    return;
+                }
+            }
+            // Without a sender the tip cannot move anymore
+            std::future::pending::<()>().await
+        };
+
+        // Dropping the job on a tip change stops its workers
+        tokio::select! {
+            mined = job.wait() => mined,
+            _ = tip_moved => Err(MinerError::Stale),
+        }
+    }
+}
+
+/// Searches the worker nonce slice, refreshing the header whenever the slice is exhausted
+fn run_worker(
+    worker: u64,
+    template: &BlockTemplate,
+    mut block: Block,
+    target: Target,
+    nonces: Range<u64>,
+    state: &JobState,
+) -> Option<Block> {
+    loop {
+        let mut encoded_header = block.header().encode().ok()?;
+        match search_nonce(
+            &mut encoded_header,
+            &target,
+            nonces.clone(),
+            &state.stop,
+            &state.hashes,
+        ) {
+            NonceSearch::Found { nonce, hash } => {
+                // Another worker may have found a block at the same time
+                if state.stop.swap(true, Ordering::Relaxed) {
+                    return None;
+                }
+                block.header.nonce = nonce;
+                block.header.current_hash = HashHelper::to_hex(&hash);
+                return Some(block);
+            }
+            NonceSearch::Stopped => return None,
+            NonceSearch::Exhausted => {
+                if state.stop.load(Ordering::Relaxed) {
+                    return None;
+                }
+                let extra_nonce = state.extra_nonce.fetch_add(1, Ordering::Relaxed);
+                debug!(
+                    "Miner worker {} refreshing its header, extra nonce {}",
+                    worker, extra_nonce
+                );
+                block = template.candidate(extra_nonce);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        blockchain::merkle_root,
+        transaction::{test_utils::keys, TransactionManager},
+    };
+
+    fn template(bits: u32) -> BlockTemplate {
+        let (_, public_key) = keys();
+        BlockTemplate {
+            previous_hash: "00".repeat(32),
+            bits,
+            coinbase: Arc::new(move |extra_nonce| {
+                TransactionManager::create_coinbase_transaction(
+                    &public_key,
+                    "miner",
+                    20,
+                    extra_nonce,
+                )
+            }),
+            transactions: vec![],
+        }
+    }
+
+    #[tokio::test]
+    async fn it_mines_across_threads_and_refreshes_exhausted_headers() {
+        // 16 nonces per worker and header, about 1 in 65536 hashes meets the target
+        let miner = Miner::new(MinerConfig {
+            threads: 4,
+            nonce_space: 64,
+            ..MinerConfig::default()
+        });
+        let mined = miner.start(template(0x1f00_ffff)).unwrap().wait().await.unwrap();
+
+        let header = mined.block.header();
+        assert!(mined.block.header().nonce() < 64);
+        assert_eq!(&header.hash().unwrap(), header.current_hash());
+        assert!(Target::from_compact(header.bits()).unwrap().is_met_by(header.current_hash()));
+        assert_eq!(
+            header.merkle_root(),
+            &HashHelper::to_hex(&merkle_root(mined.block.body().transactions()))
+        );
+
+        // The winning header was refreshed with a new extra nonce in its coinbase
+        let coinbase = &mined.block.body().transactions()[0];
+        assert!(coinbase.inputs()[0].nonce > 0);
+        assert!(mined.hashes > 64);
+        assert!(mined.hashrate() > 0.0);
+    }
+
+    #[tokio::test]
+    async fn it_stops_when_cancelled_or_the_tip_moves() {
+        // A target no hash realistically meets
+        let unsolvable = template(0x0300_ffff);
+        let miner = Miner::new(MinerConfig {
+            threads: 2,
+            ..MinerConfig::default()
+        });
+
+        let job = miner.start(unsolvable.clone()).unwrap();
+        job.cancel();
+        assert_eq!(job.wait().await.unwrap_err(), MinerError::Cancelled);
+
+        let (tip, tip_receiver) = watch::channel(unsolvable.previous_hash.clone());
+        let mining = tokio::spawn({
+            let miner = miner.clone();
+            async move { miner.mine(unsolvable, tip_receiver).await }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        tip.send_replace("11".repeat(32));
+        assert_eq!(mining.await.unwrap().unwrap_err(), MinerError::Stale);
+    }
+}
@@ -0,0 +1,34 @@
+//! # Miner
+//!
+//! Multi-threaded proof-of-work search for new blocks.
+//!
+//! A [`BlockTemplate`] holds everything a block needs but the proof of work. The
+//! [`Miner`] splits the nonce space of the template header across worker threads
+//! (`spawn_blocking`), so the async runtime is never blocked. When a worker runs out of
+//! nonces, it refreshes its header with the current time and a new extra nonce, carried
+//! by the coinbase, and starts over.
+//!
+//! A [`MiningJob`] can be cancelled at any time, and [`Miner::mine`] gives up as soon as
+//! the chain tip moves away from the template parent. Hashes tried are counted, so the
+//! hashrate of a job is known while it runs and once a block is found.
+//!
+//! The node mining service, started and stopped through a [`MiningControl`], mines in the
+//! background whenever its [`MiningPolicy`] is met.
+//!
+//! ## Exports
+//! - [`Miner`]: Starts mining jobs.
+//! - [`MinerConfig`]: Worker threads, nonce space per header and mining policy.
+//! - [`BlockTemplate`]: Block to mine, minus the proof of work.
+//! - [`MiningJob`]: Handle of a running search.
+//! - [`MinedBlock`]: Block found, with the work it took.
+//! - [`MinerError`]: Reasons mining stopped without a block.
+//! - [`MiningPolicy`]: When the mining service starts on a new block.
+//! - [`MiningControl`]: Starts and stops the mining service.
+//!
+
+#[allow(clippy::module_inception)]
+mod miner;
+mod policy;
+
+pub use miner::*;
+pub use policy::*;
@@ -0,0 +1,117 @@
+//! # Mining Policy
+//!
+//! When the node background mining service starts on a new block.
+
+use std::time::Duration;
+
+use tokio::sync::watch;
+
+use crate::config::{BLOCKCHAIN_TARGET_BLOCK_TIME_SECS, MINING_MIN_BLOCK_FILL_PERCENT};
+
+/// Condition for the mining service to start on a new block
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MiningPolicy {
+    /// Mine block after block
+    Continuous,
+    /// Mine as soon as a transaction is pending
+    MempoolNotEmpty,
+    /// Mine once pending transactions fill at least this percentage of the block size limit
+    MinBlockFill(u8),
+    /// Mine once this long has passed since the tip was mined
+    MaxInterval(Duration),
+    /// Mine as soon as any of the policies is met
+    Any(Vec<MiningPolicy>),
+}
+
+impl Default for MiningPolicy {
+    /// Mines full enough blocks, and at least one block per target block time
+    fn default() -> Self {
+        MiningPolicy::Any(vec![
+            MiningPolicy::MinBlockFill(MINING_MIN_BLOCK_FILL_PERCENT),
+            MiningPolicy::MaxInterval(Duration::from_secs(BLOCKCHAIN_TARGET_BLOCK_TIME_SECS)),
+        ])
+    }
+}
+
+/// Node state a policy decides on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MiningState {
+    pub pending_transactions: usize,
+    pub pending_size: usize,   // Total size of pending transactions, in bytes
+    pub max_block_size: usize, // Block size limit, in bytes
+    pub since_last_block: Duration,
+}
+
+impl MiningPolicy {
+    /// Whether a new block should be mined in `state`
+    pub fn should_mine(&self, state: &MiningState) -> bool {
+        match self {
+            MiningPolicy::Continuous => true,
+            MiningPolicy::MempoolNotEmpty => state.pending_transactions > 0,
+            MiningPolicy::MinBlockFill(percent) => {
+                state.pending_transactions > 0
+                    && state.pending_size.saturating_mul(100)
+                        >= state.max_block_size.saturating_mul(*percent as usize)
+            }
+            MiningPolicy::MaxInterval(interval) => state.since_last_block >= *interval,
+            MiningPolicy::Any(policies) => policies.iter().any(|policy| policy.should_mine(state)),
+        }
+    }
+}
+
+/// Starts and stops the node mining service, cloneable so it can be used while the node runs
+#[derive(Debug, Clone)]
+pub struct MiningControl {
+    enabled: watch::Sender<bool>,
+}
+
+impl MiningControl {
+    pub fn new(enabled: bool) -> Self {
+        let (enabled, _) = watch::channel(enabled);
+        Self { enabled }
+    }
+
+    pub fn start(&self) {
+        self.enabled.send_replace(true);
+    }
+
+    /// Stops mining, cancelling the block being mined
+    pub fn stop(&self) {
+        self.enabled.send_replace(false);
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        *self.enabled.borrow()
+    }
+
+    /// Watches whether mining is enabled
+    pub fn subscribe(&self) -> watch::Receiver<bool> {
+        self.enabled.subscribe()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(pending_size: usize, since_last_block: u64) -> MiningState {
+        MiningState {
+            pending_transactions: usize::from(pending_size > 0),
+            pending_size,
+            max_block_size: 1_000,
+            since_last_block: Duration::from_secs(since_last_block),
+        }
+    }
+
+    #[test]
+    fn it_decides_when_to_mine() {
+        assert!(MiningPolicy::Continuous.should_mine(&state(0, 0)));
+        assert!(!MiningPolicy::MempoolNotEmpty.should_mine(&state(0, 0)));
+        assert!(MiningPolicy::MempoolNotEmpty.should_mine(&state(10, 0)));
+
+        let policy = MiningPolicy::default();
+        assert!(!policy.should_mine(&state(699, 0)));
+        assert!(policy.should_mine(&state(700, 0)));
+        assert!(policy.should_mine(&state(0, BLOCKCHAIN_TARGET_BLOCK_TIME_SECS)));
+    }
+}
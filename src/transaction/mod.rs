@@ -9,7 +9,20 @@
 //!
 //! ## Exports
 //! - [`transaction_manager`]: Core transaction logic.
+//! - [`validation`]: Consensus validation of transactions against the UTXO set.
+//! - [`sighash`]: Per-input signatures and what they commit to.
+//! - [`transaction_encoding`]: Canonical, versioned encoding hashed into the transaction id.
 //! 
 
+mod sighash;
+mod transaction_encoding;
 mod transaction_manager;
-pub use transaction_manager::*;
\ No newline at end of file
+mod validation;
+
+#[cfg(test)]
+pub(crate) mod test_utils;
+
+pub use sighash::*;
+pub use transaction_encoding::*;
+pub use transaction_manager::*;
+pub use validation::*;
\ No newline at end of file
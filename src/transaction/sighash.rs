@@ -0,0 +1,279 @@
+//! # Signature Hash
+//!
+//! What the signature of a transaction input commits to.
+//!
+//! Every input of a regular transaction carries its own signature, made with the key
+//! owning the spent output: the compact ECDSA signature of the input *sighash*, followed
+//! by the [`SigHashType`] byte. The sighash is a SHA-256 over the transaction version, type
+//! and timestamp, the input index and the inputs and outputs selected by the sighash type,
+//! encoded as in the transaction id (see [`transaction_encoding`](super::transaction_encoding)):
+//!
+//! | Type | Byte | Inputs covered | Outputs covered |
+//! |------|------|----------------|-----------------|
+//! | `All` | `0x01` | all | all |
+//! | `Single` | `0x03` | all | the one at the input index |
+//! | `AllAnyoneCanPay` | `0x81` | the signed input | all |
+//! | `SingleAnyoneCanPay` | `0x83` | the signed input | the one at the input index |
+//!
+//! Signatures are never covered, so inputs can be signed in any order. `AnyoneCanPay` lets
+//! other parties add inputs, `Single` lets them add outputs.
+
+use hdwallet::secp256k1::{ecdsa::Signature, SecretKey};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+use super::{
+    transaction_encoding::{encode_inputs, encode_outputs, encode_timestamp},
+    Transaction, TransactionEncodingError, TransactionInput, TransactionOutput,
+    TRANSACTION_VERSION,
+};
+use crate::utils::TransactionHelper;
+
+/// Separates sighashes from any other hashed data
+const SIGHASH_DOMAIN: &str = "oxidize/sighash";
+
+const SIGHASH_ALL: u8 = 0x01;
+const SIGHASH_SINGLE: u8 = 0x03;
+const SIGHASH_ANYONECANPAY: u8 = 0x80;
+
+/// Parts of the transaction an input signature covers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SigHashType {
+    All,
+    Single,
+    AllAnyoneCanPay,
+    SingleAnyoneCanPay,
+}
+
+impl SigHashType {
+    pub fn to_byte(self) -> u8 {
+        match self {
+            SigHashType::All => SIGHASH_ALL,
+            SigHashType::Single => SIGHASH_SINGLE,
+            SigHashType::AllAnyoneCanPay => SIGHASH_ALL | SIGHASH_ANYONECANPAY,
+            SigHashType::SingleAnyoneCanPay => SIGHASH_SINGLE | SIGHASH_ANYONECANPAY,
+        }
+    }
+
+    pub fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            SIGHASH_ALL => Some(SigHashType::All),
+            SIGHASH_SINGLE => Some(SigHashType::Single),
+            b if b == SIGHASH_ALL | SIGHASH_ANYONECANPAY => Some(SigHashType::AllAnyoneCanPay),
+            b if b == SIGHASH_SINGLE | SIGHASH_ANYONECANPAY => {
+                Some(SigHashType::SingleAnyoneCanPay)
+            }
+            _ => None,
+        }
+    }
+
+    /// Only the signed input is covered
+    pub fn anyone_can_pay(self) -> bool {
+        matches!(self, SigHashType::AllAnyoneCanPay | SigHashType::SingleAnyoneCanPay)
+    }
+
+    /// Only the output at the input index is covered
+    pub fn single(self) -> bool {
+        matches!(self, SigHashType::Single | SigHashType::SingleAnyoneCanPay)
+    }
+}
+
+/// Reasons an input cannot be signed, or its signature is invalid
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum SignatureError {
+    #[error("Transaction has no input {0}")]
+    MissingInput(usize),
+    #[error("Input {0} is signed with `Single` but has no output at the same index")]
+    MissingOutput(usize),
+    #[error("Signature of input {0} is malformed")]
+    Malformed(usize),
+    #[error("Signature of input {0} does not match its public key")]
+    Invalid(usize),
+    #[error(transparent)]
+    Encoding(#[from] TransactionEncodingError),
+}
+
+/// Hash the signature of input `index` commits to, under `sighash_type`
+pub fn signature_hash(
+    transaction: &Transaction,
+    index: usize,
+    sighash_type: SigHashType,
+) -> Result<[u8; 32], SignatureError> {
+    let all_inputs = transaction.inputs();
+    let all_outputs = transaction.outputs();
+
+    let input = all_inputs.get(index).ok_or(SignatureError::MissingInput(index))?;
+    let inputs: Vec<&TransactionInput> = if sighash_type.anyone_can_pay() {
+        vec![input]
+    } else {
+        all_inputs.iter().collect()
+    };
+    let outputs: Vec<&TransactionOutput> = if sighash_type.single() {
+        vec![all_outputs.get(index).ok_or(SignatureError::MissingOutput(index))?]
+    } else {
+        all_outputs.iter().collect()
+    };
+
+    let encoded = SignedData {
+        version: transaction.metadata().version(),
+        transaction_type: transaction.metadata().r#type().to_byte(),
+        sighash_type: sighash_type.to_byte(),
+        timestamp: transaction.metadata().timestamp(),
+        index: index as u32,
+        inputs,
+        outputs,
+    }
+    .encode()?;
+    Ok(Sha256::digest(encoded).into())
+}
+
+/// Signature of input `index` under `sighash_type`: compact ECDSA signature
+/// followed by the sighash type byte
+pub fn input_signature(
+    transaction: &Transaction,
+    index: usize,
+    secret_key: &SecretKey,
+    sighash_type: SigHashType,
+) -> Result<Vec<u8>, SignatureError> {
+    let hash = signature_hash(transaction, index, sighash_type)?;
+    let mut signature = TransactionHelper::sign_transaction(secret_key, hash)
+        .serialize_compact()
+        .to_vec();
+    signature.push(sighash_type.to_byte());
+    Ok(signature)
+}
+
+/// Checks the signature of input `index` against the input public key
+pub fn verify_input(transaction: &Transaction, index: usize) -> Result<(), SignatureError> {
+    let input = transaction
+        .inputs()
+        .get(index)
+        .ok_or(SignatureError::MissingInput(index))?;
+
+    let (sighash_byte, signature) = input
+        .signature
+        .split_last()
+        .ok_or(SignatureError::Malformed(index))?;
+    let sighash_type =
+        SigHashType::from_byte(*sighash_byte).ok_or(SignatureError::Malformed(index))?;
+    let signature =
+        Signature::from_compact(signature).map_err(|_| SignatureError::Malformed(index))?;
+
+    let hash = signature_hash(transaction, index, sighash_type)?;
+    if TransactionHelper::verify_signature(&input.public_key, hash, &signature) {
+        Ok(())
+    } else {
+        Err(SignatureError::Invalid(index))
+    }
+}
+
+/// Fields covered by an input signature, in encoding order
+struct SignedData<'a> {
+    version: u32,
+    transaction_type: u8,
+    sighash_type: u8,
+    timestamp: &'a str,
+    index: u32,
+    inputs: Vec<&'a TransactionInput>,
+    outputs: Vec<&'a TransactionOutput>,
+}
+
+impl SignedData<'_> {
+    fn encode(&self) -> Result<Vec<u8>, TransactionEncodingError> {
+        if self.version != TRANSACTION_VERSION {
+            return Err(TransactionEncodingError::UnsupportedVersion(self.version));
+        }
+
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(SIGHASH_DOMAIN.as_bytes());
+        bytes.extend_from_slice(&self.version.to_le_bytes());
+        bytes.push(self.transaction_type);
+        bytes.push(self.sighash_type);
+        encode_timestamp(&mut bytes, self.timestamp)?;
+        bytes.extend_from_slice(&self.index.to_le_bytes());
+        encode_inputs(&mut bytes, self.inputs.iter().copied());
+        encode_outputs(&mut bytes, self.outputs.iter().copied())?;
+        Ok(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::test_utils::keys;
+    use crate::transaction::{TransactionManager, TransactionType};
+
+    /// Unsigned transaction spending two outputs into two outputs
+    fn transaction() -> Transaction {
+        let (_, public_key) = keys();
+        let inputs = (1..=2)
+            .map(|i| TransactionInput {
+                previous_tx_hash: [i; 32],
+                index: 0,
+                signature: Vec::new(),
+                public_key,
+                amount: 10,
+                nonce: 0,
+            })
+            .collect();
+        let outputs = ["alice", "bob"]
+            .iter()
+            .map(|recipient| TransactionOutput {
+                recipient_address: recipient.to_string(),
+                amount: 10,
+            })
+            .collect();
+        TransactionManager::create_unsigned_transaction(TransactionType::Regular, inputs, outputs)
+    }
+
+    /// `transaction` with an extra input and an extra output
+    fn extended(transaction: &Transaction) -> Transaction {
+        let mut value = serde_json::to_value(transaction).unwrap();
+        let input = value["inputs"][0].clone();
+        let output = value["outputs"][0].clone();
+        value["inputs"].as_array_mut().unwrap().push(input);
+        value["outputs"].as_array_mut().unwrap().push(output);
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn it_covers_the_parts_selected_by_the_sighash_type() {
+        let transaction = transaction();
+        let extended = extended(&transaction);
+
+        let unchanged = |sighash_type| {
+            signature_hash(&transaction, 0, sighash_type)
+                == signature_hash(&extended, 0, sighash_type)
+        };
+        assert!(!unchanged(SigHashType::All));
+        assert!(!unchanged(SigHashType::Single));
+        assert!(!unchanged(SigHashType::AllAnyoneCanPay));
+        assert!(unchanged(SigHashType::SingleAnyoneCanPay));
+
+        assert_ne!(
+            signature_hash(&transaction, 0, SigHashType::All),
+            signature_hash(&transaction, 1, SigHashType::All)
+        );
+        assert_eq!(
+            signature_hash(&transaction, 2, SigHashType::All),
+            Err(SignatureError::MissingInput(2))
+        );
+    }
+
+    #[test]
+    fn it_signs_and_verifies_inputs() {
+        let (secret_key, _) = keys();
+        let mut transaction = transaction();
+        assert_eq!(verify_input(&transaction, 0), Err(SignatureError::Malformed(0)));
+
+        let signature =
+            input_signature(&transaction, 0, &secret_key, SigHashType::Single).unwrap();
+        assert_eq!(signature.last(), Some(&0x03));
+        transaction.set_input_signature(0, signature.clone());
+        assert_eq!(verify_input(&transaction, 0), Ok(()));
+
+        // The signature of input 0 does not sign input 1
+        transaction.set_input_signature(1, signature);
+        assert_eq!(verify_input(&transaction, 1), Err(SignatureError::Invalid(1)));
+    }
+}
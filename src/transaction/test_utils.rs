@@ -0,0 +1,63 @@
+//! # Test Utilities
+//!
+//! Keys and transactions shared by the unit tests of the crate. Every transaction is signed
+//! with [`keys`], so its inputs may spend outputs paid to [`owner`].
+
+use hdwallet::secp256k1::{PublicKey, Secp256k1, SecretKey};
+
+use crate::utils::TransactionHelper;
+
+use super::{OutPoint, Transaction, TransactionInput, TransactionManager, TransactionOutput};
+
+/// Key pair signing the test transactions
+pub(crate) fn keys() -> (SecretKey, PublicKey) {
+    let secret_key = SecretKey::from_slice(&[7u8; 32]).unwrap();
+    let public_key = PublicKey::from_secret_key(&Secp256k1::new(), &secret_key);
+    (secret_key, public_key)
+}
+
+/// Address owned by `keys()`
+pub(crate) fn owner() -> String {
+    TransactionHelper::address_from_public_key(&keys().1)
+}
+
+/// Coinbase paying `amount` to `owner()`
+pub(crate) fn coinbase(amount: u64) -> Transaction {
+    TransactionManager::create_coinbase_transaction(&keys().1, &owner(), amount, 0)
+}
+
+/// Transaction spending `outpoint`, an output of `input`, into one output to `owner()` per
+/// amount of `outputs`
+pub(crate) fn spend(outpoint: OutPoint, input: u64, outputs: &[u64]) -> Transaction {
+    let outputs: Vec<_> = outputs.iter().map(|amount| (owner(), *amount)).collect();
+    spend_to(outpoint, input, &outputs)
+}
+
+/// Transaction spending the first output of `previous`, paying `amount` to `recipient`
+pub(crate) fn pay(previous: &Transaction, recipient: &str, amount: u64) -> Transaction {
+    let outpoint = OutPoint {
+        transaction_hash: previous.metadata().transaction_hash,
+        index: 0,
+    };
+    spend_to(outpoint, previous.outputs()[0].amount, &[(recipient.to_string(), amount)])
+}
+
+fn spend_to(outpoint: OutPoint, input: u64, outputs: &[(String, u64)]) -> Transaction {
+    let (secret_key, public_key) = keys();
+    let inputs = vec![TransactionInput {
+        previous_tx_hash: outpoint.transaction_hash,
+        index: outpoint.index,
+        signature: Vec::new(),
+        public_key,
+        amount: input,
+        nonce: 0,
+    }];
+    let outputs = outputs
+        .iter()
+        .map(|(recipient, amount)| TransactionOutput {
+            recipient_address: recipient.clone(),
+            amount: *amount,
+        })
+        .collect();
+    TransactionManager::create_transaction(inputs, outputs, secret_key)
+}
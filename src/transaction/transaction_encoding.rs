@@ -0,0 +1,185 @@
+//! # Transaction Encoding
+//!
+//! Canonical byte encoding of the consensus fields of a [`Transaction`], hashed with SHA-256
+//! to get the transaction id. Signatures and node-local state (e.g. whether the transaction
+//! is pending or mined) are not part of it, so the id never changes once created.
+//!
+//! Version 2 layout, integers little-endian:
+//!
+//! | Field | Size | Description |
+//! |-------|------|-------------|
+//! | version | 4 | Transaction encoding version, `2` |
+//! | type | 1 | `0` coinbase, `1` regular, `2` fee |
+//! | timestamp length | 2 | Length of the timestamp, in bytes |
+//! | timestamp | n | RFC 3339 timestamp, UTF-8 |
+//! | input count | 4 | Number of inputs |
+//! | inputs | 85 each | See below |
+//! | output count | 4 | Number of outputs |
+//! | outputs | 10 + n each | See below |
+//!
+//! An input is `previous_tx_hash (32) | index (4) | amount (8) | nonce (8) | public_key (33,
+//! compressed)`, an output is `address length (2) | recipient_address (n, UTF-8) | amount (8)`.
+//! Any change to these layouts requires a new version. Version 1 did not encode the type.
+
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+use super::{Transaction, TransactionInput, TransactionOutput};
+
+/// Current transaction encoding version
+pub const TRANSACTION_VERSION: u32 = 2;
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum TransactionEncodingError {
+    #[error("Unsupported transaction version {0}")]
+    UnsupportedVersion(u32),
+    #[error("Transaction timestamp is too long")]
+    InvalidTimestamp,
+    #[error("Recipient address of output {0} is too long")]
+    InvalidAddress(usize),
+}
+
+impl Transaction {
+    /// Canonical encoding of the consensus fields of the transaction
+    pub fn encode(&self) -> Result<Vec<u8>, TransactionEncodingError> {
+        let version = self.metadata().version();
+        if version != TRANSACTION_VERSION {
+            return Err(TransactionEncodingError::UnsupportedVersion(version));
+        }
+
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&version.to_le_bytes());
+        bytes.push(self.metadata().r#type().to_byte());
+        encode_timestamp(&mut bytes, self.metadata().timestamp())?;
+        encode_inputs(&mut bytes, self.inputs().iter());
+        encode_outputs(&mut bytes, self.outputs().iter())?;
+        Ok(bytes)
+    }
+
+    /// Transaction id: the hash of the canonical encoding
+    pub fn hash(&self) -> Result<[u8; 32], TransactionEncodingError> {
+        Ok(Sha256::digest(self.encode()?).into())
+    }
+}
+
+pub(crate) fn encode_timestamp(
+    bytes: &mut Vec<u8>,
+    timestamp: &str,
+) -> Result<(), TransactionEncodingError> {
+    let len =
+        u16::try_from(timestamp.len()).map_err(|_| TransactionEncodingError::InvalidTimestamp)?;
+    bytes.extend_from_slice(&len.to_le_bytes());
+    bytes.extend_from_slice(timestamp.as_bytes());
+    Ok(())
+}
+
+/// Encodes the count of `inputs` followed by each input, without its signature
+pub(crate) fn encode_inputs<'a>(
+    bytes: &mut Vec<u8>,
+    inputs: impl ExactSizeIterator<Item = &'a TransactionInput>,
+) {
+    bytes.extend_from_slice(&(inputs.len() as u32).to_le_bytes());
+    for input in inputs {
+        bytes.extend_from_slice(&input.previous_tx_hash);
+        bytes.extend_from_slice(&input.index.to_le_bytes());
+        bytes.extend_from_slice(&input.amount.to_le_bytes());
+        bytes.extend_from_slice(&input.nonce.to_le_bytes());
+        bytes.extend_from_slice(&input.public_key.serialize());
+    }
+}
+
+/// Encodes the count of `outputs` followed by each output
+pub(crate) fn encode_outputs<'a>(
+    bytes: &mut Vec<u8>,
+    outputs: impl ExactSizeIterator<Item = &'a TransactionOutput>,
+) -> Result<(), TransactionEncodingError> {
+    bytes.extend_from_slice(&(outputs.len() as u32).to_le_bytes());
+    for (index, output) in outputs.enumerate() {
+        let len = u16::try_from(output.recipient_address.len())
+            .map_err(|_| TransactionEncodingError::InvalidAddress(index))?;
+        bytes.extend_from_slice(&len.to_le_bytes());
+        bytes.extend_from_slice(output.recipient_address.as_bytes());
+        bytes.extend_from_slice(&output.amount.to_le_bytes());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use hdwallet::secp256k1::{PublicKey, Secp256k1, SecretKey};
+
+    use super::*;
+    use crate::utils::HashHelper;
+
+    /// Test vector transaction, see README "Transaction encoding"
+    fn vector_transaction() -> Transaction {
+        let secret_key = SecretKey::from_slice(&[1u8; 32]).unwrap();
+        let public_key = PublicKey::from_secret_key(&Secp256k1::new(), &secret_key);
+        let value = serde_json::json!({
+            "inputs": [{
+                "previous_tx_hash": vec![0x22u8; 32],
+                "index": 1,
+                "signature": "",
+                "public_key": public_key.to_string(),
+                "amount": 50,
+                "nonce": 0,
+            }],
+            "outputs": [{ "recipient_address": "bob", "amount": 40 }],
+            "metadata": {
+                "transaction_hash": vec![0u8; 32],
+                "version": 2,
+                "timestamp": "2025-01-01T00:00:00+00:00",
+                "type": "Regular",
+            },
+        });
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn it_matches_test_vectors() {
+        let transaction = vector_transaction();
+        let encoded = transaction.encode().unwrap();
+
+        assert_eq!(
+            HashHelper::to_hex(&encoded),
+            concat!(
+                "02000000",
+                "01",
+                "1900",
+                "323032352d30312d30315430303a30303a30302b30303a3030",
+                "01000000",
+                "2222222222222222222222222222222222222222222222222222222222222222",
+                "01000000",
+                "3200000000000000",
+                "0000000000000000",
+                "031b84c5567b126440995d3ed5aaba0565d71e1834604819ff9c17f5e9d5dd078f",
+                "01000000",
+                "0300",
+                "626f62",
+                "2800000000000000",
+            )
+        );
+        assert_eq!(
+            HashHelper::to_hex(&transaction.hash().unwrap()),
+            "50f043587e2284304e42fb5e244900b76180d0df156951754e4544a5d0688569"
+        );
+    }
+
+    #[test]
+    fn it_covers_the_type_but_not_signatures_and_refuses_other_versions() {
+        let transaction = vector_transaction();
+        let mut signed = transaction.clone();
+        signed.set_input_signature(0, vec![1, 2, 3]);
+        assert_eq!(signed.hash(), transaction.hash());
+
+        let mut value = serde_json::to_value(&transaction).unwrap();
+        value["metadata"]["type"] = "Fee".into();
+        let retyped: Transaction = serde_json::from_value(value).unwrap();
+        assert_ne!(retyped.hash(), transaction.hash());
+
+        let mut value = serde_json::to_value(&transaction).unwrap();
+        value["metadata"]["version"] = 1.into();
+        let legacy: Transaction = serde_json::from_value(value).unwrap();
+        assert_eq!(legacy.hash(), Err(TransactionEncodingError::UnsupportedVersion(1)));
+    }
+}
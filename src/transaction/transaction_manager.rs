@@ -3,12 +3,12 @@
 //! Handles transaction lifecycle: creation, signing, validation, and metadata tracking.
 //! Includes helpers for coinbase transactions and serialization for persistence.
 
-use bincode::{Decode, Encode};
 use chrono::Utc;
 use hdwallet::secp256k1::{PublicKey, SecretKey};
 use serde::{Deserialize, Serialize};
+use thiserror::Error;
 
-use crate::utils::TransactionHelper;
+use super::{input_signature, SigHashType, SignatureError, TRANSACTION_VERSION};
 
 /// A complete blockchain transaction containing inputs, outputs, and metadata.
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -30,6 +30,73 @@ impl Transaction {
     pub fn metadata(&self) -> &TransactionMetadata {
         &self.metadata
     }
+
+    /// Sum of all input amounts
+    pub fn input_amount(&self) -> u64 {
+        self.inputs.iter().map(|input| input.amount).sum()
+    }
+
+    /// Sum of all output amounts
+    pub fn output_amount(&self) -> u64 {
+        self.outputs.iter().map(|output| output.amount).sum()
+    }
+
+    /// Inputs minus outputs, left to the miner of the block including the transaction.
+    /// Zero for a coinbase, and when outputs exceed inputs, which validation refuses.
+    pub fn fee(&self) -> u64 {
+        if self.is_coinbase() {
+            return 0;
+        }
+        self.input_amount().saturating_sub(self.output_amount())
+    }
+
+    /// A coinbase transaction creates the block reward, from a single null input
+    pub fn is_coinbase(&self) -> bool {
+        self.metadata.r#type == TransactionType::Coinbase
+    }
+
+    /// Signs input `index` with `secret_key`, covering the parts of the transaction
+    /// selected by `sighash_type`
+    pub fn sign_input(
+        &mut self,
+        index: usize,
+        secret_key: &SecretKey,
+        sighash_type: SigHashType,
+    ) -> Result<(), SignatureError> {
+        let signature = input_signature(self, index, secret_key, sighash_type)?;
+        self.set_input_signature(index, signature);
+        Ok(())
+    }
+
+    /// Sets the signature of input `index`, e.g. made by another party with
+    /// [`input_signature`]. Signatures are not part of the transaction hash.
+    pub fn set_input_signature(&mut self, index: usize, signature: Vec<u8>) {
+        if let Some(input) = self.inputs.get_mut(index) {
+            input.signature = signature;
+        }
+    }
+
+    /// Size of the serialized (wire) transaction in bytes
+    pub fn size(&self) -> usize {
+        serde_json::to_vec(self)
+            .map(|bytes| bytes.len())
+            .unwrap_or_default()
+    }
+}
+
+/// Points to a specific output of a previous transaction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct OutPoint {
+    pub transaction_hash: [u8; 32],
+    pub index: u32,
+}
+
+impl OutPoint {
+    /// Spent by the input of a coinbase, which does not spend any previous output
+    pub const NULL: OutPoint = OutPoint {
+        transaction_hash: [0u8; 32],
+        index: u32::MAX,
+    };
 }
 
 /// References previous outputs and provides authorization for spending.
@@ -37,134 +104,196 @@ impl Transaction {
 pub struct TransactionInput {
     pub previous_tx_hash: [u8; 32], // Hash of the previous transaction
     pub index: u32,               // Index of the output being used
-    pub signature: String,
-    #[serde(default="default_public_key", skip_serializing, skip_deserializing)]
-    pub public_key: PublicKey,        // Signature for authorization
+    #[serde(with = "hex_serde")]
+    pub signature: Vec<u8>, // Compact ECDSA signature and sighash type byte, empty in a coinbase
+    #[serde(with = "public_key_serde")]
+    pub public_key: PublicKey, // Key owning the spent output, verifies the signature
     pub amount: u64,
     pub nonce: u64,
 }
 
-fn default_public_key() -> PublicKey {
-    // Replace this with a real default if needed
-    PublicKey::from_slice(&[0u8; 33]).unwrap()
+impl TransactionInput {
+    /// A null input does not reference any previous output (only valid in coinbase)
+    pub fn is_null(&self) -> bool {
+        self.outpoint() == OutPoint::NULL
+    }
+
+    /// Returns the output this input spends
+    pub fn outpoint(&self) -> OutPoint {
+        OutPoint {
+            transaction_hash: self.previous_tx_hash,
+            index: self.index,
+        }
+    }
 }
 
-impl bincode::Encode for TransactionInput {
-    fn encode<E: bincode::enc::Encoder>(
-        &self,
-        encoder: &mut E,
-    ) -> core::result::Result<(), bincode::error::EncodeError> {
-        bincode::Encode::encode(&self.signature, encoder)?;
-        bincode::Encode::encode(&self.previous_tx_hash, encoder)?;
-        bincode::Encode::encode(&self.amount, encoder)?;
-        bincode::Encode::encode(&self.nonce, encoder)?;
-        bincode::Encode::encode(&self.index, encoder)?;
+/// (De)serializes a secp256k1 public key as its compressed hex representation,
+/// so it survives persistence and the wire format.
+mod public_key_serde {
+    use std::str::FromStr;
 
-        Ok(())
+    use hdwallet::secp256k1::PublicKey;
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(key: &PublicKey, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(key)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<PublicKey, D::Error> {
+        let hex = String::deserialize(deserializer)?;
+        PublicKey::from_str(&hex).map_err(D::Error::custom)
     }
 }
 
-impl bincode::Encode for TransactionOutput {
-    fn encode<E: bincode::enc::Encoder>(
-        &self,
-        encoder: &mut E,
-    ) -> core::result::Result<(), bincode::error::EncodeError> {
-        bincode::Encode::encode(&self.recipient_address, encoder)?;
-        bincode::Encode::encode(&self.amount, encoder)?;
+/// (De)serializes bytes as a lowercase hex string
+mod hex_serde {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
 
-        Ok(())
+    use crate::utils::HashHelper;
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&HashHelper::to_hex(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let hex = String::deserialize(deserializer)?;
+        HashHelper::bytes_from_hex(&hex).ok_or_else(|| D::Error::custom("invalid hex string"))
     }
 }
 
 /// Represents the recipient and amount being transferred.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct TransactionOutput {
     pub recipient_address: String, // The address of the recipient
     pub amount: u64,               // The amount of currency being sent
 }
 
-/// Metadata attached to each transaction (hash, timestamp, status, etc.).
+/// Metadata attached to each transaction (hash, version, timestamp, etc.).
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TransactionMetadata {
     pub transaction_hash: [u8; 32],
+    version: u32, // Encoding version of the transaction id, see `TRANSACTION_VERSION`
     timestamp: String,
-    status: TransactionStatus,
     r#type: TransactionType,
-    signature: Vec<u8>, // Store signature as bytes for serialization
 }
 
-/// Transaction lifecycle states.
-#[derive(Debug, Clone, Serialize, Deserialize, Encode, Decode)]
-pub enum TransactionStatus {
-    Pending,
-    Mined,
-    Rejected,
+impl TransactionMetadata {
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    pub fn timestamp(&self) -> &String {
+        &self.timestamp
+    }
+
+    pub fn r#type(&self) -> &TransactionType {
+        &self.r#type
+    }
 }
 
+/// Where a transaction stands, as tracked by a node: not part of the transaction itself.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransactionStatus {
+    Pending,               // In the mempool
+    Mined { height: u64 }, // In the block at `height` of the active chain
+}
 
-/// Type of transaction (coinbase, fee, or standard).
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Type of transaction (coinbase, fee, or standard), part of the transaction id and of
+/// every input signature.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum TransactionType {
-    Coinbase, // For mining a new block, does not get put into UTXO
-    Fee,      // For transaction fees
-    Regular,  // P2P
+    Coinbase, // First transaction of a block, a single null input creates the block reward
+    Fee,      // Spends outputs without creating any, the whole input value goes to the miner
+    Regular,  // P2P, spends outputs into new ones
+}
+
+impl TransactionType {
+    pub fn to_byte(self) -> u8 {
+        match self {
+            TransactionType::Coinbase => 0,
+            TransactionType::Regular => 1,
+            TransactionType::Fee => 2,
+        }
+    }
 }
 
 /// Errors related to transaction creation or validation.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
 pub enum TransactionError {
-    NotEnoughFunds,
+    #[error("Not enough funds: {available} available after input fees, {required} required")]
+    NotEnoughFunds { available: u64, required: u64 },
 }
 
 /// Main struct for transaction creation and management.
 pub struct TransactionManager {}
 
 impl TransactionManager {
-    /// Creates a signed transaction from inputs and outputs.
+    /// Creates a regular transaction from inputs and outputs, signing every input with
+    /// `private_key`, over the whole transaction (`SigHashType::All`).
     pub fn create_transaction(
         inputs: Vec<TransactionInput>,
         outputs: Vec<TransactionOutput>,
         private_key: SecretKey,
     ) -> Transaction {
-        let timestamp = Utc::now().to_rfc3339();
-        let status = TransactionStatus::Pending;
-
-        // Creates transaction hash
-        let transaction_hash =
-            TransactionHelper::generate_transaction_hash(&inputs, &outputs, &timestamp, &status);
-        let r#type = TransactionType::Coinbase;
+        let transaction = TransactionManager::create_unsigned_transaction(
+            TransactionType::Regular,
+            inputs,
+            outputs,
+        );
+        TransactionManager::sign_all_inputs(transaction, &private_key)
+    }
 
-        // Signs the transaction hash using Wallet private key
-        let signature_obj = TransactionHelper::sign_transaction(&private_key, transaction_hash);
-        let signature = signature_obj.serialize_compact().to_vec(); // Convert signature to Vec<u8>
+    /// Creates a fee transaction, leaving the whole value of `inputs` to the miner,
+    /// signing every input with `private_key` over the whole transaction.
+    pub fn create_fee_transaction(
+        inputs: Vec<TransactionInput>,
+        private_key: SecretKey,
+    ) -> Transaction {
+        let transaction =
+            TransactionManager::create_unsigned_transaction(TransactionType::Fee, inputs, vec![]);
+        TransactionManager::sign_all_inputs(transaction, &private_key)
+    }
 
+    /// Creates a transaction of type `r#type` from inputs and outputs, leaving the inputs
+    /// to be signed, see [`Transaction::sign_input`]
+    pub fn create_unsigned_transaction(
+        r#type: TransactionType,
+        inputs: Vec<TransactionInput>,
+        outputs: Vec<TransactionOutput>,
+    ) -> Transaction {
         let metadata = TransactionMetadata {
-            timestamp,
-            status,
-            transaction_hash,
+            transaction_hash: [0u8; 32],
+            version: TRANSACTION_VERSION,
+            timestamp: Utc::now().to_rfc3339(),
             r#type,
-            signature,
         };
 
-        Transaction {
+        let mut transaction = Transaction {
             inputs,
             outputs,
             metadata,
-        }
+        };
+        transaction.metadata.transaction_hash = transaction
+            .hash()
+            .expect("Transactions are created at the current version.");
+        transaction
     }
 
+    /// Creates the coinbase of a block, paying `amount` to `recipient_addr`. Its null input
+    /// spends nothing, so there is nothing to sign; `nonce` is the miner extra nonce.
     pub fn create_coinbase_transaction(
-        private_key: &SecretKey,
         public_key: &PublicKey,
         recipient_addr: &str,
         amount: u64,
         nonce: u64
     ) -> Transaction {
         let transaction_input = TransactionInput {
-            previous_tx_hash: [0u8; 32],
-            index: 0,
-            signature: String::from("INITIAL_COINBASE_SIGNATURE"),
-            public_key: public_key.clone(),
-            amount: amount,
+            previous_tx_hash: OutPoint::NULL.transaction_hash,
+            index: OutPoint::NULL.index,
+            signature: Vec::new(),
+            public_key: *public_key,
+            amount,
             nonce,
         };
 
@@ -176,9 +305,16 @@ impl TransactionManager {
         };
         let outputs = vec![transaction_output];
 
-        let tx = TransactionManager::create_transaction(inputs, outputs, private_key.clone());
+        TransactionManager::create_unsigned_transaction(TransactionType::Coinbase, inputs, outputs)
+    }
 
-        tx
+    fn sign_all_inputs(mut transaction: Transaction, private_key: &SecretKey) -> Transaction {
+        for index in 0..transaction.inputs.len() {
+            transaction
+                .sign_input(index, private_key, SigHashType::All)
+                .expect("Every input of the transaction can be signed.");
+        }
+        transaction
     }
 
     pub fn broadcast_transaction(&self, _transaction: &Transaction) -> Result<(), String> {
@@ -186,13 +322,13 @@ impl TransactionManager {
         todo!()
     }
 
-    pub fn calculate_fee(&self, _transaction: &Transaction) -> u64 {
-        // Calculate transaction fee
-        todo!()
+    /// Fee paid by `transaction`, its inputs minus its outputs
+    pub fn calculate_fee(&self, transaction: &Transaction) -> u64 {
+        transaction.fee()
     }
 
     pub fn track_transaction(&self, _tx_id: &str) -> Result<TransactionStatus, String> {
-        // Track the status of the transaction
+        // Track the status of the transaction, see `Blockchain::transaction_status`
         todo!()
     }
 }
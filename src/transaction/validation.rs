@@ -0,0 +1,560 @@
+//! # Transaction Validation
+//!
+//! Consensus checks a transaction must pass before it is admitted to the mempool
+//! or connected as part of a block.
+//!
+//! - [`check_transaction`]: context-free checks (structure, size, version and hash,
+//!   input signatures, dust outside coinbases, overflow).
+//! - [`validate_transaction`]: full validation of a regular transaction against a [`UtxoView`],
+//!   in the [`SpendContext`] of the block that would include it.
+//! - [`validate_coinbase`]: structural validation of a coinbase transaction.
+//! - [`validate_block_transactions`]: validates the transactions of a block in order,
+//!   letting later transactions spend outputs created earlier in the same block.
+//!
+//! The transaction type sets its shape: a coinbase has a single null input and comes first in
+//! its block, a regular transaction spends previous outputs into new ones, and a fee transaction
+//! spends previous outputs without creating any.
+//!
+//! An input is only valid if its public key owns the address of the output it spends, and
+//! its signature, see [`sighash`](super::SigHashType), verifies with that key.
+//!
+//! Coinbase outputs only become spendable once `coinbase_maturity` blocks were mined on top
+//! of the block creating them, so a reorganization cannot erase coins already spent onwards.
+
+use std::collections::{HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use super::{
+    verify_input, OutPoint, Transaction, TransactionEncodingError, TransactionOutput,
+    TransactionType,
+};
+use crate::{
+    config::{TRANSACTION_DUST_THRESHOLD, TRANSACTION_MAX_SIZE},
+    utils::TransactionHelper,
+};
+
+/// An unspent output together with the data needed to validate its spending.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UtxoEntry {
+    pub output: TransactionOutput,
+    pub height: u64,       // Height of the block that created the output
+    pub is_coinbase: bool, // Created by a coinbase transaction
+}
+
+impl UtxoEntry {
+    /// Whether the output may be spent by the block described by `context`:
+    /// always for regular outputs, once buried deep enough for coinbase outputs
+    pub fn is_mature(&self, context: SpendContext) -> bool {
+        !self.is_coinbase || context.height.saturating_sub(self.height) >= context.coinbase_maturity
+    }
+}
+
+/// Read access to the set of unspent outputs.
+pub trait UtxoView {
+    /// Returns the unspent output referenced by `outpoint`, or `None` if it is unknown or spent
+    fn unspent_output(&self, outpoint: &OutPoint) -> Option<UtxoEntry>;
+}
+
+/// Block a transaction is validated for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpendContext {
+    pub height: u64,            // Height of the block spending the outputs
+    pub coinbase_maturity: u64, // Blocks between a coinbase and the first block that may spend it
+}
+
+/// Reasons a transaction is invalid.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum TransactionValidationError {
+    #[error("Transaction has no inputs")]
+    EmptyInputs,
+    #[error("Transaction has no outputs")]
+    EmptyOutputs,
+    #[error("Fee transaction must not have outputs")]
+    FeeWithOutputs,
+    #[error("Transaction size of {size} bytes exceeds the maximum of {max} bytes")]
+    Oversize { size: usize, max: usize },
+    #[error("Transaction cannot be encoded: {0}")]
+    Encoding(TransactionEncodingError),
+    #[error("Transaction hash does not match its contents")]
+    HashMismatch,
+    #[error("Invalid signature for input {0}")]
+    InvalidSignature(usize),
+    #[error("Input {0} spends the same output as a previous input")]
+    DuplicateInput(usize),
+    #[error("Input {0} has a null outpoint, which is only allowed in a coinbase")]
+    NullInput(usize),
+    #[error("Input {0} references an unknown or already spent output")]
+    MissingOutput(usize),
+    #[error("Input {index} spends a coinbase output {depth} blocks deep, {required} required")]
+    ImmatureCoinbaseSpend {
+        index: usize,
+        depth: u64,
+        required: u64,
+    },
+    #[error("Input {0} public key does not own the address of the spent output")]
+    PublicKeyMismatch(usize),
+    #[error("Input {0} amount does not match the spent output")]
+    InputAmountMismatch(usize),
+    #[error("Output {0} is below the dust threshold")]
+    DustOutput(usize),
+    #[error("Transaction amounts overflow")]
+    Overflow,
+    #[error("Inputs ({inputs}) are lower than outputs ({outputs})")]
+    ValueImbalance { inputs: u64, outputs: u64 },
+    #[error("Coinbase transaction is only allowed as the first transaction of a block")]
+    UnexpectedCoinbase,
+    #[error("Coinbase transaction must have exactly one null input")]
+    InvalidCoinbase,
+}
+
+/// Context-free checks, valid for every transaction type
+pub fn check_transaction(transaction: &Transaction) -> Result<(), TransactionValidationError> {
+    if transaction.inputs().is_empty() {
+        return Err(TransactionValidationError::EmptyInputs);
+    }
+
+    let has_outputs = !transaction.outputs().is_empty();
+    match (transaction.metadata().r#type(), has_outputs) {
+        (TransactionType::Fee, true) => {
+            return Err(TransactionValidationError::FeeWithOutputs);
+        }
+        (TransactionType::Coinbase | TransactionType::Regular, false) => {
+            return Err(TransactionValidationError::EmptyOutputs);
+        }
+        _ => {}
+    }
+
+    // A coinbase spends nothing, any other transaction only spends previous outputs
+    let coinbase = transaction.is_coinbase();
+    if coinbase && (transaction.inputs().len() != 1 || !transaction.inputs()[0].is_null()) {
+        return Err(TransactionValidationError::InvalidCoinbase);
+    }
+    if let Some(idx) = transaction.inputs().iter().position(|input| input.is_null()) {
+        if !coinbase {
+            return Err(TransactionValidationError::NullInput(idx));
+        }
+    }
+
+    let size = transaction.size();
+    if size > TRANSACTION_MAX_SIZE {
+        return Err(TransactionValidationError::Oversize {
+            size,
+            max: TRANSACTION_MAX_SIZE,
+        });
+    }
+
+    let hash = transaction
+        .hash()
+        .map_err(TransactionValidationError::Encoding)?;
+    if hash != transaction.metadata().transaction_hash {
+        return Err(TransactionValidationError::HashMismatch);
+    }
+
+    // Coinbase inputs spend nothing, there is nothing to sign for
+    let mut seen = HashSet::new();
+    for (idx, input) in transaction.inputs().iter().enumerate() {
+        if !coinbase && verify_input(transaction, idx).is_err() {
+            return Err(TransactionValidationError::InvalidSignature(idx));
+        }
+
+        if !seen.insert(input.outpoint()) {
+            return Err(TransactionValidationError::DuplicateInput(idx));
+        }
+    }
+
+    let mut total: u64 = 0;
+    for (idx, output) in transaction.outputs().iter().enumerate() {
+        // Coinbases are exempt from dust: once issuance stops, one without fees pays 0
+        if !coinbase && output.amount < TRANSACTION_DUST_THRESHOLD {
+            return Err(TransactionValidationError::DustOutput(idx));
+        }
+        total = total
+            .checked_add(output.amount)
+            .ok_or(TransactionValidationError::Overflow)?;
+    }
+
+    Ok(())
+}
+
+/// Validates a regular (non-coinbase) transaction against the unspent outputs in `utxos`,
+/// for inclusion in the block described by `context`. Returns the fee paid by the transaction.
+pub fn validate_transaction(
+    transaction: &Transaction,
+    utxos: &impl UtxoView,
+    context: SpendContext,
+) -> Result<u64, TransactionValidationError> {
+    check_transaction(transaction)?;
+
+    if transaction.is_coinbase() {
+        return Err(TransactionValidationError::UnexpectedCoinbase);
+    }
+
+    let mut inputs: u64 = 0;
+    for (idx, input) in transaction.inputs().iter().enumerate() {
+        let spent = utxos
+            .unspent_output(&input.outpoint())
+            .ok_or(TransactionValidationError::MissingOutput(idx))?;
+
+        if TransactionHelper::address_from_public_key(&input.public_key)
+            != spent.output.recipient_address
+        {
+            return Err(TransactionValidationError::PublicKeyMismatch(idx));
+        }
+
+        if !spent.is_mature(context) {
+            return Err(TransactionValidationError::ImmatureCoinbaseSpend {
+                index: idx,
+                depth: context.height.saturating_sub(spent.height),
+                required: context.coinbase_maturity,
+            });
+        }
+
+        if spent.output.amount != input.amount {
+            return Err(TransactionValidationError::InputAmountMismatch(idx));
+        }
+
+        inputs = inputs
+            .checked_add(spent.output.amount)
+            .ok_or(TransactionValidationError::Overflow)?;
+    }
+
+    // Overflow was already ruled out by `check_transaction`
+    let outputs = transaction.output_amount();
+    if inputs < outputs {
+        return Err(TransactionValidationError::ValueImbalance { inputs, outputs });
+    }
+
+    Ok(inputs - outputs)
+}
+
+/// Validates the structure of a coinbase transaction. Returns the value it creates.
+pub fn validate_coinbase(transaction: &Transaction) -> Result<u64, TransactionValidationError> {
+    check_transaction(transaction)?;
+
+    if !transaction.is_coinbase() {
+        return Err(TransactionValidationError::InvalidCoinbase);
+    }
+
+    Ok(transaction.output_amount())
+}
+
+/// A transaction of a block failed validation.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("Transaction {index} of the block is invalid: {error}")]
+pub struct BlockTransactionError {
+    pub index: usize,
+    pub error: TransactionValidationError,
+}
+
+/// Validates the transactions of the block described by `context` in order: a coinbase first,
+/// followed by regular transactions, which may spend outputs created earlier in the block.
+/// Returns the total fees paid by the regular transactions.
+pub fn validate_block_transactions(
+    transactions: &[Transaction],
+    utxos: &impl UtxoView,
+    context: SpendContext,
+) -> Result<u64, BlockTransactionError> {
+    let fail = |index, error| BlockTransactionError { index, error };
+
+    let coinbase = transactions
+        .first()
+        .ok_or(fail(0, TransactionValidationError::InvalidCoinbase))?;
+    validate_coinbase(coinbase).map_err(|error| fail(0, error))?;
+
+    let mut view = BlockUtxoView::new(utxos, context.height);
+    view.apply(coinbase);
+
+    let mut fees: u64 = 0;
+    for (index, transaction) in transactions.iter().enumerate().skip(1) {
+        let fee = validate_transaction(transaction, &view, context)
+            .map_err(|error| fail(index, error))?;
+        fees = fees
+            .checked_add(fee)
+            .ok_or(fail(index, TransactionValidationError::Overflow))?;
+        view.apply(transaction);
+    }
+
+    Ok(fees)
+}
+
+/// Overlay of a [`UtxoView`] with the effect of the transactions already validated in a block.
+struct BlockUtxoView<'a, V: UtxoView> {
+    base: &'a V,
+    height: u64,
+    created: HashMap<OutPoint, UtxoEntry>,
+    spent: HashSet<OutPoint>,
+}
+
+impl<'a, V: UtxoView> BlockUtxoView<'a, V> {
+    fn new(base: &'a V, height: u64) -> Self {
+        Self {
+            base,
+            height,
+            created: HashMap::new(),
+            spent: HashSet::new(),
+        }
+    }
+
+    fn apply(&mut self, transaction: &Transaction) {
+        if !transaction.is_coinbase() {
+            for input in transaction.inputs() {
+                let outpoint = input.outpoint();
+                if self.created.remove(&outpoint).is_none() {
+                    self.spent.insert(outpoint);
+                }
+            }
+        }
+
+        for (index, output) in transaction.outputs().iter().enumerate() {
+            let outpoint = OutPoint {
+                transaction_hash: transaction.metadata().transaction_hash,
+                index: index as u32,
+            };
+            let entry = UtxoEntry {
+                output: output.clone(),
+                height: self.height,
+                is_coinbase: transaction.is_coinbase(),
+            };
+            self.created.insert(outpoint, entry);
+        }
+    }
+}
+
+impl<V: UtxoView> UtxoView for BlockUtxoView<'_, V> {
+    fn unspent_output(&self, outpoint: &OutPoint) -> Option<UtxoEntry> {
+        if let Some(entry) = self.created.get(outpoint) {
+            return Some(entry.clone());
+        }
+        if self.spent.contains(outpoint) {
+            return None;
+        }
+        self.base.unspent_output(outpoint)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hdwallet::secp256k1::{PublicKey, Secp256k1, SecretKey};
+
+    use super::*;
+    use crate::transaction::test_utils::{coinbase, keys, owner, spend};
+    use crate::transaction::{TransactionInput, TransactionManager};
+
+    const CONTEXT: SpendContext = SpendContext {
+        height: 2,
+        coinbase_maturity: 1,
+    };
+
+    struct TestUtxos(HashMap<OutPoint, UtxoEntry>);
+
+    impl UtxoView for TestUtxos {
+        fn unspent_output(&self, outpoint: &OutPoint) -> Option<UtxoEntry> {
+            self.0.get(outpoint).cloned()
+        }
+    }
+
+    fn funding() -> (OutPoint, TestUtxos) {
+        let outpoint = OutPoint {
+            transaction_hash: [9u8; 32],
+            index: 0,
+        };
+        let entry = UtxoEntry {
+            output: TransactionOutput {
+                recipient_address: owner(),
+                amount: 50,
+            },
+            height: 1,
+            is_coinbase: false,
+        };
+        (outpoint, TestUtxos(HashMap::from([(outpoint, entry)])))
+    }
+
+    #[test]
+    fn it_accepts_valid_transaction_and_returns_fee() {
+        let (outpoint, utxos) = funding();
+        let transaction = spend(outpoint, 50, &[30, 15]);
+
+        assert_eq!(validate_transaction(&transaction, &utxos, CONTEXT), Ok(5));
+    }
+
+    #[test]
+    fn it_rejects_invalid_transactions() {
+        let (outpoint, utxos) = funding();
+
+        let unknown = OutPoint {
+            transaction_hash: [8u8; 32],
+            index: 0,
+        };
+        assert_eq!(
+            validate_transaction(&spend(unknown, 50, &[10]), &utxos, CONTEXT),
+            Err(TransactionValidationError::MissingOutput(0))
+        );
+        assert_eq!(
+            validate_transaction(&spend(outpoint, 50, &[60]), &utxos, CONTEXT),
+            Err(TransactionValidationError::ValueImbalance {
+                inputs: 50,
+                outputs: 60
+            })
+        );
+        assert_eq!(
+            validate_transaction(&spend(outpoint, 40, &[10]), &utxos, CONTEXT),
+            Err(TransactionValidationError::InputAmountMismatch(0))
+        );
+        assert_eq!(
+            validate_transaction(&spend(outpoint, 50, &[10, 0]), &utxos, CONTEXT),
+            Err(TransactionValidationError::DustOutput(1))
+        );
+        assert_eq!(
+            validate_transaction(&spend(outpoint, 50, &[u64::MAX, 1]), &utxos, CONTEXT),
+            Err(TransactionValidationError::Overflow)
+        );
+
+        // Properly signed, but by a key that does not own the spent output
+        let mut stolen = utxos.0.clone();
+        stolen.get_mut(&outpoint).unwrap().output.recipient_address = "victim".to_string();
+        assert_eq!(
+            validate_transaction(&spend(outpoint, 50, &[40]), &TestUtxos(stolen), CONTEXT),
+            Err(TransactionValidationError::PublicKeyMismatch(0))
+        );
+
+        let coinbase = coinbase(20);
+        assert_eq!(
+            validate_transaction(&coinbase, &utxos, CONTEXT),
+            Err(TransactionValidationError::UnexpectedCoinbase)
+        );
+    }
+
+    #[test]
+    fn it_enforces_type_specific_rules() {
+        let (outpoint, utxos) = funding();
+        let (secret_key, public_key) = keys();
+        let input = |outpoint: OutPoint| TransactionInput {
+            previous_tx_hash: outpoint.transaction_hash,
+            index: outpoint.index,
+            signature: Vec::new(),
+            public_key,
+            amount: 50,
+            nonce: 0,
+        };
+
+        assert_eq!(
+            validate_transaction(&spend(OutPoint::NULL, 50, &[10]), &utxos, CONTEXT),
+            Err(TransactionValidationError::NullInput(0))
+        );
+
+        // A fee transaction leaves its whole input to the miner
+        let fee = TransactionManager::create_fee_transaction(vec![input(outpoint)], secret_key);
+        assert_eq!(validate_transaction(&fee, &utxos, CONTEXT), Ok(50));
+        let outputs = spend(outpoint, 50, &[10]).outputs().clone();
+        let paying = TransactionManager::create_unsigned_transaction(
+            TransactionType::Fee,
+            vec![input(outpoint)],
+            outputs.clone(),
+        );
+        assert_eq!(check_transaction(&paying), Err(TransactionValidationError::FeeWithOutputs));
+
+        let coinbase = TransactionManager::create_unsigned_transaction(
+            TransactionType::Coinbase,
+            vec![input(OutPoint::NULL), input(outpoint)],
+            outputs,
+        );
+        assert_eq!(validate_coinbase(&coinbase), Err(TransactionValidationError::InvalidCoinbase));
+    }
+
+    #[test]
+    fn it_exempts_only_coinbases_from_dust() {
+        let (outpoint, _) = funding();
+
+        assert_eq!(check_transaction(&coinbase(0)), Ok(()));
+        assert_eq!(
+            check_transaction(&spend(outpoint, 50, &[0])),
+            Err(TransactionValidationError::DustOutput(0))
+        );
+    }
+
+    #[test]
+    fn it_refuses_immature_coinbase_spends() {
+        let (outpoint, mut utxos) = funding();
+        utxos.0.get_mut(&outpoint).unwrap().is_coinbase = true;
+        let transaction = spend(outpoint, 50, &[50]);
+
+        let context = SpendContext {
+            height: 10,
+            coinbase_maturity: 10,
+        };
+        assert_eq!(
+            validate_transaction(&transaction, &utxos, context),
+            Err(TransactionValidationError::ImmatureCoinbaseSpend {
+                index: 0,
+                depth: 9,
+                required: 10
+            })
+        );
+
+        let matured = SpendContext {
+            height: 11,
+            ..context
+        };
+        assert_eq!(validate_transaction(&transaction, &utxos, matured), Ok(0));
+    }
+
+    #[test]
+    fn it_rejects_tampered_transactions() {
+        let (outpoint, utxos) = funding();
+        let transaction = spend(outpoint, 50, &[40]);
+
+        // Redirect the output without re-signing
+        let mut tampered = serde_json::to_value(&transaction).unwrap();
+        tampered["outputs"][0]["recipient_address"] = "attacker".into();
+        let tampered: Transaction = serde_json::from_value(tampered).unwrap();
+        assert_eq!(
+            validate_transaction(&tampered, &utxos, CONTEXT),
+            Err(TransactionValidationError::HashMismatch)
+        );
+
+        // Signed by a key that is not the input's key
+        let mut forged = serde_json::to_value(&transaction).unwrap();
+        let other_key = PublicKey::from_secret_key(
+            &Secp256k1::new(),
+            &SecretKey::from_slice(&[4u8; 32]).unwrap(),
+        );
+        forged["inputs"][0]["public_key"] = other_key.to_string().into();
+        let forged: Transaction = serde_json::from_value(forged).unwrap();
+        assert!(matches!(
+            validate_transaction(&forged, &utxos, CONTEXT),
+            Err(TransactionValidationError::HashMismatch)
+                | Err(TransactionValidationError::InvalidSignature(0))
+        ));
+    }
+
+    #[test]
+    fn it_validates_chained_transactions_within_a_block() {
+        let (outpoint, utxos) = funding();
+        let coinbase = coinbase(20);
+        let parent = spend(outpoint, 50, &[45]);
+        let child = spend(
+            OutPoint {
+                transaction_hash: parent.metadata().transaction_hash,
+                index: 0,
+            },
+            45,
+            &[40],
+        );
+
+        let block = vec![coinbase.clone(), parent.clone(), child.clone()];
+        assert_eq!(validate_block_transactions(&block, &utxos, CONTEXT), Ok(10));
+
+        // The child cannot come before its parent, nor can the parent be spent twice
+        let reordered = vec![coinbase.clone(), child, parent.clone()];
+        assert!(validate_block_transactions(&reordered, &utxos, CONTEXT).is_err());
+        let double_spend = vec![coinbase, parent.clone(), parent];
+        assert_eq!(
+            validate_block_transactions(&double_spend, &utxos, CONTEXT)
+                .unwrap_err()
+                .index,
+            2
+        );
+    }
+}
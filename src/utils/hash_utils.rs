@@ -6,41 +6,55 @@
 use sha2::{Digest, Sha256};
 
 use crate::blockchain::Block;
-use crate::transaction::Transaction;
 
 /// HashHelper struct storing various helper methods related to hashing
 pub struct HashHelper;
 
 impl HashHelper {
-    /// Generates hash based on previous block hash, difficulty, timestamp, transactions vector and nonce
-    pub fn generate_hash(
-        previous_hash: &String,
-        difficulty: u8,
-        timestamp: &String,
-        transactions: &Vec<Transaction>,
-        nonce: u64,
-    ) -> String {
-        let combined_string = format!(
-            "{}{}{}{:?}{}",
-            previous_hash, difficulty, timestamp, transactions, nonce
-        );
-        let mut hasher = Sha256::new();
-        hasher.update(combined_string);
-        let hash_result = hasher.finalize();
-        let hash_result = format!("{:x}", hash_result);
-        hash_result
+    /// Generates block hash from the canonical encoding of the block header,
+    /// see [`BlockHeader::encode`](crate::blockchain::BlockHeader::encode)
+    pub fn generate_hash(encoded_header: &[u8]) -> String {
+        Self::to_hex(&Self::hash_bytes(encoded_header))
+    }
+
+    /// SHA-256 of the encoded block header, as raw bytes
+    pub fn hash_bytes(encoded_header: &[u8]) -> [u8; 32] {
+        Sha256::digest(encoded_header).into()
+    }
+
+    /// Encodes bytes (e.g. a transaction hash) as a lowercase hex string
+    pub fn to_hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    /// Decodes a hex string into a 32-byte hash
+    pub fn hash_from_hex(hex: &str) -> Option<[u8; 32]> {
+        if hex.len() != 64 {
+            return None;
+        }
+        Self::bytes_from_hex(hex)?.try_into().ok()
+    }
+
+    /// Decodes a hex string of any even length into bytes
+    pub fn bytes_from_hex(hex: &str) -> Option<Vec<u8>> {
+        if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+            return None;
+        }
+
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+            .collect()
     }
 
     /// Checks if current block hash valid hash
-    /// by recalculating the hash using block data and comparing it to the currently stored hash
+    /// by recalculating the hash of the encoded block header and comparing it to the currently
+    /// stored hash.
+    /// Transactions are covered through the merkle root, checked against the body separately
     pub fn is_valid_hash(block: &Block) -> bool {
-        let hash = Self::generate_hash(
-            block.header().previous_hash(),
-            block.header().difficulty(),
-            block.header().timestamp(),
-            block.body().transactions(),
-            block.header().nonce(),
-        );
-        &hash == block.header().current_hash()
+        match block.header().hash() {
+            Ok(hash) => &hash == block.header().current_hash(),
+            Err(_) => false,
+        }
     }
 }
@@ -1,39 +1,21 @@
 //! # Transaction Utils
 //!
-//! Provides tools for hashing, signing, and verifying transactions.
+//! Provides tools for addressing, signing, and verifying transactions.
+//! Transaction ids are computed by `Transaction::hash`.
 //!
 
 use hdwallet::secp256k1::{ecdsa::Signature, Message, PublicKey, Secp256k1, SecretKey};
 use sha2::{Digest, Sha256};
 
-use crate::transaction::{TransactionInput, TransactionOutput, TransactionStatus};
+use super::HashHelper;
 /// Transaction-related helper functions.
 pub struct TransactionHelper {}
 
 impl TransactionHelper {
-    /// Generates a 32-byte transaction hash from inputs, outputs, timestamp, and status.
-    pub fn generate_transaction_hash(
-        inputs: &Vec<TransactionInput>,
-        outputs: &Vec<TransactionOutput>,
-        timestamp: &String,
-        status: &TransactionStatus,
-    ) -> [u8; 32] {
-        // 1. Serialize the transaction deterministically
-        let tx_bytes = bincode::encode_to_vec(
-            &(inputs, outputs, timestamp, status),
-            bincode::config::standard(),
-        )
-        .expect("Serialization failed");
-
-        // 2. Hash the bytes
-        let mut hasher = Sha256::new();
-        hasher.update(&tx_bytes);
-        let result = hasher.finalize();
-
-        // 3. Return raw bytes (32 bytes)
-        let mut hash_bytes = [0u8; 32];
-        hash_bytes.copy_from_slice(&result);
-        hash_bytes
+    /// Address owned by `public_key`: the hex encoded SHA-256 of the public key hex string
+    pub fn address_from_public_key(public_key: &PublicKey) -> String {
+        let hash = Sha256::digest(public_key.to_string());
+        HashHelper::to_hex(&hash)
     }
 
     /// Signs transaction using ECDSA with the given Wallet secret/private key
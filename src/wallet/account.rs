@@ -2,76 +2,443 @@
 //!
 //! Represents a wallet account, including its address, balance, and transaction history.
 //!
+//! Each account owns the keys below its BIP44 path `m/44'/coin_type'/account'`, see
+//! [`derivation`](super::derivation). It keeps the account extended public key, enough to
+//! derive fresh receive and change addresses without the wallet seed.
+//!
+//! Per chain, the account hands out at most `WALLET_GAP_LIMIT` unused addresses in a row
+//! after the last used one, so restoring the wallet and scanning that many addresses past
+//! the last used one finds every address that may have been paid.
+//!
+//! Payments spend the unspent outputs of the account, as last synced from the node together
+//! with the block they could be spent in.
+//!
+//! The account is persisted as an [`AccountRecord`], holding no secret: the addresses are
+//! derived again from the extended public key when the account is loaded.
+
+use std::collections::BTreeMap;
 
 use chrono::Utc;
-use hdwallet::secp256k1::PublicKey;
-use sha2::{Digest, Sha256};
+use hdwallet::{
+    secp256k1::PublicKey,
+    traits::{Deserialize as _, Serialize as _},
+    ExtendedPubKey,
+};
+use thiserror::Error;
 
-use crate::transaction::Transaction;
+use super::{derivation, AccountRecord, Chain, DerivationPath};
+use crate::{
+    blockchain::{Balance, HistoryEntry},
+    config::{BLOCKCHAIN_COINBASE_MATURITY, WALLET_GAP_LIMIT},
+    transaction::{OutPoint, SpendContext, Transaction, TransactionStatus, UtxoEntry},
+    utils::{HashHelper, TransactionHelper},
+};
 
-/// Stores account data including address, balance, and transaction history.
+/// Length of a serialized extended public key: compressed public key and chain code
+const EXTENDED_PUBLIC_KEY_LEN: usize = 65;
+
+/// Errors raised while handing out addresses.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum AccountError {
+    #[error("{limit} unused {chain:?} addresses already handed out")]
+    GapLimitReached { chain: Chain, limit: u32 },
+    #[error("Cannot derive address key: {0}")]
+    KeyDerivation(String),
+    #[error("Stored {chain:?} address {index} does not derive from the account key")]
+    AddressMismatch { chain: Chain, index: u32 },
+}
+
+/// Addresses handed out on one chain of an account, by index
 #[derive(Debug, Clone, Default)]
+struct AddressChain {
+    addresses: Vec<(String, PublicKey)>,
+    used: u32, // Index after the last used address, 0 if none was used
+}
+
+/// Stores account data including address, balance, and transaction history.
+#[derive(Debug, Clone)]
 pub struct Account {
-    address: String,
+    index: u32, // BIP44 account number
+    public_key: ExtendedPubKey, // Extended public key at the account path
+    address: String, // First receive address
     name: String,
-    balance: u64,
+    balance: Balance, // Last known balance, split into spendable, immature and unconfirmed amounts
     created_at: String,
-    transaction_history: Vec<Transaction>, // local mempool
+    transaction_history: Vec<HistoryEntry>, // Transactions of the account, as last synced
+    receive: AddressChain,
+    change: AddressChain,
+    gap_limit: u32,
+    labels: BTreeMap<String, String>, // Labels by address
+    utxos: Vec<(OutPoint, UtxoEntry)>, // Unspent outputs paying the account, as last synced
+    spend_context: SpendContext,       // Next block of the chain the outputs were synced from
 }
 
 impl Account {
-    /// Creates a new account from a public key and name.
-    pub fn new(public_key: &PublicKey, name: &str) -> Self {
-        let created_at = Utc::now().to_rfc3339();
-        let address = Self::generate_address(public_key);
-        let transaction_history = vec![];
-        let name = String::from(name);
+    /// Creates account `index` from its extended public key (at `m/44'/coin_type'/index'`)
+    /// and name, handing out its first receive address.
+    pub fn new(index: u32, public_key: ExtendedPubKey, name: &str) -> Result<Self, AccountError> {
+        let mut account = Self::empty(index, public_key, name, Utc::now().to_rfc3339());
+        account.address = account.next_address(Chain::External)?;
+        Ok(account)
+    }
+
+    /// Rebuilds an account from its record, deriving its addresses again from the extended
+    /// public key and checking them against the stored ones
+    pub fn from_record(record: &AccountRecord) -> Result<Self, AccountError> {
+        let public_key = HashHelper::bytes_from_hex(&record.extended_public_key)
+            .filter(|bytes| bytes.len() == EXTENDED_PUBLIC_KEY_LEN)
+            .and_then(|bytes| ExtendedPubKey::deserialize(bytes.as_slice()).ok())
+            .ok_or_else(|| AccountError::KeyDerivation("Invalid extended public key".into()))?;
+        let mut account =
+            Self::empty(record.index, public_key, &record.name, record.created_at.clone());
+        account.receive.used = record.receive_used;
+        account.change.used = record.change_used;
+        account.labels = record.labels.clone();
+
+        for (chain, addresses) in [
+            (Chain::External, &record.receive),
+            (Chain::Internal, &record.change),
+        ] {
+            for (index, address) in addresses.iter().enumerate() {
+                if account.next_address(chain)? != *address {
+                    return Err(AccountError::AddressMismatch {
+                        chain,
+                        index: index as u32,
+                    });
+                }
+            }
+        }
+        account.address = match record.receive.first() {
+            Some(address) => address.clone(),
+            None => account.next_address(Chain::External)?,
+        };
+        Ok(account)
+    }
 
+    /// Public metadata of the account, to persist it
+    pub fn to_record(&self) -> AccountRecord {
+        let addresses = |chain: &AddressChain| {
+            chain.addresses.iter().map(|(address, _)| address.clone()).collect()
+        };
+        AccountRecord {
+            index: self.index,
+            name: self.name.clone(),
+            created_at: self.created_at.clone(),
+            extended_public_key: HashHelper::to_hex(&self.public_key.serialize()),
+            receive: addresses(&self.receive),
+            change: addresses(&self.change),
+            receive_used: self.receive.used,
+            change_used: self.change.used,
+            labels: self.labels.clone(),
+        }
+    }
+
+    /// Account without any address handed out yet
+    fn empty(index: u32, public_key: ExtendedPubKey, name: &str, created_at: String) -> Self {
         Self {
-            address,
-            name,
+            index,
+            public_key,
+            address: String::new(),
+            name: String::from(name),
+            balance: Balance::default(),
             created_at,
-            balance: 0,
-            transaction_history,
+            transaction_history: vec![],
+            receive: AddressChain::default(),
+            change: AddressChain::default(),
+            gap_limit: WALLET_GAP_LIMIT,
+            labels: BTreeMap::new(),
+            utxos: vec![],
+            spend_context: SpendContext {
+                height: 0,
+                coinbase_maturity: BLOCKCHAIN_COINBASE_MATURITY,
+            },
+        }
+    }
+
+    /// Hands out the next receive address
+    pub fn next_receive_address(&mut self) -> Result<String, AccountError> {
+        self.next_address(Chain::External)
+    }
+
+    /// Hands out the next change address
+    pub fn next_change_address(&mut self) -> Result<String, AccountError> {
+        self.next_address(Chain::Internal)
+    }
+
+    /// Derives the next address of `chain`, unless the gap limit is reached
+    fn next_address(&mut self, chain: Chain) -> Result<String, AccountError> {
+        let gap_limit = self.gap_limit;
+        let index = self.chain(chain).addresses.len() as u32;
+        if index >= self.chain(chain).used.saturating_add(gap_limit) {
+            return Err(AccountError::GapLimitReached {
+                chain,
+                limit: gap_limit,
+            });
+        }
+
+        let (address, public_key) = self.derive_address(chain, index)?;
+        self.chain_mut(chain).addresses.push((address.clone(), public_key));
+        Ok(address)
+    }
+
+    fn derive_address(
+        &self,
+        chain: Chain,
+        index: u32,
+    ) -> Result<(String, PublicKey), AccountError> {
+        let public_key = derivation::public_key(&self.public_key, chain, index)
+            .map_err(|e| AccountError::KeyDerivation(e.to_string()))?;
+        Ok((Self::generate_address(&public_key), public_key))
+    }
+
+    /// Number of addresses of `chain` up to the last one used
+    pub fn used(&self, chain: Chain) -> u32 {
+        self.chain(chain).used
+    }
+
+    /// The `gap_limit` addresses of `chain` after the last used one, derived without handing
+    /// them out, starting at index [`Account::used`]. Scanned when restoring the account.
+    pub fn lookahead(&self, chain: Chain) -> Result<Vec<String>, AccountError> {
+        let used = self.used(chain);
+        (used..used.saturating_add(self.gap_limit))
+            .map(|index| Ok(self.derive_address(chain, index)?.0))
+            .collect()
+    }
+
+    /// Records that the address of `chain` at `index` received funds, handing out every
+    /// address up to it. Fails if that is past the gap limit.
+    pub fn mark_used_at(&mut self, chain: Chain, index: u32) -> Result<(), AccountError> {
+        if index >= self.used(chain).saturating_add(self.gap_limit) {
+            return Err(AccountError::GapLimitReached {
+                chain,
+                limit: self.gap_limit,
+            });
+        }
+        while self.chain(chain).addresses.len() as u32 <= index {
+            self.next_address(chain)?;
+        }
+        let chain = self.chain_mut(chain);
+        chain.used = chain.used.max(index + 1);
+        Ok(())
+    }
+
+    /// Records that `address` received funds, allowing more addresses to be handed out
+    /// after it. Returns false if the address is not one of the account.
+    pub fn mark_used(&mut self, address: &str) -> bool {
+        let Some(path) = self.path_of(address) else {
+            return false;
+        };
+        let chain = self.chain_mut(path.chain);
+        chain.used = chain.used.max(path.index + 1);
+        true
+    }
+
+    /// Path of an address handed out by the account
+    pub fn path_of(&self, address: &str) -> Option<DerivationPath> {
+        [Chain::External, Chain::Internal].into_iter().find_map(|chain| {
+            let index = self.chain(chain).addresses.iter().position(|(a, _)| a == address)?;
+            Some(DerivationPath {
+                account: self.index,
+                chain,
+                index: index as u32,
+            })
+        })
+    }
+
+    /// Public key of an address handed out by the account
+    pub fn public_key_of(&self, address: &str) -> Option<PublicKey> {
+        let path = self.path_of(address)?;
+        Some(self.chain(path.chain).addresses[path.index as usize].1)
+    }
+
+    /// Labels an address of the account, e.g. with the payer it was given to.
+    /// Returns false if the address is not one of the account.
+    pub fn set_label(&mut self, address: &str, label: &str) -> bool {
+        if self.path_of(address).is_none() {
+            return false;
+        }
+        self.labels.insert(address.to_string(), label.to_string());
+        true
+    }
+
+    pub fn label(&self, address: &str) -> Option<&String> {
+        self.labels.get(address)
+    }
+
+    /// Addresses handed out on `chain`, by index
+    pub fn addresses(&self, chain: Chain) -> Vec<&String> {
+        self.chain(chain).addresses.iter().map(|(address, _)| address).collect()
+    }
+
+    fn chain(&self, chain: Chain) -> &AddressChain {
+        match chain {
+            Chain::External => &self.receive,
+            Chain::Internal => &self.change,
+        }
+    }
+
+    fn chain_mut(&mut self, chain: Chain) -> &mut AddressChain {
+        match chain {
+            Chain::External => &mut self.receive,
+            Chain::Internal => &mut self.change,
         }
     }
 
+    /// Records the unspent outputs paying the account, on a chain whose next block is
+    /// described by `context`
+    pub fn set_utxos(&mut self, utxos: Vec<(OutPoint, UtxoEntry)>, context: SpendContext) {
+        self.utxos = utxos;
+        self.spend_context = context;
+    }
+
+    pub fn utxos(&self) -> &Vec<(OutPoint, UtxoEntry)> {
+        &self.utxos
+    }
+
+    /// Unspent outputs the next block may spend, i.e. all but immature coinbase outputs
+    pub fn spendable_utxos(&self) -> Vec<(OutPoint, UtxoEntry)> {
+        self.utxos
+            .iter()
+            .filter(|(_, entry)| entry.is_mature(self.spend_context))
+            .cloned()
+            .collect()
+    }
+
+    /// Records a payment sent by the account: its inputs are no longer spendable
+    pub fn record_payment(&mut self, transaction: Transaction) {
+        let spent: Vec<OutPoint> = transaction.inputs().iter().map(|i| i.outpoint()).collect();
+        self.utxos.retain(|(outpoint, _)| !spent.contains(outpoint));
+        self.transaction_history.push(HistoryEntry {
+            transaction,
+            status: TransactionStatus::Pending,
+        });
+    }
+
+    /// Records the transactions of the account reported by the chain. The addresses they pay
+    /// are marked used.
+    pub fn set_history(&mut self, history: Vec<HistoryEntry>) {
+        for entry in &history {
+            for output in entry.transaction.outputs() {
+                self.mark_used(&output.recipient_address);
+            }
+        }
+        self.transaction_history = history;
+    }
+
     /// Generate Account address based on the public key
     fn generate_address(public_key: &PublicKey) -> String {
-        let combined_string = format!("{}", public_key);
-        let mut hasher = Sha256::new();
-        hasher.update(combined_string);
-        let hash_result = hasher.finalize();
-        let hash_result = format!("{:x}", hash_result);
-        hash_result
+        TransactionHelper::address_from_public_key(public_key)
     }
 
-    /// Returns the next nonce based on transaction history length.
+    /// Returns the next nonce based on transaction history length. It numbers payments and
+    /// is shared by all their inputs; a sync replacing the history may repeat it. It need not
+    /// be unique: each input spends an outpoint, which can be spent only once, so that signed
+    /// inputs and transaction hashes are unique whatever the nonce.
     pub fn next_nonce(&self) -> u64 {
         self.transaction_history.len() as u64
     }
 
-    pub fn get_balance() -> u64 {
-        todo!()
+    /// BIP44 account number
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+
+    /// Extended public key of the account, deriving all of its addresses
+    pub fn extended_public_key(&self) -> &ExtendedPubKey {
+        &self.public_key
+    }
+
+    /// Unused addresses handed out in a row on each chain, at most
+    pub fn gap_limit(&self) -> u32 {
+        self.gap_limit
     }
 
     pub fn name(&self) -> &String {
         &self.name
     }
 
-    pub fn transaction_history(&self) -> &Vec<Transaction> {
+    pub fn transaction_history(&self) -> &Vec<HistoryEntry> {
         &self.transaction_history
     }
 
+    /// First receive address of the account
     pub fn address(&self) -> &String {
         &self.address
     }
 
-    pub fn balance(&self) -> u64 {
+    pub fn balance(&self) -> Balance {
         self.balance
     }
 
+    /// Records the balance reported by the chain
+    pub fn set_balance(&mut self, balance: Balance) {
+        self.balance = balance;
+    }
+
     pub fn created_at(&self) -> &String {
         &self.created_at
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use hdwallet::ExtendedPrivKey;
+
+    use super::*;
+
+    fn account() -> Account {
+        let master = ExtendedPrivKey::with_seed(&[5u8; 64]).unwrap();
+        let key = derivation::account_private_key(&master, 0).unwrap();
+        Account::new(0, ExtendedPubKey::from_private_key(&key), "main").unwrap()
+    }
+
+    #[test]
+    fn it_hands_out_fresh_addresses_up_to_the_gap_limit() {
+        let mut account = account();
+        let first = account.address().clone();
+        let change = account.next_change_address().unwrap();
+        assert_ne!(first, change);
+        assert_eq!(
+            account.path_of(&change),
+            Some(DerivationPath {
+                account: 0,
+                chain: Chain::Internal,
+                index: 0
+            })
+        );
+
+        for _ in 1..WALLET_GAP_LIMIT {
+            account.next_receive_address().unwrap();
+        }
+        assert_eq!(
+            account.next_receive_address(),
+            Err(AccountError::GapLimitReached {
+                chain: Chain::External,
+                limit: WALLET_GAP_LIMIT
+            })
+        );
+
+        // Funds received on the second address allow `WALLET_GAP_LIMIT` addresses after it
+        let second = account.addresses(Chain::External)[1].clone();
+        assert!(account.mark_used(&second));
+        assert!(account.next_receive_address().is_ok());
+        assert!(account.next_receive_address().is_ok());
+        assert!(account.next_receive_address().is_err());
+        assert!(!account.mark_used("elsewhere"));
+    }
+
+    #[test]
+    fn it_looks_ahead_of_the_last_used_address() {
+        let mut account = account();
+        let lookahead = account.lookahead(Chain::Internal).unwrap();
+        assert_eq!(lookahead.len(), WALLET_GAP_LIMIT as usize);
+        assert!(account.addresses(Chain::Internal).is_empty());
+
+        // Restoring finds the fourth change address paid
+        account.mark_used_at(Chain::Internal, 3).unwrap();
+        assert_eq!(account.addresses(Chain::Internal), lookahead[..4].iter().collect::<Vec<_>>());
+        assert_eq!(account.lookahead(Chain::Internal).unwrap()[0], lookahead[4]);
+        assert!(account.mark_used_at(Chain::Internal, 4 + WALLET_GAP_LIMIT).is_err());
+        assert_eq!(account.addresses(Chain::Internal).len(), 4);
+    }
+}
@@ -0,0 +1,343 @@
+//! # Coin Selection
+//!
+//! Picks the unspent outputs of an account funding a payment, and the change it needs.
+//!
+//! Every input adds its own fee at the payment fee rate, so outputs are compared by their
+//! *effective value*: their amount minus the fee of spending them. Outputs not worth more
+//! than that are never selected. A selection must cover the payment plus the fee of the
+//! transaction without inputs, see [`PaymentCosts`]. What it leaves over goes to a change
+//! output, unless that is less than the fee of the change output plus the dust threshold:
+//! then it is left to the miner.
+//!
+//! Strategies, see [`CoinSelection`]:
+//! - largest-first: the largest outputs until the payment is covered, the fewest inputs.
+//! - branch-and-bound: a depth-first search for outputs covering the payment so closely that
+//!   no change is needed, wasting the least to fees. Falls back to largest-first.
+//! - random-improve (CIP-2): random outputs until the payment is covered, then more while
+//!   they bring the selection closer to twice the payment, never above three times. The
+//!   change is then about the size of the payment, and can fund payments alike later on.
+
+use std::cmp::Reverse;
+
+use bip39::rand::{seq::SliceRandom, thread_rng};
+use hdwallet::secp256k1::{PublicKey, Secp256k1, SecretKey};
+
+use crate::{
+    config::TRANSACTION_DUST_THRESHOLD,
+    mempool::FeeRate,
+    transaction::{
+        OutPoint, TransactionError, TransactionInput, TransactionManager, TransactionOutput,
+        TransactionType, UtxoEntry,
+    },
+};
+
+/// Longest RFC 3339 timestamp of a transaction, with nanoseconds
+const MAX_TIMESTAMP_LEN: usize = "2025-01-01T00:00:00.000000000+00:00".len();
+
+/// Compact ECDSA signature and sighash type byte
+const SIGNATURE_LEN: usize = 65;
+
+/// Search steps after which branch-and-bound settles for the best match found so far
+const BNB_MAX_TRIES: usize = 100_000;
+
+/// Fees of the parts of a payment transaction at its fee rate, from upper bounds of their
+/// serialized sizes, so the transaction pays at least that rate
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PaymentCosts {
+    pub base: u64,   // Transaction without inputs, paying the recipient
+    pub input: u64,  // Each input
+    pub change: u64, // Change output
+}
+
+impl PaymentCosts {
+    /// Costs of paying `recipient` at `fee_rate`, with change to `change_address`
+    pub fn new(fee_rate: FeeRate, recipient: &str, change_address: &str) -> Self {
+        let output_size = |address: &str| {
+            let output = TransactionOutput {
+                recipient_address: address.to_string(),
+                amount: u64::MAX,
+            };
+            serde_json::to_vec(&output).map(|bytes| bytes.len()).unwrap_or_default()
+        };
+
+        let base = TransactionManager::create_unsigned_transaction(
+            TransactionType::Regular,
+            vec![],
+            vec![TransactionOutput {
+                recipient_address: recipient.to_string(),
+                amount: u64::MAX,
+            }],
+        );
+        let base_size = base.size() - base.metadata().timestamp().len() + MAX_TIMESTAMP_LEN;
+
+        // Separated from the previous input or output by a comma
+        PaymentCosts {
+            base: fee_rate.fee_for(base_size),
+            input: fee_rate.fee_for(max_input_size() + 1),
+            change: fee_rate.fee_for(output_size(change_address) + 1),
+        }
+    }
+}
+
+/// Serialized size of a signed input, at most
+fn max_input_size() -> usize {
+    let secret_key = SecretKey::from_slice(&[1u8; 32]).expect("Secret key is valid.");
+    let input = TransactionInput {
+        previous_tx_hash: [u8::MAX; 32],
+        index: u32::MAX,
+        signature: vec![u8::MAX; SIGNATURE_LEN],
+        public_key: PublicKey::from_secret_key(&Secp256k1::signing_only(), &secret_key),
+        amount: u64::MAX,
+        nonce: u64::MAX,
+    };
+    serde_json::to_vec(&input).map(|bytes| bytes.len()).unwrap_or_default()
+}
+
+/// Outputs selected to fund a payment
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Selection {
+    pub inputs: Vec<(OutPoint, UtxoEntry)>,
+    pub fee: u64,
+    pub change: u64, // Amount of the change output, 0 for none
+}
+
+/// Strategy picking the outputs funding a payment
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CoinSelection {
+    /// Spend the largest outputs first
+    LargestFirst,
+    /// Look for outputs leaving no change, falling back to largest-first
+    #[default]
+    BranchAndBound,
+    /// Spend random outputs, adding more towards twice the payment
+    RandomImprove,
+}
+
+impl CoinSelection {
+    /// Selects outputs of `candidates` paying `amount` and the fees of `costs`
+    pub fn select(
+        &self,
+        candidates: &[(OutPoint, UtxoEntry)],
+        amount: u64,
+        costs: PaymentCosts,
+    ) -> Result<Selection, TransactionError> {
+        // Effective values, largest first
+        let mut candidates: Vec<(&(OutPoint, UtxoEntry), u64)> = candidates
+            .iter()
+            .filter_map(|utxo| {
+                let value = utxo.1.output.amount.checked_sub(costs.input)?;
+                (value > 0).then_some((utxo, value))
+            })
+            .collect();
+        candidates.sort_by_key(|(_, value)| Reverse(*value));
+        let values: Vec<u64> = candidates.iter().map(|(_, value)| *value).collect();
+
+        let target = amount.saturating_add(costs.base);
+        let available = values.iter().fold(0u64, |total, value| total.saturating_add(*value));
+        if available < target {
+            return Err(TransactionError::NotEnoughFunds {
+                available,
+                required: target,
+            });
+        }
+
+        // Any excess up to this is left to the miner rather than paid as change
+        let max_waste = costs.change.saturating_add(TRANSACTION_DUST_THRESHOLD).saturating_sub(1);
+        let selected = match self {
+            CoinSelection::LargestFirst => largest_first(&values, target),
+            CoinSelection::BranchAndBound => branch_and_bound(&values, target, max_waste)
+                .unwrap_or_else(|| largest_first(&values, target)),
+            CoinSelection::RandomImprove => random_improve(&values, target),
+        };
+
+        let total = selected.iter().fold(0u64, |total, &index| total.saturating_add(values[index]));
+        let excess = total - target;
+        let change = if excess > max_waste {
+            excess - costs.change
+        } else {
+            0
+        };
+        let inputs: Vec<(OutPoint, UtxoEntry)> =
+            selected.iter().map(|&index| candidates[index].0.clone()).collect();
+        let input_amount: u64 = inputs.iter().map(|(_, entry)| entry.output.amount).sum();
+        Ok(Selection {
+            inputs,
+            fee: input_amount - amount - change,
+            change,
+        })
+    }
+}
+
+/// Indices of the first `values` (largest first) covering `target`
+fn largest_first(values: &[u64], target: u64) -> Vec<usize> {
+    let mut total = 0u64;
+    (0..values.len())
+        .take_while(|&index| {
+            let covered = total >= target;
+            total = total.saturating_add(values[index]);
+            !covered
+        })
+        .collect()
+}
+
+/// Indices of `values` (largest first) covering `target` with the least excess, at most
+/// `max_waste`, if any
+fn branch_and_bound(values: &[u64], target: u64, max_waste: u64) -> Option<Vec<usize>> {
+    let mut remaining = vec![0u64; values.len() + 1];
+    for index in (0..values.len()).rev() {
+        remaining[index] = remaining[index + 1].saturating_add(values[index]);
+    }
+
+    let mut search = BranchAndBound {
+        values,
+        remaining,
+        target,
+        upper: target.saturating_add(max_waste),
+        tries: BNB_MAX_TRIES,
+        selected: vec![],
+        best: None,
+    };
+    search.explore(0, 0);
+    search.best.map(|(_, selected)| selected)
+}
+
+/// Depth-first search state of branch-and-bound
+struct BranchAndBound<'a> {
+    values: &'a [u64],
+    remaining: Vec<u64>, // Sum of the values from each index on
+    target: u64,
+    upper: u64,
+    tries: usize,
+    selected: Vec<usize>,
+    best: Option<(u64, Vec<usize>)>, // Least excess found, and its selection
+}
+
+impl BranchAndBound<'_> {
+    /// Explores the selections of the values from `index` on, on top of `total`
+    fn explore(&mut self, index: usize, total: u64) {
+        if self.tries == 0 || self.best.as_ref().is_some_and(|(excess, _)| *excess == 0) {
+            return;
+        }
+        self.tries -= 1;
+
+        if total > self.upper || total.saturating_add(self.remaining[index]) < self.target {
+            return;
+        }
+        if total >= self.target {
+            // Any further value only adds to the excess
+            let excess = total - self.target;
+            if self.best.as_ref().is_none_or(|(best, _)| excess < *best) {
+                self.best = Some((excess, self.selected.clone()));
+            }
+            return;
+        }
+
+        self.selected.push(index);
+        self.explore(index + 1, total.saturating_add(self.values[index]));
+        self.selected.pop();
+        self.explore(index + 1, total);
+    }
+}
+
+/// Indices of random `values` covering `target`, improved towards twice the target
+fn random_improve(values: &[u64], target: u64) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..values.len()).collect();
+    order.shuffle(&mut thread_rng());
+    let mut order = order.into_iter();
+
+    let mut selected = vec![];
+    let mut total = 0u64;
+    for index in order.by_ref() {
+        selected.push(index);
+        total = total.saturating_add(values[index]);
+        if total >= target {
+            break;
+        }
+    }
+
+    let ideal = target.saturating_mul(2);
+    let max = target.saturating_mul(3);
+    for index in order {
+        let improved = total.saturating_add(values[index]);
+        if improved > max || improved.abs_diff(ideal) >= total.abs_diff(ideal) {
+            break;
+        }
+        selected.push(index);
+        total = improved;
+    }
+    selected
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COSTS: PaymentCosts = PaymentCosts {
+        base: 10,
+        input: 5,
+        change: 4,
+    };
+
+    fn utxos(amounts: &[u64]) -> Vec<(OutPoint, UtxoEntry)> {
+        amounts
+            .iter()
+            .enumerate()
+            .map(|(index, &amount)| {
+                let outpoint = OutPoint {
+                    transaction_hash: [1u8; 32],
+                    index: index as u32,
+                };
+                let output = TransactionOutput {
+                    recipient_address: "me".to_string(),
+                    amount,
+                };
+                let entry = UtxoEntry {
+                    output,
+                    height: 0,
+                    is_coinbase: false,
+                };
+                (outpoint, entry)
+            })
+            .collect()
+    }
+
+    fn amounts(selection: &Selection) -> Vec<u64> {
+        selection.inputs.iter().map(|(_, entry)| entry.output.amount).collect()
+    }
+
+    #[test]
+    fn it_selects_outputs_with_each_strategy() {
+        // Effective values 100, 55, 40, the last output costs more than it is worth
+        let utxos = utxos(&[60, 105, 3, 45]);
+
+        let largest = CoinSelection::LargestFirst.select(&utxos, 85, COSTS).unwrap();
+        assert_eq!(amounts(&largest), vec![105]);
+        assert_eq!((largest.fee, largest.change), (19, 1));
+
+        let exact = CoinSelection::BranchAndBound.select(&utxos, 85, COSTS).unwrap();
+        assert_eq!(amounts(&exact), vec![60, 45]);
+        assert_eq!((exact.fee, exact.change), (20, 0));
+
+        // No changeless match, falls back to largest-first
+        let fallback = CoinSelection::BranchAndBound.select(&utxos, 135, COSTS).unwrap();
+        assert_eq!(amounts(&fallback), vec![105, 60]);
+        assert_eq!((fallback.fee, fallback.change), (24, 6));
+
+        assert_eq!(
+            CoinSelection::LargestFirst.select(&utxos, 190, COSTS),
+            Err(TransactionError::NotEnoughFunds {
+                available: 195,
+                required: 200
+            })
+        );
+    }
+
+    #[test]
+    fn it_improves_random_selections_towards_twice_the_payment() {
+        // Effective value 10 each, the payment with its base fee needs 5 of them
+        let utxos = utxos(&[15; 30]);
+        let selection = CoinSelection::RandomImprove.select(&utxos, 40, COSTS).unwrap();
+        assert_eq!(selection.inputs.len(), 10);
+        assert_eq!((selection.fee, selection.change), (64, 46));
+    }
+}
@@ -0,0 +1,132 @@
+//! # Derivation
+//!
+//! BIP32/BIP44 key derivation paths of wallet keys:
+//!
+//! ```text
+//! m / 44' / coin_type' / account' / change / index
+//! ```
+//!
+//! Each account derives its own extended key at `m/44'/coin_type'/account'` (hardened).
+//! Below it, the `change` level separates the [`Chain::External`] chain of receive addresses
+//! from the [`Chain::Internal`] chain of change addresses, and `index` numbers the addresses
+//! of a chain. These two levels are not hardened, so an account's extended public key alone
+//! derives all of its addresses.
+
+use std::fmt;
+
+use hdwallet::{
+    secp256k1::{PublicKey, SecretKey},
+    ExtendedPrivKey, ExtendedPubKey, KeyIndex,
+};
+use serde::{Deserialize, Serialize};
+
+use super::WalletError;
+use crate::config::WALLET_BIP44_COIN_TYPE;
+
+/// BIP44 purpose level
+const PURPOSE: u32 = 44;
+
+/// Address chain of an account, the `change` level of the path
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Chain {
+    External, // Receive addresses, handed out to payers
+    Internal, // Change addresses, paid back by the wallet itself
+}
+
+impl Chain {
+    pub fn index(self) -> u32 {
+        match self {
+            Chain::External => 0,
+            Chain::Internal => 1,
+        }
+    }
+}
+
+/// Full path of an address key
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct DerivationPath {
+    pub account: u32,
+    pub chain: Chain,
+    pub index: u32,
+}
+
+impl fmt::Display for DerivationPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "m/{}'/{}'/{}'/{}/{}",
+            PURPOSE,
+            WALLET_BIP44_COIN_TYPE,
+            self.account,
+            self.chain.index(),
+            self.index
+        )
+    }
+}
+
+/// Extended private key of `account`, at `m/44'/coin_type'/account'`
+pub fn account_private_key(
+    master: &ExtendedPrivKey,
+    account: u32,
+) -> Result<ExtendedPrivKey, WalletError> {
+    [PURPOSE, WALLET_BIP44_COIN_TYPE, account]
+        .into_iter()
+        .try_fold(master.clone(), |key, index| {
+            let index = KeyIndex::hardened_from_normalize_index(index).map_err(derivation_error)?;
+            key.derive_private_key(index).map_err(derivation_error)
+        })
+}
+
+/// Private key at `path`, for signing
+pub fn private_key(
+    master: &ExtendedPrivKey,
+    path: &DerivationPath,
+) -> Result<SecretKey, WalletError> {
+    let account = account_private_key(master, path.account)?;
+    let key = account
+        .derive_private_key(KeyIndex::Normal(path.chain.index()))
+        .and_then(|chain| chain.derive_private_key(KeyIndex::Normal(path.index)))
+        .map_err(derivation_error)?;
+    Ok(key.private_key)
+}
+
+/// Public key of address `index` of `chain`, derived from the account extended public key
+pub fn public_key(
+    account: &ExtendedPubKey,
+    chain: Chain,
+    index: u32,
+) -> Result<PublicKey, WalletError> {
+    let key = account
+        .derive_public_key(KeyIndex::Normal(chain.index()))
+        .and_then(|chain| chain.derive_public_key(KeyIndex::Normal(index)))
+        .map_err(derivation_error)?;
+    Ok(key.public_key)
+}
+
+fn derivation_error(error: hdwallet::error::Error) -> WalletError {
+    WalletError::KeyDerivation(format!("{:?}", error))
+}
+
+#[cfg(test)]
+mod tests {
+    use hdwallet::secp256k1::Secp256k1;
+
+    use super::*;
+
+    #[test]
+    fn it_derives_matching_private_and_public_keys() {
+        let master = ExtendedPrivKey::with_seed(&[7u8; 64]).unwrap();
+        let account = ExtendedPubKey::from_private_key(&account_private_key(&master, 1).unwrap());
+        let path = DerivationPath {
+            account: 1,
+            chain: Chain::Internal,
+            index: 3,
+        };
+        assert_eq!(path.to_string(), "m/44'/1'/1'/1/3");
+
+        let secret_key = private_key(&master, &path).unwrap();
+        let expected = PublicKey::from_secret_key(&Secp256k1::new(), &secret_key);
+        assert_eq!(public_key(&account, Chain::Internal, 3).unwrap(), expected);
+        assert_ne!(public_key(&account, Chain::External, 3).unwrap(), expected);
+    }
+}
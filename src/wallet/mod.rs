@@ -5,14 +5,27 @@
 //!
 //! ## Exports
 //! - [`Wallet`]: Core wallet struct for managing accounts and transactions.
+//! - [`WalletSeed`]: BIP39 seed a wallet is derived from, restorable from its mnemonic.
 //! - [`Account`]: Individual account structure with balance, address, and transaction history.
+//! - [`DerivationPath`]: BIP32/BIP44 path (account / change / index) of a wallet key.
+//! - [`CoinSelection`]: Strategies picking the unspent outputs funding a payment.
+//! - [`WalletFile`]: On-disk wallet, its seed encrypted under a password, the rest watch-only.
 //! - [`WalletClient`]: WebSocket client to interact with blockchain nodes.
 //! 
 
+#[allow(clippy::module_inception)]
 mod wallet;
 mod wallet_client;
 mod account;
+mod derivation;
+mod coin_selection;
+mod wallet_file;
 
-pub use wallet::Wallet;
-pub use account::Account;
+pub use wallet::{Wallet, WalletError, WalletSeed};
+pub use account::{Account, AccountError};
+pub use derivation::{Chain, DerivationPath};
+pub use coin_selection::{CoinSelection, PaymentCosts, Selection};
+pub use wallet_file::{
+    AccountRecord, EncryptedSeed, KdfParams, WalletFile, WalletFileError, WALLET_FILE_VERSION,
+};
 pub use wallet_client::WalletClient;
\ No newline at end of file
@@ -4,13 +4,32 @@
 //! and initiating transactions on the blockchain network.
 //!
 //! ## Features
-//! - Generate wallet keypair (mnemonic-based, ECDSA)
+//! - Generate wallet keys (mnemonic-based, BIP32/BIP44 derivation, ECDSA)
+//! - Restore a wallet from its BIP39 mnemonic and optional passphrase
 //! - Create and manage multiple accounts
-//! - Initiate and broadcast transactions via WebSocket
+//! - Initiate and broadcast transactions via WebSocket, funded by coin selection
+//! - Sync balances, unspent outputs and history of every account with the node
 //! - Account lookup by name
-//! 
+//!
+//! ## Recovery
+//! A new wallet generates a 24-word BIP39 mnemonic, handed out once by
+//! [`Wallet::take_mnemonic`]. The mnemonic and the optional passphrase give the BIP39 seed,
+//! from which every key is derived, so [`Wallet::restore`] regenerates the same keys. It then
+//! finds the accounts used on the chain again, with the addresses they handed out up to the
+//! last one paid, see [`Wallet::discover_accounts`] and [`derivation`](super::derivation).
+//!
+//! ## Persistence
+//! [`Wallet::save`] writes the wallet to a file whose seed is encrypted under the password set
+//! with [`Wallet::encrypt`], see [`wallet_file`](super::wallet_file). A wallet read back with
+//! [`Wallet::load`] starts locked: its accounts and addresses are available, but signing and
+//! creating accounts need the seed, decrypted by [`Wallet::unlock`] for a limited time.
 
-use std::error::Error;
+use std::{
+    error::Error,
+    fmt,
+    path::Path,
+    time::{Duration, Instant},
+};
 
 use bip39::{Language, Mnemonic};
 use chrono::Utc;
@@ -18,37 +37,238 @@ use hdwallet::{
     secp256k1::{PublicKey, SecretKey},
     ExtendedPrivKey, ExtendedPubKey,
 };
+use serde_json::json;
+use tokio::sync::broadcast;
 
 use anyhow::Result;
+use thiserror::Error;
 use tracing::info;
+use zeroize::Zeroize;
 
 use crate::{
-    comms::{Message, RequestType},
-    transaction::{TransactionInput, TransactionManager, TransactionOutput},
+    blockchain::{Balance, HistoryEntry, UtxosResponse},
+    comms::{EventTopic, NodeEvent, RequestType},
+    mempool::FeeRate,
+    transaction::{
+        SigHashType, SpendContext, Transaction, TransactionError, TransactionInput,
+        TransactionManager, TransactionOutput, TransactionType,
+    },
+    utils::HashHelper,
+};
+
+use super::{
+    derivation, Account, AccountError, Chain, CoinSelection, EncryptedSeed, KdfParams,
+    PaymentCosts, WalletClient, WalletFile, WalletFileError, WALLET_FILE_VERSION,
 };
 
-use super::{Account, WalletClient};
+/// Number of words of a generated mnemonic
+const MNEMONIC_WORDS: usize = 24;
+
+/// Errors raised while creating, restoring or unlocking a wallet.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum WalletError {
+    #[error("Invalid mnemonic: {0}")]
+    InvalidMnemonic(String),
+    #[error("Cannot derive wallet keys: {0}")]
+    KeyDerivation(String),
+    #[error(transparent)]
+    Account(#[from] AccountError),
+    #[error("Address {0} does not belong to the wallet")]
+    UnknownAddress(String),
+    #[error("Account {0} not found")]
+    UnknownAccount(String),
+    #[error(transparent)]
+    Transaction(#[from] TransactionError),
+    #[error("Wallet is locked")]
+    Locked,
+    #[error("Invalid wallet password")]
+    InvalidPassword,
+    #[error("Wallet has no password")]
+    NotEncrypted,
+    #[error("Wallet already has a password")]
+    AlreadyEncrypted,
+    #[error("Wallet password is empty")]
+    EmptyPassword,
+    #[error("Cannot encrypt wallet seed: {0}")]
+    Encryption(String),
+    #[error("Account {0} does not derive from the wallet seed")]
+    AccountMismatch(u32),
+}
+
+/// BIP39 seed every wallet key is derived from, never printed
+#[derive(Clone, PartialEq, Eq)]
+pub struct WalletSeed([u8; 64]);
+
+impl WalletSeed {
+    /// Seed of `mnemonic` (English words) protected by `passphrase`, empty for none
+    pub fn from_mnemonic(mnemonic: &str, passphrase: &str) -> Result<Self, WalletError> {
+        let mnemonic = Mnemonic::parse_in(Language::English, mnemonic)
+            .map_err(|e| WalletError::InvalidMnemonic(e.to_string()))?;
+        Ok(WalletSeed(mnemonic.to_seed(passphrase)))
+    }
+
+    pub(crate) fn from_bytes(bytes: [u8; 64]) -> Self {
+        WalletSeed(bytes)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 64] {
+        &self.0
+    }
+
+    /// BIP32 master key, root of every wallet key
+    fn master_key(&self) -> Result<ExtendedPrivKey, WalletError> {
+        ExtendedPrivKey::with_seed(&self.0)
+            .map_err(|e| WalletError::KeyDerivation(format!("{:?}", e)))
+    }
+
+    /// Id of the wallet: SHA-256 of the master public key, hex encoded.
+    /// Every restore of the wallet gets the same id.
+    fn wallet_id(&self) -> Result<String, WalletError> {
+        let master = ExtendedPubKey::from_private_key(&self.master_key()?);
+        Ok(HashHelper::to_hex(&HashHelper::hash_bytes(&master.public_key.serialize())))
+    }
+}
+
+impl Drop for WalletSeed {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+impl fmt::Debug for WalletSeed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("WalletSeed(..)")
+    }
+}
 
 /// Wallet struct managing accounts, keypair, and WebSocket client
 #[derive(Debug, Clone)]
 pub struct Wallet {
-    pub id: String, // Derived from public key
+    pub id: String, // Derived from the master public key
     pub name: String,
     pub created_at: String,
     pub accounts: Vec<Account>,
-    public_key: PublicKey,
-    private_key: SecretKey,
+    seed: Option<WalletSeed>, // None while locked
+    encrypted_seed: Option<EncryptedSeed>, // Set once the wallet has a password
+    unlocked_until: Option<Instant>, // Seed is unusable after it, if set
+    coin_selection: CoinSelection, // Strategy funding payments
+    mnemonic: Option<String>, // Recovery phrase of a new wallet, until taken
     ws: WalletClient, // Currently stored for testing; future design may remove
 }
 
 impl Wallet {
     /// Creates a new wallet with a generated keypair and WebSocket connection
     pub async fn new(name: String, ws_uri: String) -> Self {
+        Wallet::new_with_passphrase(name, ws_uri, "").await
+    }
+
+    /// Creates a new wallet whose seed is also protected by a BIP39 `passphrase`.
+    /// Restoring it takes both the mnemonic and the passphrase.
+    pub async fn new_with_passphrase(name: String, ws_uri: String, passphrase: &str) -> Self {
+        let mut rng = bip39::rand::thread_rng();
+        let mnemonic = Mnemonic::generate_in_with(&mut rng, Language::English, MNEMONIC_WORDS)
+            .expect("Mnemonic word count is valid.")
+            .to_string();
+        let seed = WalletSeed::from_mnemonic(&mnemonic, passphrase)
+            .expect("Generated mnemonic is valid.");
+        let mut wallet = Wallet::from_seed(name, ws_uri, seed)
+            .await
+            .expect("Generated seed derives a master key.");
+        wallet.mnemonic = Some(mnemonic);
+        wallet
+    }
+
+    /// Restores a wallet from its `mnemonic` and `passphrase` (empty for none),
+    /// regenerating the keys it was created with and discovering its accounts on the
+    /// chain, see [`Wallet::discover_accounts`]
+    pub async fn restore(
+        name: String,
+        ws_uri: String,
+        mnemonic: &str,
+        passphrase: &str,
+    ) -> Result<Self, Box<dyn Error>> {
+        let seed = WalletSeed::from_mnemonic(mnemonic, passphrase)?;
+        let mut wallet = Wallet::from_seed(name, ws_uri, seed).await?;
+        wallet.discover_accounts().await?;
+        Ok(wallet)
+    }
+
+    /// Wallet of `seed`, without accounts
+    async fn from_seed(
+        name: String,
+        ws_uri: String,
+        seed: WalletSeed,
+    ) -> Result<Self, WalletError> {
+        let id = seed.wallet_id()?;
         let created_at = Utc::now().to_rfc3339();
         let accounts = vec![];
-        let (public_key, private_key) = Wallet::generate_key_pair().unwrap();
-        let id = "".to_string();
-        let mut ws = WalletClient::connect(ws_uri.to_string(), |message| {
+        let ws = Wallet::connect(ws_uri).await;
+
+        Ok(Self {
+            id,
+            seed: Some(seed),
+            encrypted_seed: None,
+            unlocked_until: None,
+            coin_selection: CoinSelection::default(),
+            mnemonic: None,
+            created_at,
+            accounts,
+            name,
+            ws,
+        })
+    }
+
+    /// Loads a wallet saved with [`Wallet::save`]. It starts locked, see [`Wallet::unlock`].
+    pub async fn load(path: &Path, ws_uri: String) -> Result<Self, WalletFileError> {
+        let file = WalletFile::read(path)?;
+        let accounts = file
+            .accounts
+            .iter()
+            .enumerate()
+            .map(|(position, record)| {
+                if record.index as usize != position {
+                    return Err(WalletFileError::Corrupted(format!(
+                        "Account {} stored at position {}",
+                        record.index, position
+                    )));
+                }
+                Ok(Account::from_record(record)?)
+            })
+            .collect::<Result<Vec<_>, WalletFileError>>()?;
+        let ws = Wallet::connect(ws_uri).await;
+
+        Ok(Self {
+            id: file.id,
+            seed: None,
+            encrypted_seed: Some(file.seed),
+            unlocked_until: None,
+            coin_selection: CoinSelection::default(),
+            mnemonic: None,
+            created_at: file.created_at,
+            accounts,
+            name: file.name,
+            ws,
+        })
+    }
+
+    /// Saves the wallet to `path`. The seed is never written in clear, so the wallet needs
+    /// a password first, see [`Wallet::encrypt`].
+    pub fn save(&self, path: &Path) -> Result<(), WalletFileError> {
+        let seed = self.encrypted_seed.clone().ok_or(WalletError::NotEncrypted)?;
+        let file = WalletFile {
+            version: WALLET_FILE_VERSION,
+            id: self.id.clone(),
+            name: self.name.clone(),
+            created_at: self.created_at.clone(),
+            accounts: self.accounts.iter().map(Account::to_record).collect(),
+            seed,
+        };
+        file.write(path)
+    }
+
+    /// Connects to the blockchain node at `ws_uri`
+    async fn connect(ws_uri: String) -> WalletClient {
+        let mut ws = WalletClient::connect(ws_uri, |message| {
             info!("Received message: {}", message);
         })
         .await
@@ -58,68 +278,305 @@ impl Wallet {
 
         // Lets wait for the full blockchain init before sending messages.
         // ws.send_message(NodeMessageType::Balance { balance: 24 }).await?;
+        ws
+    }
 
-        Self {
-            id,
-            public_key,
-            private_key,
-            created_at,
-            accounts,
-            name,
-            ws,
+    /// Sets the password encrypting the seed of a new or restored wallet
+    pub fn encrypt(&mut self, password: &str) -> Result<(), WalletError> {
+        self.encrypt_with(password, KdfParams::default())
+    }
+
+    /// Sets the password encrypting the seed, the key being derived from it with `kdf`.
+    /// The password must not be empty.
+    pub fn encrypt_with(&mut self, password: &str, kdf: KdfParams) -> Result<(), WalletError> {
+        if self.encrypted_seed.is_some() {
+            return Err(WalletError::AlreadyEncrypted);
+        }
+        if password.is_empty() {
+            return Err(WalletError::EmptyPassword);
+        }
+        let encrypted = EncryptedSeed::seal(self.seed()?, password, kdf)?;
+        self.encrypted_seed = Some(encrypted);
+        Ok(())
+    }
+
+    /// Encrypts the seed again under `new_password`, with the current key derivation
+    /// parameters. The wallet file keeps the old password until the wallet is saved.
+    pub fn change_password(
+        &mut self,
+        old_password: &str,
+        new_password: &str,
+    ) -> Result<(), WalletError> {
+        let encrypted = self.encrypted_seed.as_ref().ok_or(WalletError::NotEncrypted)?;
+        if new_password.is_empty() {
+            return Err(WalletError::EmptyPassword);
+        }
+        let seed = encrypted.open(old_password)?;
+        self.encrypted_seed = Some(EncryptedSeed::seal(&seed, new_password, KdfParams::default())?);
+        Ok(())
+    }
+
+    /// Decrypts the seed for `timeout`, after which the wallet is locked again. Fails if an
+    /// account key does not derive from the seed, e.g. after the wallet file was tampered with.
+    pub fn unlock(&mut self, password: &str, timeout: Duration) -> Result<(), WalletError> {
+        let encrypted = self.encrypted_seed.as_ref().ok_or(WalletError::NotEncrypted)?;
+        let seed = encrypted.open(password)?;
+        let master = seed.master_key()?;
+        for account in &self.accounts {
+            let key = derivation::account_private_key(&master, account.index())?;
+            if ExtendedPubKey::from_private_key(&key) != *account.extended_public_key() {
+                return Err(WalletError::AccountMismatch(account.index()));
+            }
+        }
+
+        self.seed = Some(seed);
+        self.unlocked_until = Instant::now().checked_add(timeout);
+        Ok(())
+    }
+
+    /// Wipes the decrypted seed from memory. Only a wallet with a password can be locked.
+    pub fn lock(&mut self) -> Result<(), WalletError> {
+        if self.encrypted_seed.is_none() {
+            return Err(WalletError::NotEncrypted);
+        }
+        self.seed = None;
+        self.unlocked_until = None;
+        Ok(())
+    }
+
+    /// Whether the seed is unavailable: never unlocked, locked again or its unlock timed out
+    pub fn is_locked(&self) -> bool {
+        self.seed().is_err()
+    }
+
+    /// Wipes the seed once the unlock timed out
+    fn lock_if_expired(&mut self) {
+        if self.seed.is_some() && self.is_locked() {
+            self.seed = None;
+            self.unlocked_until = None;
+        }
+    }
+
+    /// Returns the recovery mnemonic of a newly created wallet. It is handed out only once:
+    /// later calls, and restored wallets, return `None`.
+    pub fn take_mnemonic(&mut self) -> Option<String> {
+        self.mnemonic.take()
+    }
+
+    /// Exports the BIP39 seed the wallet keys are derived from, unless the wallet is locked
+    pub fn seed(&self) -> Result<&WalletSeed, WalletError> {
+        match (&self.seed, self.unlocked_until) {
+            (Some(_), Some(until)) if Instant::now() >= until => Err(WalletError::Locked),
+            (Some(seed), _) => Ok(seed),
+            (None, _) => Err(WalletError::Locked),
         }
     }
 
 
-    /// Initiate payment by creating a transaction from the UTXOs and broadcasting it to the network
-    /// TODO: This function does not yet handle UTXO selection, fees, or change addresses
+    /// Pays `amount` to `recipient_addr` from an account, see [`Wallet::create_payment`],
+    /// and submits the transaction to the node. The account records the payment once the
+    /// node accepts it; a rejection is returned as an error.
     pub async fn initiate_payment(
         &mut self,
         account_name: &str,
         recipient_addr: &str,
         amount: u64,
+        fee_rate: FeeRate,
     ) -> Result<(), Box<dyn Error>> {
-        let account = self.find_account(account_name)?;
-
-        let previous_tx_hash = account
-            .transaction_history()
-            .last()
-            .map(|tx| tx.metadata().transaction_hash)
-            .unwrap_or([0u8; 32]);
-
-        let tx_input = TransactionInput {
-            previous_tx_hash, // Placeholder, should be set to actual previous transaction hash
-            index: 0,         // Placeholder, should be set to actual index
-            signature: String::from("PLACEHOLDER_SIGNATURE"), // Placeholder, should be set to actual signature
-            public_key: self.public_key.clone(),
-            amount,
-            nonce: account.next_nonce(),
-        };
+        let tx = self.create_payment(account_name, recipient_addr, amount, fee_rate)?;
 
-        let tx_output = TransactionOutput {
-            amount,
-            recipient_address: recipient_addr.to_string(),
-        };
+        info!("Created transaction: {:?}", tx);
 
-        let tx = TransactionManager::create_transaction(
-            vec![tx_input],
-            vec![tx_output],
-            self.private_key.clone(),
-        );
+        let payload = serde_json::to_value(&tx)?;
+        self.ws.request(RequestType::SubmitTransaction, payload).await?;
 
-        info!("Created transaction: {:?}", tx);
+        if let Some(account) = self.accounts.iter_mut().find(|acc| acc.name() == account_name) {
+            account.record_payment(tx);
+        }
 
-        let message = Message::Request {
-            id: uuid::Uuid::new_v4().to_string(),
-            r#type: RequestType::SubmitTransaction,
-            payload: tx,
-        };
+        Ok(())
+    }
 
-        self.ws.send_message(message).await?;
+    /// Discovers the accounts of the wallet used on the chain, following BIP44: the next
+    /// account is scanned for transactions paying its addresses, up to the gap limit after
+    /// the last used one, and kept if any was used. Discovery stops at the first unused
+    /// account. Returns the number of accounts found.
+    pub async fn discover_accounts(&mut self) -> Result<usize, Box<dyn Error>> {
+        let mut found = 0;
+        loop {
+            self.lock_if_expired();
+            let index = self.accounts.len() as u32;
+            let account_key = derivation::account_private_key(&self.seed()?.master_key()?, index)?;
+            let public_key = ExtendedPubKey::from_private_key(&account_key);
+            let mut account = Account::new(index, public_key, &format!("Account {}", index))?;
+            if !self.scan_account(&mut account).await? {
+                return Ok(found);
+            }
+            self.accounts.push(account);
+            found += 1;
+        }
+    }
+
+    /// Marks used the addresses of `account` paid on the chain, scanning each chain until
+    /// the gap limit passes without any. Returns whether any address was used.
+    async fn scan_account(&mut self, account: &mut Account) -> Result<bool, Box<dyn Error>> {
+        let mut used = false;
+        for chain in [Chain::External, Chain::Internal] {
+            loop {
+                let window = account.lookahead(chain)?;
+                let payload = json!({ "addresses": window });
+                let history = self.ws.request(RequestType::GetHistory, payload).await?;
+                let history: Vec<HistoryEntry> = serde_json::from_value(history)?;
+                let last_paid = history
+                    .iter()
+                    .flat_map(|entry| entry.transaction.outputs())
+                    .filter_map(|output| window.iter().position(|a| *a == output.recipient_address))
+                    .max();
+                let Some(position) = last_paid else {
+                    break;
+                };
+                account.mark_used_at(chain, account.used(chain) + position as u32)?;
+                used = true;
+            }
+        }
+        Ok(used)
+    }
+
+    /// Syncs every account with the node: its balance, unspent outputs and history, over all
+    /// the addresses it handed out. Addresses paid by its history are marked used.
+    pub async fn sync(&mut self) -> Result<(), Box<dyn Error>> {
+        for position in 0..self.accounts.len() {
+            let account = &self.accounts[position];
+            let addresses: Vec<&String> = [Chain::External, Chain::Internal]
+                .into_iter()
+                .flat_map(|chain| account.addresses(chain))
+                .collect();
+            let payload = json!({ "addresses": addresses });
 
+            let balance = self.ws.request(RequestType::GetBalance, payload.clone()).await?;
+            let utxos = self.ws.request(RequestType::GetUtxos, payload.clone()).await?;
+            let history = self.ws.request(RequestType::GetHistory, payload).await?;
+            let utxos: UtxosResponse = serde_json::from_value(utxos)?;
+            let context = SpendContext {
+                height: utxos.height,
+                coinbase_maturity: utxos.coinbase_maturity,
+            };
+
+            let account = &mut self.accounts[position];
+            account.set_balance(serde_json::from_value(balance)?);
+            account.set_utxos(utxos.utxos, context);
+            account.set_history(serde_json::from_value(history)?);
+        }
         Ok(())
     }
 
+    /// Subscribes to the node events keeping the wallet current, new blocks and confirmed
+    /// transactions, returning their receiver for [`Wallet::sync_on_update`]
+    pub async fn subscribe_updates(
+        &mut self,
+    ) -> Result<broadcast::Receiver<NodeEvent>, Box<dyn Error>> {
+        let updates = self.ws.events();
+        self.ws.subscribe(EventTopic::NewBlock).await?;
+        self.ws.subscribe(EventTopic::TxConfirmed).await?;
+        Ok(updates)
+    }
+
+    /// Waits for the next new block or confirmed transaction announced on `updates`, then
+    /// syncs the wallet. Fails once the connection to the node is closed.
+    pub async fn sync_on_update(
+        &mut self,
+        updates: &mut broadcast::Receiver<NodeEvent>,
+    ) -> Result<(), Box<dyn Error>> {
+        loop {
+            match updates.recv().await {
+                Ok(NodeEvent {
+                    topic: EventTopic::NewBlock | EventTopic::TxConfirmed,
+                    ..
+                }) => break,
+                Ok(_) => {}
+                // Missed updates are caught up by syncing anyway
+                Err(broadcast::error::RecvError::Lagged(_)) => break,
+                Err(broadcast::error::RecvError::Closed) => {
+                    return Err("Connection to the node closed".into())
+                }
+            }
+        }
+        // Every event of a block comes at once, one sync covers them all
+        while updates.try_recv().is_ok() {}
+        self.sync().await
+    }
+
+    /// Creates a signed payment of `amount` to `recipient_addr`, funded by unspent outputs of
+    /// an account picked with the wallet [`CoinSelection`] and paying at least `fee_rate`.
+    /// Any change goes to a fresh change address of the account.
+    pub fn create_payment(
+        &mut self,
+        account_name: &str,
+        recipient_addr: &str,
+        amount: u64,
+        fee_rate: FeeRate,
+    ) -> Result<Transaction, WalletError> {
+        self.lock_if_expired();
+        self.seed()?;
+        let position = self
+            .accounts
+            .iter()
+            .position(|acc| acc.name() == account_name)
+            .ok_or_else(|| WalletError::UnknownAccount(account_name.to_string()))?;
+        let account = &self.accounts[position];
+
+        // Change addresses are as long as the first address of the account
+        let costs = PaymentCosts::new(fee_rate, recipient_addr, account.address());
+        let selection = self.coin_selection.select(&account.spendable_utxos(), amount, costs)?;
+
+        let nonce = account.next_nonce();
+        let mut inputs = Vec::with_capacity(selection.inputs.len());
+        let mut private_keys = Vec::with_capacity(selection.inputs.len());
+        for (outpoint, entry) in &selection.inputs {
+            let (public_key, private_key) = self.key_pair(&entry.output.recipient_address)?;
+            inputs.push(TransactionInput {
+                previous_tx_hash: outpoint.transaction_hash,
+                index: outpoint.index,
+                signature: Vec::new(), // Set when the transaction is signed
+                public_key,
+                amount: entry.output.amount,
+                nonce,
+            });
+            private_keys.push(private_key);
+        }
+
+        let mut outputs = vec![TransactionOutput {
+            amount,
+            recipient_address: recipient_addr.to_string(),
+        }];
+        if selection.change > 0 {
+            // Paid by this payment, so the next one gets a fresh address again
+            let account = &mut self.accounts[position];
+            let change_address = account.next_change_address()?;
+            account.mark_used(&change_address);
+            outputs.push(TransactionOutput {
+                amount: selection.change,
+                recipient_address: change_address,
+            });
+        }
+
+        let mut tx = TransactionManager::create_unsigned_transaction(
+            TransactionType::Regular,
+            inputs,
+            outputs,
+        );
+        for (index, private_key) in private_keys.iter().enumerate() {
+            tx.sign_input(index, private_key, SigHashType::All)
+                .expect("Every input of the transaction can be signed.");
+        }
+        Ok(tx)
+    }
+
+    /// Strategy picking the outputs funding payments
+    pub fn set_coin_selection(&mut self, coin_selection: CoinSelection) {
+        self.coin_selection = coin_selection;
+    }
+
     /// Finds an account by name
     pub fn find_account(&self, name: &str) -> Result<Account, String> {
         let result = self.accounts.iter().find(|acc| acc.name() == name);
@@ -129,50 +586,145 @@ impl Wallet {
         }
     }
 
-    /// Create new account for the wallet, based on the `public_key`
-    pub fn create_new_account(&mut self, name: &str) {
-        let account = Account::new(&self.public_key, name);
+    /// Creates the next account of the wallet, deriving its keys at
+    /// `m/44'/coin_type'/account'` with `account` the number of existing accounts
+    pub fn create_new_account(&mut self, name: &str) -> Result<&Account, WalletError> {
+        self.lock_if_expired();
+        let index = self.accounts.len() as u32;
+        let account_key = derivation::account_private_key(&self.seed()?.master_key()?, index)?;
+        let account = Account::new(index, ExtendedPubKey::from_private_key(&account_key), name)?;
         self.accounts.push(account);
+        Ok(self.accounts.last().expect("Account was just added."))
     }
 
-    /// Generates wallet keypair from mnemonic (24 words, English)
-    fn generate_key_pair() -> Result<(PublicKey, SecretKey), String> {
-        // Generate a mnemonic and seed
-        let mut rng = bip39::rand::thread_rng();
-        let mnemonic = Mnemonic::generate_in_with(&mut rng, Language::English, 24).unwrap();
-        let seed = mnemonic.to_seed(""); // Create the seed from the mnemonic
-
-        // Attempt to create an ExtendedPrivKey from the seed
-        let master_key = ExtendedPrivKey::with_seed(&seed);
-
-        // Check if the master key creation succeeded
-        if let Ok(master_key) = master_key {
-            // Derive child private and public keys if master key is created successfully
-            let child_priv_key = master_key
-                .derive_private_key(hdwallet::KeyIndex::Normal(0))
-                .unwrap();
-            let child_pub_key = ExtendedPubKey::from_private_key(&child_priv_key);
-
-            // Return the derived public key
-            return Ok((child_pub_key.public_key, master_key.private_key));
-        }
+    /// Key pair of an address handed out by one of the wallet accounts, to spend its outputs
+    pub fn key_pair(&self, address: &str) -> Result<(PublicKey, SecretKey), WalletError> {
+        let unknown = || WalletError::UnknownAddress(address.to_string());
+        let account = self
+            .accounts
+            .iter()
+            .find(|account| account.path_of(address).is_some())
+            .ok_or_else(unknown)?;
+        let path = account.path_of(address).ok_or_else(unknown)?;
+        let public_key = account.public_key_of(address).ok_or_else(unknown)?;
+        let private_key = derivation::private_key(&self.seed()?.master_key()?, &path)?;
+        Ok((public_key, private_key))
+    }
 
-        // If the master key creation failed, return an error
-        Err("Cannot create master key from seed!".to_string())
+    /// Balance of all wallet accounts together
+    pub fn balance(&self) -> Balance {
+        self.accounts
+            .iter()
+            .fold(Balance::default(), |total, account| total + account.balance())
     }
 
     /// Returns references to wallet accounts
     pub fn accounts(&self) -> &Vec<Account> {
         &self.accounts
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{net::TcpListener, sync::mpsc};
+
+    use super::*;
+    use crate::blockchain::{BlockchainListener, NodeRequest};
+
+    const MNEMONIC: &str = "abandon abandon abandon abandon abandon abandon abandon abandon \
+                            abandon abandon abandon about";
 
-    /// Accessor for wallet public key
-    pub fn public_key(&self) -> &PublicKey {
-        &self.public_key
+    #[test]
+    fn it_derives_the_bip39_seed() {
+        // BIP39 test vector
+        let seed = WalletSeed::from_mnemonic(MNEMONIC, "TREZOR").unwrap();
+        assert_eq!(
+            HashHelper::to_hex(seed.as_bytes()),
+            concat!(
+                "c55257c360c07c72029aebc1b53c05ed0362ada38ead3e3e9efa3708e53495531f09a6987599d18",
+                "264c1e1c92f2cf141630c7a3c4ab7c81b2f001698e7463b04",
+            )
+        );
+        assert_eq!(format!("{:?}", seed), "WalletSeed(..)");
+
+        assert!(matches!(
+            WalletSeed::from_mnemonic("abandon abandon", ""),
+            Err(WalletError::InvalidMnemonic(_))
+        ));
     }
 
-    /// Accessor for wallet private key
-    pub fn private_key(&self) -> &SecretKey {
-        &self.private_key
+    #[test]
+    fn it_restores_the_same_keys() {
+        let account_key = |passphrase, account| {
+            let seed = WalletSeed::from_mnemonic(MNEMONIC, passphrase).unwrap();
+            let master = seed.master_key().unwrap();
+            derivation::account_private_key(&master, account).unwrap()
+        };
+
+        assert_eq!(account_key("", 0), account_key("", 0));
+        assert_ne!(account_key("", 0), account_key("", 1));
+        assert_ne!(account_key("", 0), account_key("passphrase", 0));
+    }
+
+    /// Node listener on a free local port, for wallets to connect to
+    async fn node_uri() -> String {
+        let tcp_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let uri = tcp_listener.local_addr().unwrap().to_string();
+        let (requests, mut received) = mpsc::unbounded_channel::<NodeRequest>();
+        let listener = BlockchainListener::new().with_requests(requests);
+        tokio::spawn(async move { listener.serve(tcp_listener).await });
+        // An empty chain: no address was ever paid
+        tokio::spawn(async move {
+            while let Some(request) = received.recv().await {
+                let _ = request.respond_to.send(Ok(json!([])));
+            }
+        });
+        uri
+    }
+
+    #[tokio::test]
+    async fn it_derives_the_wallet_id_from_the_master_key() {
+        let uri = node_uri().await;
+        let wallet = Wallet::restore("first".into(), uri.clone(), MNEMONIC, "").await.unwrap();
+        let again = Wallet::restore("again".into(), uri.clone(), MNEMONIC, "").await.unwrap();
+        let other = Wallet::restore("other".into(), uri, MNEMONIC, "passphrase").await.unwrap();
+
+        assert_eq!(wallet.id.len(), 64);
+        assert_eq!(wallet.id, again.id);
+        assert_ne!(wallet.id, other.id);
+    }
+
+    #[tokio::test]
+    async fn it_saves_an_encrypted_wallet_and_unlocks_it_for_a_while() {
+        let uri = node_uri().await;
+        let path = std::env::temp_dir()
+            .join(format!("oxidize-wallet-{}.json", uuid::Uuid::new_v4()));
+        let mut wallet = Wallet::restore("saved".into(), uri.clone(), MNEMONIC, "").await.unwrap();
+        let address = wallet.create_new_account("main").unwrap().address().clone();
+        assert!(matches!(
+            wallet.save(&path),
+            Err(WalletFileError::Wallet(WalletError::NotEncrypted))
+        ));
+        assert_eq!(wallet.encrypt(""), Err(WalletError::EmptyPassword));
+        wallet.encrypt("first").unwrap();
+        wallet.save(&path).unwrap();
+
+        // Accounts are readable while locked, keys are not
+        let mut loaded = Wallet::load(&path, uri).await.unwrap();
+        assert_eq!(loaded.id, wallet.id);
+        assert_eq!(loaded.accounts()[0].address(), &address);
+        assert!(loaded.is_locked());
+        assert_eq!(loaded.key_pair(&address), Err(WalletError::Locked));
+
+        assert_eq!(loaded.change_password("first", ""), Err(WalletError::EmptyPassword));
+        loaded.change_password("first", "second").unwrap();
+        loaded.unlock("second", Duration::ZERO).unwrap();
+        assert!(loaded.is_locked());
+
+        loaded.unlock("second", Duration::from_secs(60)).unwrap();
+        assert_eq!(loaded.key_pair(&address), wallet.key_pair(&address));
+        loaded.lock().unwrap();
+        assert!(loaded.is_locked());
+        std::fs::remove_file(&path).unwrap();
     }
 }
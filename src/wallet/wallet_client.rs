@@ -2,33 +2,79 @@
 //!
 //! Provides a WebSocket client for interacting with the blockchain network,
 //! sending transactions, and receiving events.
-//! 
+//!
+//! Requests sent with [`WalletClient::request`] are answered by a response with the same
+//! id, handed back to the caller. Events of the topics the client subscribed to are
+//! broadcast to the receivers of [`WalletClient::events`].
+
+use std::{
+    collections::HashMap,
+    error::Error,
+    sync::{Arc, Mutex, MutexGuard},
+    time::Duration,
+};
+
+use serde_json::Value;
+use tokio::sync::{broadcast, oneshot};
+
+use crate::{
+    comms::{self, EventTopic, NodeEvent, RequestType},
+    config::WALLET_REQUEST_TIMEOUT_SECS,
+    websockets::WebSocketClient,
+};
 
-use std::error::Error;
+/// Node events a client buffers, before slow receivers start lagging
+const EVENT_CHANNEL_CAPACITY: usize = 256;
 
-use crate::{comms, websockets::WebSocketClient};
+/// Where to send the answer of each request waiting for its response, by request id
+type Responders = HashMap<String, oneshot::Sender<Result<Value, String>>>;
+type PendingRequests = Arc<Mutex<Responders>>;
 
 #[derive(Debug, Clone)]
 pub struct WalletClient {
     address: String,
     ws: WebSocketClient,
+    pending: PendingRequests,
+    events: broadcast::Sender<NodeEvent>,
 }
 
 impl WalletClient {
-    /// Connects to a blockchain node via WebSocket.
+    /// Connects to a blockchain node via WebSocket. Every message received is also handed
+    /// to `receiver_handler`.
     pub async fn connect<F>(
         address: String,
         receiver_handler: F,
     ) -> Result<WalletClient, Box<dyn Error>>
     where
-        F: Fn(String) -> () + Send + Sync + 'static + Clone,
+        F: Fn(String) + Send + Sync + 'static + Clone,
     {
-        let ws = WebSocketClient::connect(address.to_string(), receiver_handler).await?;
-        let wc = WalletClient { address, ws };
+        let pending = PendingRequests::default();
+        let (events, _) = broadcast::channel(EVENT_CHANNEL_CAPACITY);
+
+        let router = {
+            let pending = pending.clone();
+            let events = events.clone();
+            move |message: String| {
+                route_message(&message, &pending, &events);
+                receiver_handler(message);
+            }
+        };
+        let ws = WebSocketClient::connect(address.to_string(), router).await?;
+        let wc = WalletClient {
+            address,
+            ws,
+            pending,
+            events,
+        };
 
         Ok(wc)
     }
 
+    /// Returns the address of the blockchain node this client is connected to.
+    pub fn address(&self) -> &String {
+        &self.address
+    }
+
     /// Sends a custom message to the blockchain network.
     pub async fn send_message<T: serde::Serialize>(
         &mut self,
@@ -40,11 +86,43 @@ impl WalletClient {
         Ok(())
     }
 
-    /// Sends a ping message to the blockchain node.
-    pub async fn ping(&mut self) -> Result<(), Box<dyn Error>> {
+    /// Sends a request to the node and waits for its answer, the data of the response.
+    /// An error answered by the node is returned as an error.
+    pub async fn request(
+        &mut self,
+        r#type: RequestType,
+        payload: Value,
+    ) -> Result<Value, Box<dyn Error>> {
+        let id = uuid::Uuid::new_v4().to_string();
+        let (respond_to, response) = oneshot::channel();
+        self.pending_requests().insert(id.clone(), respond_to);
+
+        let message = comms::Message::Request {
+            id: id.clone(),
+            r#type,
+            payload,
+        };
+        if let Err(e) = self.ws.send_message(message).await {
+            self.pending_requests().remove(&id);
+            return Err(e);
+        }
+
+        let timeout = Duration::from_secs(WALLET_REQUEST_TIMEOUT_SECS);
+        match tokio::time::timeout(timeout, response).await {
+            Ok(Ok(answer)) => Ok(answer?),
+            Ok(Err(_)) => Err("Connection to the node closed".into()),
+            Err(_) => {
+                self.pending_requests().remove(&id);
+                Err(format!("Node did not answer request {} in time", id).into())
+            }
+        }
+    }
+
+    /// Subscribes to the node events of `topic`, received through [`WalletClient::events`]
+    pub async fn subscribe(&mut self, topic: EventTopic) -> Result<(), Box<dyn Error>> {
         let message = comms::Message::Event {
             id: uuid::Uuid::new_v4().to_string(),
-            topic: comms::EventTopic::BlockchainPing,
+            topic,
             data: (),
         };
 
@@ -52,4 +130,40 @@ impl WalletClient {
 
         Ok(())
     }
+
+    /// Receiver of the node events received from now on
+    pub fn events(&self) -> broadcast::Receiver<NodeEvent> {
+        self.events.subscribe()
+    }
+
+    /// Sends a ping message to the blockchain node.
+    pub async fn ping(&mut self) -> Result<(), Box<dyn Error>> {
+        self.subscribe(EventTopic::BlockchainPing).await
+    }
+
+    fn pending_requests(&self) -> MutexGuard<'_, Responders> {
+        self.pending.lock().expect("Pending requests lock is poisoned")
+    }
+}
+
+/// Hands a response to the request waiting for it, and an event to the event receivers
+fn route_message(message: &str, pending: &PendingRequests, events: &broadcast::Sender<NodeEvent>) {
+    match serde_json::from_str::<comms::Message<Value>>(message) {
+        Ok(comms::Message::Response { id, data, error, .. }) => {
+            let respond_to = pending.lock().expect("Pending requests lock is poisoned").remove(&id);
+            if let Some(respond_to) = respond_to {
+                let answer = match error {
+                    Some(error) => Err(error),
+                    None => Ok(data.unwrap_or(Value::Null)),
+                };
+                // The caller may have given up waiting
+                let _ = respond_to.send(answer);
+            }
+        }
+        Ok(comms::Message::Event { topic, data, .. }) => {
+            // No receivers is not an error, events are simply dropped
+            let _ = events.send(NodeEvent { topic, data });
+        }
+        _ => {}
+    }
 }
@@ -0,0 +1,313 @@
+//! # Wallet File
+//!
+//! Persists a [`Wallet`](super::Wallet) as a JSON file. Its only secret is the BIP39 seed:
+//! private keys are derived from it when signing and never stored. The seed is encrypted
+//! under the wallet password, everything else stays readable without it, enough for a
+//! watch-only view of the wallet:
+//!
+//! - `version`: wallet file version, `1`
+//! - `id`, `name`, `created_at`: wallet metadata
+//! - `accounts`: per account, its index, name, extended public key, the receive and change
+//!   addresses handed out so far and the labels given to them
+//! - `seed`: the encrypted seed
+//!
+//! The seed is encrypted with ChaCha20-Poly1305 under a 256-bit key derived from the password
+//! with Argon2id. Every encryption picks a fresh salt and nonce, stored next to the ciphertext
+//! with the Argon2id parameters, so files written with other parameters still open.
+
+use std::{
+    collections::BTreeMap,
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::Path,
+};
+
+use argon2::{Algorithm, Argon2, Params, Version};
+use bip39::rand::{thread_rng, RngCore};
+use chacha20poly1305::{
+    aead::{Aead, KeyInit},
+    ChaCha20Poly1305, Nonce,
+};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use zeroize::Zeroizing;
+
+use super::{AccountError, WalletError, WalletSeed};
+use crate::config::{WALLET_KDF_ITERATIONS, WALLET_KDF_MEMORY_KIB, WALLET_KDF_PARALLELISM};
+
+/// Current wallet file version
+pub const WALLET_FILE_VERSION: u32 = 1;
+
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+const KEY_LEN: usize = 32;
+
+/// Errors raised while reading or writing a wallet file.
+#[derive(Error, Debug)]
+pub enum WalletFileError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    Serialization(#[from] serde_json::Error),
+    #[error("Unsupported wallet file version {found}, expected {supported}")]
+    UnsupportedVersion { found: u32, supported: u32 },
+    #[error("Corrupted wallet file: {0}")]
+    Corrupted(String),
+    #[error(transparent)]
+    Account(#[from] AccountError),
+    #[error(transparent)]
+    Wallet(#[from] WalletError),
+}
+
+/// Argon2id parameters deriving the seed encryption key from the password
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KdfParams {
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+impl Default for KdfParams {
+    fn default() -> Self {
+        KdfParams {
+            memory_kib: WALLET_KDF_MEMORY_KIB,
+            iterations: WALLET_KDF_ITERATIONS,
+            parallelism: WALLET_KDF_PARALLELISM,
+        }
+    }
+}
+
+/// Wallet seed encrypted under a password
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EncryptedSeed {
+    kdf: KdfParams,
+    salt: Vec<u8>,
+    nonce: Vec<u8>,
+    ciphertext: Vec<u8>, // Seed followed by the Poly1305 tag
+}
+
+impl EncryptedSeed {
+    /// Encrypts `seed` under `password`, with a fresh salt and nonce
+    pub fn seal(seed: &WalletSeed, password: &str, kdf: KdfParams) -> Result<Self, WalletError> {
+        let mut salt = vec![0u8; SALT_LEN];
+        let mut nonce = [0u8; NONCE_LEN];
+        let mut rng = thread_rng();
+        rng.fill_bytes(&mut salt);
+        rng.fill_bytes(&mut nonce);
+
+        let key = derive_key(password, &salt, &kdf)?;
+        let ciphertext = ChaCha20Poly1305::new((&*key).into())
+            .encrypt(&Nonce::from(nonce), seed.as_bytes().as_slice())
+            .map_err(|_| WalletError::Encryption("Cannot encrypt the seed".to_string()))?;
+        Ok(EncryptedSeed {
+            kdf,
+            salt,
+            nonce: nonce.to_vec(),
+            ciphertext,
+        })
+    }
+
+    /// Decrypts the seed. A wrong password, or a tampered ciphertext, fails with
+    /// [`WalletError::InvalidPassword`].
+    pub fn open(&self, password: &str) -> Result<WalletSeed, WalletError> {
+        let nonce: [u8; NONCE_LEN] = self
+            .nonce
+            .as_slice()
+            .try_into()
+            .map_err(|_| WalletError::Encryption("Invalid nonce length".to_string()))?;
+
+        let key = derive_key(password, &self.salt, &self.kdf)?;
+        let plaintext = ChaCha20Poly1305::new((&*key).into())
+            .decrypt(&Nonce::from(nonce), self.ciphertext.as_slice())
+            .map(Zeroizing::new)
+            .map_err(|_| WalletError::InvalidPassword)?;
+        let seed = plaintext
+            .as_slice()
+            .try_into()
+            .map_err(|_| WalletError::Encryption("Invalid seed length".to_string()))?;
+        Ok(WalletSeed::from_bytes(seed))
+    }
+
+    pub fn kdf(&self) -> &KdfParams {
+        &self.kdf
+    }
+}
+
+/// 256-bit key derived from `password` with Argon2id
+fn derive_key(
+    password: &str,
+    salt: &[u8],
+    kdf: &KdfParams,
+) -> Result<Zeroizing<[u8; KEY_LEN]>, WalletError> {
+    let encryption_error = |e: argon2::Error| WalletError::Encryption(e.to_string());
+    let params = Params::new(
+        kdf.memory_kib,
+        kdf.iterations,
+        kdf.parallelism,
+        Some(KEY_LEN),
+    )
+    .map_err(encryption_error)?;
+    let mut key = Zeroizing::new([0u8; KEY_LEN]);
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(password.as_bytes(), salt, key.as_mut())
+        .map_err(encryption_error)?;
+    Ok(key)
+}
+
+/// Public metadata of an account, readable without the password
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AccountRecord {
+    pub index: u32,
+    pub name: String,
+    pub created_at: String,
+    pub extended_public_key: String, // Hex of the compressed public key and the chain code
+    pub receive: Vec<String>,        // Receive addresses handed out, by index
+    pub change: Vec<String>,         // Change addresses handed out, by index
+    pub receive_used: u32,           // Index after the last used receive address
+    pub change_used: u32,            // Index after the last used change address
+    pub labels: BTreeMap<String, String>, // Labels by address
+}
+
+/// Contents of a wallet file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WalletFile {
+    pub version: u32,
+    pub id: String,
+    pub name: String,
+    pub created_at: String,
+    pub accounts: Vec<AccountRecord>,
+    pub seed: EncryptedSeed,
+}
+
+/// Version of a wallet file, read before the rest of the file
+#[derive(Deserialize)]
+struct FileVersion {
+    version: u32,
+}
+
+impl WalletFile {
+    /// Reads a wallet file without decrypting its seed, e.g. for a watch-only view
+    pub fn read(path: &Path) -> Result<Self, WalletFileError> {
+        let bytes = fs::read(path)?;
+        let FileVersion { version } = serde_json::from_slice(&bytes)?;
+        if version != WALLET_FILE_VERSION {
+            return Err(WalletFileError::UnsupportedVersion {
+                found: version,
+                supported: WALLET_FILE_VERSION,
+            });
+        }
+        Ok(serde_json::from_slice(&bytes)?)
+    }
+
+    /// Writes the wallet file, readable by its owner only. `path` is replaced once the new
+    /// file is fully written, so a crash never leaves a half-written wallet behind.
+    pub fn write(&self, path: &Path) -> Result<(), WalletFileError> {
+        let temp_path = path.with_extension("tmp");
+        let mut options = OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+        let mut file = options.open(&temp_path)?;
+        file.write_all(&serde_json::to_vec_pretty(self)?)?;
+        file.sync_all()?;
+        fs::rename(&temp_path, path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use hdwallet::{ExtendedPrivKey, ExtendedPubKey};
+
+    use super::*;
+    use crate::wallet::{derivation, Account, Chain};
+
+    /// Cheap Argon2id parameters, the defaults are too slow for unit tests
+    const TEST_KDF: KdfParams = KdfParams {
+        memory_kib: 64,
+        iterations: 1,
+        parallelism: 1,
+    };
+
+    fn temp_path() -> PathBuf {
+        std::env::temp_dir().join(format!("oxidize-wallet-{}.json", uuid::Uuid::new_v4()))
+    }
+
+    #[test]
+    fn it_encrypts_the_seed_under_the_password() {
+        let seed = WalletSeed::from_bytes([9u8; 64]);
+        let encrypted = EncryptedSeed::seal(&seed, "correct horse", TEST_KDF).unwrap();
+        assert_eq!(encrypted.open("correct horse").unwrap(), seed);
+        assert_eq!(
+            encrypted.open("wrong horse"),
+            Err(WalletError::InvalidPassword)
+        );
+
+        // Fresh salt and nonce on every encryption
+        let again = EncryptedSeed::seal(&seed, "correct horse", TEST_KDF).unwrap();
+        assert_ne!(again.ciphertext, encrypted.ciphertext);
+
+        let mut tampered = encrypted.clone();
+        tampered.ciphertext[0] ^= 1;
+        assert_eq!(
+            tampered.open("correct horse"),
+            Err(WalletError::InvalidPassword)
+        );
+    }
+
+    #[test]
+    fn it_keeps_account_metadata_readable_without_the_password() {
+        let master = ExtendedPrivKey::with_seed(&[3u8; 64]).unwrap();
+        let key = derivation::account_private_key(&master, 0).unwrap();
+        let mut account = Account::new(0, ExtendedPubKey::from_private_key(&key), "main").unwrap();
+        let change = account.next_change_address().unwrap();
+        assert!(account.mark_used(&change));
+        assert!(account.set_label(&change, "change of the first payment"));
+
+        let path = temp_path();
+        let file = WalletFile {
+            version: WALLET_FILE_VERSION,
+            id: String::new(),
+            name: "wallet".to_string(),
+            created_at: "2025-01-01T00:00:00+00:00".to_string(),
+            accounts: vec![account.to_record()],
+            seed: EncryptedSeed::seal(&WalletSeed::from_bytes([3u8; 64]), "pw", TEST_KDF).unwrap(),
+        };
+        file.write(&path).unwrap();
+        let read = WalletFile::read(&path).unwrap();
+        assert_eq!(read, file);
+
+        let restored = Account::from_record(&read.accounts[0]).unwrap();
+        assert_eq!(restored.addresses(Chain::Internal), vec![&change]);
+        assert_eq!(
+            restored.label(&change),
+            Some(&"change of the first payment".to_string())
+        );
+        assert_eq!(
+            restored.public_key_of(&change),
+            account.public_key_of(&change)
+        );
+
+        let mut record = read.accounts[0].clone();
+        record.change[0] = account.address().clone();
+        assert_eq!(
+            Account::from_record(&record).unwrap_err(),
+            AccountError::AddressMismatch {
+                chain: Chain::Internal,
+                index: 0
+            }
+        );
+
+        let mut newer = serde_json::to_value(&file).unwrap();
+        newer["version"] = (WALLET_FILE_VERSION + 1).into();
+        fs::write(&path, serde_json::to_vec(&newer).unwrap()).unwrap();
+        assert!(matches!(
+            WalletFile::read(&path),
+            Err(WalletFileError::UnsupportedVersion { .. })
+        ));
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -19,7 +19,7 @@ impl WebSocketClient {
      /// Connects to a WebSocket server and starts listening to messages
     pub async fn connect<F>(address: String, receiver_handler: F) -> Result<Self, Box<dyn Error>>
     where
-        F: Fn(String) -> () + Send + Sync + 'static + Clone,
+        F: Fn(String) + Send + Sync + 'static + Clone,
     {
         let url_string = format!("ws://{address}");
 
@@ -57,7 +57,7 @@ impl WebSocketClient {
         message: comms::Message<T>,
     ) -> Result<(), Box<dyn Error>> {
         let serialized = serde_json::to_string(&message)?;
-        let _ = self.sender.send(serialized).await?;
+        self.sender.send(serialized).await?;
         Ok(())
     }
 }
@@ -7,6 +7,7 @@ use tracing::info;
 use std::collections::HashMap;
 use std::pin::Pin;
 use std::sync::Arc;
+use tokio::net::TcpListener;
 use tokio::sync::{broadcast, mpsc, Mutex};
 use tokio_tungstenite::accept_async;
 use tokio_tungstenite::tungstenite::protocol::Message;
@@ -18,6 +19,12 @@ pub struct WebSocketServer {
     broadcaster: broadcast::Sender<String>,
 }
 
+impl Default for WebSocketServer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl WebSocketServer {
     pub fn new() -> Self {
         let (broadcaster, _) = broadcast::channel(100);
@@ -39,7 +46,23 @@ impl WebSocketServer {
             + Sync
             + 'static,
     {
-        let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
+        let listener = TcpListener::bind(addr).await.unwrap();
+        self.serve(listener, handle_message).await;
+    }
+
+    /// Same as `run`, but on an already bound listener.
+    /// Useful when binding to port `0` and the actual address is needed before serving.
+    pub async fn serve<F>(&self, listener: TcpListener, handle_message: F)
+    where
+        F: Fn(
+                String,
+                usize,
+                Arc<Mutex<HashMap<usize, mpsc::UnboundedSender<Message>>>>,
+            ) -> Pin<Box<dyn std::future::Future<Output = ()> + Send + 'static>>
+            + Send
+            + Sync
+            + 'static,
+    {
         let mut id_counter = 0;
 
         // Wrap the closure in an Arc for safe sharing.
@@ -85,7 +108,13 @@ impl WebSocketServer {
         }
     }
 
+    /// Subscribes to every message broadcast by the server
+    pub fn subscribe(&self) -> broadcast::Receiver<String> {
+        self.broadcaster.subscribe()
+    }
+
     pub async fn broadcast(&self, message: String) {
+        let _ = self.broadcaster.send(message.clone());
         for (_, client) in self.clients.lock().await.iter() {
             let _ = client.send(Message::Text(message.clone()));
         }
@@ -15,6 +15,12 @@ pub struct SubscriptionManager {
     subscribers: Subscribers,
 }
 
+impl Default for SubscriptionManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl SubscriptionManager {
     pub fn new() -> Self {
         Self {